@@ -0,0 +1,26 @@
+#ifndef MODMAN_H
+#define MODMAN_H
+
+/* C API for modman, built with `cargo rustc --lib --release --features ffi --crate-type cdylib` */
+
+#define MODMAN_NOT_INSTALLED 0
+#define MODMAN_PARTIAL 1
+#define MODMAN_INSTALLED 2
+#define MODMAN_ERROR -1
+
+/* Newline separated names of the valid modules in modules_dir, or NULL on error */
+char *modman_list(const char *modules_dir);
+
+/* Install status of a module, or MODMAN_ERROR */
+int modman_status(const char *modules_dir, const char *module);
+
+/* Install a module, returning 0 on success or MODMAN_ERROR */
+int modman_install(const char *modules_dir, const char *module, int force);
+
+/* Message for the last error on this thread, or NULL */
+char *modman_last_error(void);
+
+/* Release a string returned by any of the above */
+void modman_string_free(char *s);
+
+#endif
@@ -0,0 +1,71 @@
+//! Setting up a fresh machine: cloning the dotfiles repository and choosing what to install.
+//!
+//! A `bootstrap.toml` at the top of the repository lists the modules a new machine gets, instead
+//! of all of them:
+//!
+//! ```toml
+//! modules = ["zsh", "git", "nvim"]
+//! ```
+extern crate toml;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+static PLAN_FILE: &str = "bootstrap.toml";
+
+/// What to install on a fresh machine
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Plan {
+    /// Modules to install, or all of them if empty
+    #[serde(default)]
+    pub modules: Vec<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum BootstrapError {
+    #[error(
+        "{0} already exists and isn't empty; Run `modman install --all` to install its modules"
+    )]
+    Exists(String),
+    #[error("git clone {0} returned non-zero code")]
+    Clone(String),
+    #[error("Bootstrap {0}: {1}")]
+    Parse(String, #[source] toml::de::Error),
+    #[error("Bootstrap {0}: {1}")]
+    IO(String, #[source] io::Error),
+}
+
+/// Clone the repository at `url` into `dir`, which mustn't have anything in it yet
+pub fn clone(url: &str, dir: &Path) -> Result<(), BootstrapError> {
+    let occupied = fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some());
+    if occupied {
+        return Err(BootstrapError::Exists(dir.display().to_string()));
+    }
+    let status = process::Command::new("git")
+        .arg("clone")
+        .arg(url)
+        .arg(dir)
+        .status()
+        .map_err(|err| BootstrapError::IO("git".to_owned(), err))?;
+    if !status.success() {
+        return Err(BootstrapError::Clone(url.to_owned()));
+    }
+    Ok(())
+}
+
+impl Plan {
+    /// The plan in the repository at `dir`, or the default of installing everything if it has
+    /// none
+    pub fn load(dir: &Path) -> Result<Plan, BootstrapError> {
+        let path = dir.join(PLAN_FILE);
+        if !path.exists() {
+            return Ok(Plan::default());
+        }
+        let buf =
+            fs::read(&path).map_err(|err| BootstrapError::IO(path.display().to_string(), err))?;
+        toml::from_slice(&buf).map_err(|err| BootstrapError::Parse(path.display().to_string(), err))
+    }
+}
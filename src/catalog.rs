@@ -0,0 +1,237 @@
+//! Catalog of well-known programs, where they keep their configuration and how to reload it.
+//!
+//! A built-in catalog is extended (or overridden) by `$XDG_CONFIG_HOME/modman/catalog.toml`:
+//!
+//! ```toml
+//! [apps.myterm]
+//! targets = ["xdg-config:myterm"]
+//! reload = "pkill -USR1 myterm"
+//! ```
+extern crate toml;
+
+use dirs;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use target;
+
+static CATALOG_DIR: &str = "modman";
+static CATALOG_FILE: &str = "catalog.toml";
+
+/// A program's config locations, as module targets, and the command that reloads them
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct App {
+    pub targets: Vec<String>,
+    pub reload: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Catalog {
+    #[serde(default)]
+    apps: BTreeMap<String, App>,
+}
+
+#[derive(Debug, Error)]
+pub enum CatalogError {
+    #[error("Catalog {0}: {1}")]
+    Parse(String, #[source] toml::de::Error),
+    #[error("Catalog {0}: {1}")]
+    IO(String, #[source] io::Error),
+}
+
+struct Builtin {
+    name: &'static str,
+    targets: &'static [&'static str],
+    reload: Option<&'static str>,
+}
+
+macro_rules! catalog {
+    (@reload) => { None };
+    (@reload $reload:expr) => { Some($reload) };
+    ($($name:expr => [$($target:expr),+] $(reload $reload:expr)?),+ $(,)*) => {
+        &[$(Builtin {
+            name: $name,
+            targets: &[$($target),+],
+            reload: catalog!(@reload $($reload)?),
+        }),+]
+    };
+}
+
+static BUILTIN: &[Builtin] = catalog![
+    "aerc" => ["xdg-config:aerc"],
+    "alacritty" => ["xdg-config:alacritty"],
+    "aria2" => ["xdg-config:aria2"],
+    "atuin" => ["xdg-config:atuin"],
+    "awesome" => ["xdg-config:awesome"] reload "awesome-client 'awesome.restart()'",
+    "bash" => [".bashrc", ".bash_profile", ".bash_logout"],
+    "bat" => ["xdg-config:bat"],
+    "bottom" => ["xdg-config:bottom"],
+    "bspwm" => ["xdg-config:bspwm"] reload "bspc wm -r",
+    "btop" => ["xdg-config:btop"],
+    "cava" => ["xdg-config:cava"],
+    "cmus" => ["xdg-config:cmus/rc"],
+    "conky" => ["xdg-config:conky"],
+    "ctags" => [".ctags", "xdg-config:ctags"],
+    "curl" => [".curlrc"],
+    "direnv" => ["xdg-config:direnv"],
+    "dunst" => ["xdg-config:dunst"] reload "killall dunst",
+    "emacs" => [".emacs", ".emacs.d", "xdg-config:emacs"],
+    "fastfetch" => ["xdg-config:fastfetch"],
+    "fish" => ["xdg-config:fish/config.fish", "xdg-config:fish/functions"],
+    "fontconfig" => ["xdg-config:fontconfig"] reload "fc-cache -f",
+    "foot" => ["xdg-config:foot"],
+    "fuzzel" => ["xdg-config:fuzzel"],
+    "fzf" => [".fzf.bash", ".fzf.zsh"],
+    "gdb" => [".gdbinit"],
+    "gh" => ["xdg-config:gh/config.yml"],
+    "ghostty" => ["xdg-config:ghostty"],
+    "git" => [".gitconfig", ".gitignore_global", "xdg-config:git"],
+    "gitui" => ["xdg-config:gitui"],
+    "gnupg" => [".gnupg/gpg.conf", ".gnupg/gpg-agent.conf"],
+    "gtk" => ["xdg-config:gtk-3.0/settings.ini", "xdg-config:gtk-4.0/settings.ini"],
+    "helix" => ["xdg-config:helix"],
+    "htop" => ["xdg-config:htop"],
+    "hyprland" => ["xdg-config:hypr"] reload "hyprctl reload",
+    "i3" => ["xdg-config:i3", ".i3"] reload "i3-msg reload",
+    "i3status" => ["xdg-config:i3status"],
+    "idea" => [".ideavimrc"],
+    "imv" => ["xdg-config:imv"],
+    "inputrc" => [".inputrc"],
+    "irssi" => [".irssi/config"],
+    "k9s" => ["xdg-config:k9s"],
+    "kak" => ["xdg-config:kak"],
+    "kanshi" => ["xdg-config:kanshi"] reload "pkill -HUP kanshi",
+    "kitty" => ["xdg-config:kitty"] reload "pkill -USR1 kitty",
+    "lazygit" => ["xdg-config:lazygit"],
+    "less" => [".lesskey"],
+    "lf" => ["xdg-config:lf"],
+    "mako" => ["xdg-config:mako"] reload "makoctl reload",
+    "mimeapps" => ["xdg-config:mimeapps.list"],
+    "mpd" => ["xdg-config:mpd", ".mpdconf"],
+    "mpv" => ["xdg-config:mpv"],
+    "msmtp" => [".msmtprc"],
+    "mutt" => [".muttrc", ".mutt", "xdg-config:mutt"],
+    "nano" => [".nanorc", "xdg-config:nano"],
+    "ncmpcpp" => [".ncmpcpp", "xdg-config:ncmpcpp"],
+    "neomutt" => [".neomuttrc", "xdg-config:neomutt"],
+    "neovim" => ["xdg-config:nvim"],
+    "newsboat" => [".newsboat", "xdg-config:newsboat"],
+    "nnn" => ["xdg-config:nnn"],
+    "npm" => [".npmrc"],
+    "offlineimap" => [".offlineimaprc"],
+    "picom" => ["xdg-config:picom", "xdg-config:picom.conf"],
+    "polybar" => ["xdg-config:polybar"] reload "polybar-msg cmd restart",
+    "profile" => [".profile"],
+    "pulse" => ["xdg-config:pulse/default.pa"],
+    "python" => [".pythonrc", ".pypirc"],
+    "qutebrowser" => ["xdg-config:qutebrowser/config.py"],
+    "ranger" => ["xdg-config:ranger"],
+    "readline" => [".editrc"],
+    "redshift" => ["xdg-config:redshift.conf", "xdg-config:redshift"],
+    "ripgrep" => [".ripgreprc"],
+    "rofi" => ["xdg-config:rofi"],
+    "screen" => [".screenrc"],
+    "ssh" => [".ssh/config"],
+    "starship" => ["xdg-config:starship.toml"],
+    "sway" => ["xdg-config:sway"] reload "swaymsg reload",
+    "swaylock" => ["xdg-config:swaylock"],
+    "sxhkd" => ["xdg-config:sxhkd"] reload "pkill -USR1 sxhkd",
+    "systemd" => ["xdg-config:systemd/user"] reload "systemctl --user daemon-reload",
+    "termite" => ["xdg-config:termite"],
+    "tig" => [".tigrc"],
+    "tmux" => [".tmux.conf", "xdg-config:tmux"] reload "tmux source-file ~/.tmux.conf",
+    "topgrade" => ["xdg-config:topgrade.toml"],
+    "urxvt" => [".urxvt"],
+    "vifm" => ["xdg-config:vifm", ".vifm"],
+    "vim" => [".vimrc", ".vim"],
+    "vscode" => ["xdg-config:Code/User/settings.json", "xdg-config:Code/User/keybindings.json"],
+    "waybar" => ["xdg-config:waybar"] reload "pkill -USR2 waybar",
+    "wezterm" => [".wezterm.lua", "xdg-config:wezterm"],
+    "wget" => [".wgetrc"],
+    "wofi" => ["xdg-config:wofi"],
+    "x11" => [".xinitrc", ".xprofile", ".Xresources", ".Xdefaults"] reload "xrdb -merge ~/.Xresources",
+    "xmonad" => [".xmonad"] reload "xmonad --restart",
+    "yazi" => ["xdg-config:yazi"],
+    "youtube-dl" => ["xdg-config:youtube-dl", "xdg-config:yt-dlp"],
+    "zathura" => ["xdg-config:zathura"],
+    "zed" => ["xdg-config:zed/settings.json", "xdg-config:zed/keymap.json"],
+    "zellij" => ["xdg-config:zellij"],
+    "zsh" => [".zshrc", ".zshenv", ".zprofile", ".zlogin", "xdg-config:zsh"],
+];
+
+impl App {
+    /// Targets that exist and aren't already symlinks (and so presumably managed elsewhere)
+    pub fn unmanaged_targets(&self) -> Vec<&str> {
+        self.targets
+            .iter()
+            .map(|target| target.as_str())
+            .filter(|target| {
+                fs::symlink_metadata(target::resolve(target))
+                    .is_ok_and(|metadata| !metadata.file_type().is_symlink())
+            })
+            .collect()
+    }
+}
+
+impl Catalog {
+    pub fn builtin() -> Catalog {
+        let apps = BUILTIN
+            .iter()
+            .map(|app| {
+                let entry = App {
+                    targets: app
+                        .targets
+                        .iter()
+                        .map(|target| target.to_string())
+                        .collect(),
+                    reload: app.reload.map(|reload| reload.to_owned()),
+                };
+                (app.name.to_owned(), entry)
+            })
+            .collect();
+        Catalog { apps }
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(CATALOG_DIR).join(CATALOG_FILE))
+    }
+
+    /// The built-in catalog, with the user's catalog added on top
+    pub fn load() -> Result<Catalog, CatalogError> {
+        let mut catalog = Catalog::builtin();
+        let path = match Catalog::path() {
+            Some(ref path) if path.exists() => path.clone(),
+            _ => return Ok(catalog),
+        };
+
+        let buf =
+            fs::read(&path).map_err(|err| CatalogError::IO(path.display().to_string(), err))?;
+        let user: Catalog = toml::from_slice(&buf)
+            .map_err(|err| CatalogError::Parse(path.display().to_string(), err))?;
+        catalog.apps.extend(user.apps);
+        Ok(catalog)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&App> {
+        self.apps.get(name)
+    }
+
+    pub fn apps(&self) -> &BTreeMap<String, App> {
+        &self.apps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_sorted() {
+        assert!(BUILTIN.windows(2).all(|pair| pair[0].name < pair[1].name));
+        let catalog = Catalog::builtin();
+        assert!(catalog.get("kitty").unwrap().reload.is_some());
+        assert!(catalog.get("vim").unwrap().reload.is_none());
+    }
+}
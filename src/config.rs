@@ -0,0 +1,191 @@
+extern crate toml;
+
+use dirs;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use target;
+
+static CONFIG_DIR: &str = "modman";
+static CONFIG_FILE: &str = "config.toml";
+static QUARANTINE_AFTER: u32 = 3;
+static LOG_RETENTION_DAYS: u32 = 30;
+static RUN_LOG_KB: u32 = 1024;
+static STATE_LOCK_TIMEOUT: u32 = 60;
+
+/// Global modman configuration, read from `$XDG_CONFIG_HOME/modman/config.toml`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Config {
+    /// Module directory used unless `--modules-dir` is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modules_dir: Option<String>,
+
+    /// Module directories searched after `modules_dir`, e.g. a shared work repository. Modules
+    /// in earlier directories hide those of the same name in later ones.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_modules_dirs: Vec<String>,
+
+    /// Record local usage statistics (see `modman stats`)
+    #[serde(default)]
+    pub stats: bool,
+
+    /// Consecutive verification or script failures before a module is quarantined (0 disables)
+    #[serde(default = "quarantine_after")]
+    pub quarantine_after: u32,
+
+    /// Days script logs are kept for (0 keeps them until their module is deleted)
+    #[serde(default = "log_retention_days")]
+    pub log_retention_days: u32,
+
+    /// Kilobytes the run log grows to before it is rotated (0 disables it)
+    #[serde(default = "run_log_kb")]
+    pub run_log_kb: u32,
+
+    /// Seconds init and cleanup scripts may run before they are killed, unless they set their
+    /// own timeout (0 lets them run forever)
+    #[serde(default)]
+    pub script_timeout: u32,
+
+    /// Where to look for links left behind by modules, as resource targets (default the home
+    /// and XDG config directories)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub target_roots: Vec<String>,
+
+    /// Create links relative to their directory, so the modules and home directory can be moved
+    /// or mounted elsewhere together, e.g. in a container (resources can override it)
+    #[serde(default)]
+    pub relative_links: bool,
+
+    /// Seconds to wait for another modman run to finish with the state file (0 waits forever)
+    #[serde(default = "state_lock_timeout")]
+    pub state_lock_timeout: u32,
+
+    /// Commit the files `--force` is about to delete to the `modman-backups` branch of the
+    /// primary modules directory's repository (or `backup_repo`), so they can be recovered
+    #[serde(default)]
+    pub git_backups: bool,
+
+    /// Repository to back up to instead, created if it doesn't exist
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_repo: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            modules_dir: None,
+            extra_modules_dirs: Vec::new(),
+            stats: false,
+            quarantine_after: QUARANTINE_AFTER,
+            log_retention_days: LOG_RETENTION_DAYS,
+            run_log_kb: RUN_LOG_KB,
+            script_timeout: 0,
+            target_roots: Vec::new(),
+            relative_links: false,
+            state_lock_timeout: STATE_LOCK_TIMEOUT,
+            git_backups: false,
+            backup_repo: None,
+        }
+    }
+}
+
+fn quarantine_after() -> u32 {
+    QUARANTINE_AFTER
+}
+
+fn log_retention_days() -> u32 {
+    LOG_RETENTION_DAYS
+}
+
+fn run_log_kb() -> u32 {
+    RUN_LOG_KB
+}
+
+fn state_lock_timeout() -> u32 {
+    STATE_LOCK_TIMEOUT
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Config {0}: {1}")]
+    Parse(String, #[source] toml::de::Error),
+    #[error("Config {0}: {1}")]
+    Serialize(String, #[source] toml::ser::Error),
+    #[error("Config {0}: {1}")]
+    IO(String, #[source] io::Error),
+}
+
+impl Config {
+    /// How long script logs are kept for, if not forever
+    pub fn log_retention(&self) -> Option<Duration> {
+        match self.log_retention_days {
+            0 => None,
+            days => Some(Duration::from_secs(u64::from(days) * 24 * 60 * 60)),
+        }
+    }
+
+    /// How long scripts may run by default, if not forever
+    pub fn script_timeout(&self) -> Option<Duration> {
+        match self.script_timeout {
+            0 => None,
+            secs => Some(Duration::from_secs(u64::from(secs))),
+        }
+    }
+
+    /// How long to wait for another modman run to finish with the state file, if not forever
+    pub fn state_lock_timeout(&self) -> Option<Duration> {
+        match self.state_lock_timeout {
+            0 => None,
+            secs => Some(Duration::from_secs(u64::from(secs))),
+        }
+    }
+
+    /// Directories to look for links left behind by modules in
+    pub fn target_roots(&self) -> Vec<PathBuf> {
+        if !self.target_roots.is_empty() {
+            return self
+                .target_roots
+                .iter()
+                .map(|root| target::resolve(root))
+                .collect();
+        }
+        let mut roots = vec![target::root()];
+        let config_dir = target::resolve("xdg-config:");
+        if !roots.contains(&config_dir) {
+            roots.push(config_dir);
+        }
+        roots
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(CONFIG_DIR).join(CONFIG_FILE))
+    }
+
+    /// Load the global config, falling back to the defaults if it does not exist.
+    pub fn load() -> Result<Config, ConfigError> {
+        let path = match Config::path() {
+            Some(path) => path,
+            None => return Ok(Config::default()),
+        };
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let buf =
+            fs::read(&path).map_err(|err| ConfigError::IO(path.display().to_string(), err))?;
+        toml::from_slice(&buf).map_err(|err| ConfigError::Parse(path.display().to_string(), err))
+    }
+
+    pub fn save(&self) -> Result<(), ConfigError> {
+        let path = match Config::path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let wrap = |err| ConfigError::IO(path.display().to_string(), err);
+        fs::create_dir_all(path.parent().unwrap()).map_err(&wrap)?;
+        let buf = toml::to_string(self)
+            .map_err(|err| ConfigError::Serialize(path.display().to_string(), err))?;
+        fs::write(&path, buf).map_err(&wrap)
+    }
+}
@@ -0,0 +1,130 @@
+//! Stepping through the files in the way of an install batch's links, one by one.
+use modman::module::{Module, Resolution};
+use modman::state::State;
+use output::{self, ask, confirm, paint, Style};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+static CHOICES: &[(&str, Resolution, &str)] = &[
+    (
+        "k",
+        Resolution::Keep,
+        "keep the file and leave the resource unlinked",
+    ),
+    ("o", Resolution::Overwrite, "delete the file"),
+    (
+        "b",
+        Resolution::Backup,
+        "move the file aside, to <name>.modman-bak",
+    ),
+    (
+        "a",
+        Resolution::Adopt,
+        "move the file into the module, replacing the resource",
+    ),
+];
+
+/// Apply the remembered decisions about files in the way of the modules' links, and with `ask`
+/// offer to resolve the others, showing how each differs from the resource. The resources of kept
+/// files are left out of the modules. Files that aren't resolved still fail the install or sync.
+pub fn resolve(modules: &mut [Module], state: &mut State, ask: bool) -> io::Result<()> {
+    let foreign: Vec<(usize, String, PathBuf)> = modules
+        .iter()
+        .enumerate()
+        .flat_map(|(i, module)| {
+            module
+                .foreign_files()
+                .into_iter()
+                .map(move |(resource, system_file)| (i, resource.to_owned(), system_file))
+        })
+        .collect();
+    let unknown = foreign
+        .iter()
+        .filter(|(_, _, system_file)| state.decision(system_file).is_none())
+        .count();
+    let interactive = ask && unknown > 0 && output::can_ask() && {
+        println!("{} files are in the way of links", unknown);
+        confirm("Resolve them one by one?", true)?
+    };
+    if interactive {
+        for &(key, _, description) in CHOICES {
+            println!("  {} - {}", key, description);
+        }
+    }
+
+    let mut kept: Vec<Vec<PathBuf>> = vec![Vec::new(); modules.len()];
+    for (i, resource, system_file) in foreign {
+        let module = &modules[i];
+        let resolution = match state.decision(&system_file) {
+            Some(resolution) => resolution,
+            None if interactive => {
+                let resolution = ask_resolution(module, &resource, &system_file)?;
+                if confirm("Remember this for future runs?", false)? {
+                    state.record_decision(&system_file, resolution);
+                }
+                resolution
+            }
+            None => continue,
+        };
+        match module.resolve_foreign(&resource, resolution) {
+            Ok(()) if resolution == Resolution::Keep => kept[i].push(system_file),
+            Ok(()) => {}
+            Err(err) => warn!("{}", err),
+        }
+    }
+    for (module, kept) in modules.iter_mut().zip(kept) {
+        module.exclude_targets(&kept);
+    }
+    Ok(())
+}
+
+/// Files in the way of the modules' links that a remembered decision overwrites, to confirm and back
+/// up before `resolve` deletes them
+pub fn remembered_overwrites(modules: &[Module], state: &State) -> Vec<PathBuf> {
+    modules
+        .iter()
+        .flat_map(Module::foreign_targets)
+        .filter(|system_file| state.decision(system_file) == Some(Resolution::Overwrite))
+        .collect()
+}
+
+fn ask_resolution(module: &Module, resource: &str, system_file: &Path) -> io::Result<Resolution> {
+    println!(
+        "{}",
+        paint(
+            Style::Warn,
+            format!(
+                "Module {}: {} is in the way of {}",
+                module.name(),
+                system_file.display(),
+                resource
+            )
+        )
+    );
+    show_diff(system_file, &module.path().join(resource));
+    let keys: Vec<&str> = CHOICES.iter().map(|&(key, _, _)| key).collect();
+    loop {
+        let answer = ask(&format!("Resolve ({})", keys.join("/")), "k")?;
+        let choice = CHOICES
+            .iter()
+            .find(|&&(key, _, _)| key == answer.to_lowercase());
+        if let Some(&(_, resolution, _)) = choice {
+            return Ok(resolution);
+        }
+    }
+}
+
+// Show how the file differs from the resource, as a unified diff from the file to the resource
+fn show_diff(system_file: &Path, resource: &Path) {
+    let status = process::Command::new("diff")
+        .arg("-ru")
+        .arg(system_file)
+        .arg(resource)
+        .status();
+    match status {
+        Ok(status) if status.code() == Some(0) => println!("{}", paint(Style::Dim, "(identical)")),
+        Ok(_) => {}
+        Err(_) => println!("{}", paint(Style::Dim, "(diff is not available)")),
+    }
+}
@@ -0,0 +1,917 @@
+//! Module config definitions, parsed and checked without touching the filesystem so the same
+//! code can validate configs anywhere (see the `wasm` feature).
+extern crate regex;
+extern crate serde;
+extern crate serde_json;
+extern crate toml;
+
+use self::regex::Regex;
+use self::serde::de::{self, Deserialize, Deserializer, Error, MapAccess, Visitor};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Component, Path};
+use std::time::Duration;
+
+/// Newest config schema; configs without a `version` are version 1
+pub static SCHEMA_VERSION: u32 = 2;
+pub static INIT_SCRIPT: &'static str = "init.sh";
+pub static CLEANUP_SCRIPT: &'static str = "cleanup.sh";
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ModuleDef {
+    #[serde(default = "default_version")]
+    pub version: u32,
+
+    pub description: Option<String>,
+
+    /// SPDX identifier (or free text) for the module's contents
+    pub license: Option<String>,
+
+    /// Where the module's contents were taken from
+    pub source_url: Option<String>,
+
+    pub author: Option<String>,
+
+    /// Documentation inside the module, shown by `modman info`, instead of `README.md`
+    pub docs: Option<String>,
+
+    /// Catalog entry of the program the module configures, which provides a default `reload`
+    pub app: Option<String>,
+
+    /// Shell command run after the module's links change, e.g. to make a program reread them
+    pub reload: Option<String>,
+
+    /// Shell command run after install to check the program still starts, e.g. `zsh -ic exit`
+    pub probe: Option<String>,
+
+    #[serde(default, deserialize_with = "deserialize_script")]
+    pub init: Option<ScriptDef>,
+
+    /// Only run the init script on the first successful install
+    #[serde(default)]
+    pub init_once: bool,
+
+    #[serde(default, deserialize_with = "deserialize_script")]
+    pub cleanup: Option<ScriptDef>,
+
+    #[serde(default)]
+    pub packages: Vec<String>,
+
+    #[serde(default)]
+    pub systemd: SystemdDef,
+
+    /// Files the module's scripts generate outside of the resources, as targets
+    #[serde(default)]
+    pub artifacts: Vec<String>,
+
+    /// Service that must be ready before the module's scripts run
+    pub wait_for: Option<WaitDef>,
+
+    #[serde(default)]
+    pub script_env: ScriptEnvDef,
+
+    #[serde(deserialize_with = "deserialize_resources")]
+    pub resources: HashMap<String, ResourceDef>,
+}
+
+fn default_version() -> u32 {
+    1
+}
+
+/// A script to run on install or uninstall; written as a table, or `true` before version 2
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptDef {
+    /// Script inside the module, instead of the default `init.sh` or `cleanup.sh`
+    pub script: Option<String>,
+
+    /// Program to run the script with, instead of executing it directly
+    pub interpreter: Option<String>,
+
+    /// How long the script may run before it is killed, e.g. `120` or `2m`, instead of the
+    /// global `script_timeout`
+    pub timeout: Option<String>,
+
+    /// Directory the script runs in, relative to the module, instead of the module itself. `~`
+    /// starts a path in the home directory.
+    pub workdir: Option<String>,
+
+    /// Written as `true`, which is deprecated
+    #[serde(skip)]
+    pub shorthand: bool,
+}
+
+fn deserialize_script<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<ScriptDef>, D::Error> {
+    struct ScriptVisitor;
+
+    impl<'de> Visitor<'de> for ScriptVisitor {
+        type Value = Option<ScriptDef>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a boolean or a table with a script and/or interpreter")
+        }
+
+        fn visit_bool<E: de::Error>(self, enabled: bool) -> Result<Option<ScriptDef>, E> {
+            Ok(if enabled {
+                Some(ScriptDef {
+                    shorthand: true,
+                    ..ScriptDef::default()
+                })
+            } else {
+                None
+            })
+        }
+
+        fn visit_map<M: MapAccess<'de>>(self, map: M) -> Result<Option<ScriptDef>, M::Error> {
+            ScriptDef::deserialize(de::value::MapAccessDeserializer::new(map)).map(Some)
+        }
+    }
+
+    deserializer.deserialize_any(ScriptVisitor)
+}
+
+/// Where and how a resource is installed; written as either just the target or a table
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceDef {
+    pub target: String,
+
+    /// The resource is age (`.age`) or gpg encrypted and is decrypted to the target
+    #[serde(default)]
+    pub encrypted: bool,
+
+    /// Only install on this OS (`linux`, `macos`, `windows`, ...)
+    pub if_os: Option<String>,
+
+    /// Only install on the machine with this hostname
+    pub if_hostname: Option<String>,
+
+    /// Only install if the variable is set and not empty (`VAR`) or has a value (`VAR=value`)
+    pub if_env: Option<String>,
+
+    /// Only install if the program is on the `PATH`
+    pub if_command: Option<String>,
+
+    /// Only install if the program the target configures, named after it (`waybar` for
+    /// `xdg-config:waybar`), is on the `PATH`
+    #[serde(default)]
+    pub if_app: bool,
+
+    /// The resource is a directory whose files are linked one by one below the target, which is
+    /// a real directory, so programs can keep their own files next to them
+    #[serde(default)]
+    pub link_children: bool,
+
+    /// Link with a path relative to the target's directory, overriding the global
+    /// `relative_links`, so the modules and home directory can be moved together
+    pub relative_link: Option<bool>,
+}
+
+// Accepts a plain target string as shorthand for a table with only a target
+struct TargetOrTable(ResourceDef);
+
+impl<'de> Deserialize<'de> for TargetOrTable {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<TargetOrTable, D::Error> {
+        struct ResourceVisitor;
+
+        impl<'de> Visitor<'de> for ResourceVisitor {
+            type Value = TargetOrTable;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a target path or a table with a target")
+            }
+
+            fn visit_str<E: de::Error>(self, target: &str) -> Result<TargetOrTable, E> {
+                Ok(TargetOrTable(ResourceDef {
+                    target: target.to_owned(),
+                    ..ResourceDef::default()
+                }))
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, map: M) -> Result<TargetOrTable, M::Error> {
+                ResourceDef::deserialize(de::value::MapAccessDeserializer::new(map))
+                    .map(TargetOrTable)
+            }
+        }
+
+        deserializer.deserialize_any(ResourceVisitor)
+    }
+}
+
+fn deserialize_resources<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, ResourceDef>, D::Error> {
+    let resources: HashMap<String, TargetOrTable> = HashMap::deserialize(deserializer)?;
+    Ok(resources
+        .into_iter()
+        .map(|(resource, definition)| (resource, definition.0))
+        .collect())
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct SystemdDef {
+    #[serde(default)]
+    pub units: Vec<String>,
+}
+
+/// A systemd unit that must be active or a TCP address that must accept connections
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WaitDef {
+    pub unit: Option<String>,
+
+    /// `host:port`
+    pub tcp: Option<String>,
+
+    /// How long to wait before giving up, e.g. `30s` or `2m`
+    #[serde(default = "default_wait_timeout")]
+    pub timeout: String,
+}
+
+fn default_wait_timeout() -> String {
+    "30s".to_owned()
+}
+
+/// Environment the module's scripts, reload and probe run in, whatever the user's shell sets
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptEnvDef {
+    /// Set as `LANG` and `LC_ALL`
+    pub locale: Option<String>,
+
+    /// File mode creation mask, in octal, e.g. `022`
+    pub umask: Option<String>,
+
+    /// Directories put in front of `PATH`; relative ones are inside the module
+    #[serde(default)]
+    pub path: Vec<String>,
+}
+
+impl ScriptEnvDef {
+    /// The umask, if it is set and valid
+    pub fn umask(&self) -> Option<u32> {
+        self.umask
+            .as_ref()
+            .and_then(|umask| u32::from_str_radix(umask, 8).ok())
+            .filter(|&umask| umask <= 0o777)
+    }
+}
+
+/// Parse a duration written as a number of seconds, optionally suffixed with `s`, `m` or `h`
+pub fn parse_duration(val: &str) -> Option<Duration> {
+    let (number, unit) = match val.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => val.split_at(i),
+        None => (val, "s"),
+    };
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return None,
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .map(|number| Duration::from_secs(number * seconds))
+}
+
+impl ModuleDef {
+    /// Parse a module config and check everything that does not depend on the filesystem
+    pub fn parse(buf: &[u8]) -> Result<ModuleDef, toml::de::Error> {
+        let module_definition: ModuleDef = toml::from_slice(buf)?;
+        module_definition.check()?;
+        Ok(module_definition)
+    }
+
+    /// Build a config from one already parsed, e.g. merged with its includes
+    pub fn from_value(value: toml::Value) -> Result<ModuleDef, toml::de::Error> {
+        let module_definition: ModuleDef = value.try_into()?;
+        module_definition.check()?;
+        Ok(module_definition)
+    }
+
+    /// Deprecated forms used by the config, which `modman migrate` rewrites. They are read as
+    /// before until the config declares the version that removes them.
+    pub fn deprecations(&self) -> Vec<Deprecation> {
+        let scripts = [
+            ("init", &self.init, INIT_SCRIPT),
+            ("cleanup", &self.cleanup, CLEANUP_SCRIPT),
+        ];
+        scripts
+            .iter()
+            .filter(|&&(_, script, _)| script.as_ref().is_some_and(|script| script.shorthand))
+            .map(|&(kind, _, default)| Deprecation {
+                key: vec![kind.to_owned()],
+                form: format!("{} = true", kind),
+                replacement: format!("{} = {{ script = \"{}\" }}", kind, default),
+                removed_in: 2,
+            })
+            .collect()
+    }
+
+    fn check(&self) -> Result<(), toml::de::Error> {
+        match self.problem() {
+            Some((_, message)) => Err(toml::de::Error::custom(message)),
+            None => Ok(()),
+        }
+    }
+
+    // The first problem serde can't catch, with the key it is about
+    fn problem(&self) -> Option<(Vec<String>, String)> {
+        let key = |path: &[&str]| path.iter().map(|part| part.to_string()).collect();
+        if self.version > SCHEMA_VERSION {
+            return Some((
+                key(&["version"]),
+                format!(
+                    "version {} needs a newer modman (this one supports up to {})",
+                    self.version, SCHEMA_VERSION
+                ),
+            ));
+        }
+        let removed = self
+            .deprecations()
+            .into_iter()
+            .find(|deprecation| self.version >= deprecation.removed_in);
+        if let Some(deprecation) = removed {
+            return Some((
+                deprecation.key.clone(),
+                format!("{}; Run `modman migrate`", deprecation),
+            ));
+        }
+
+        let scripts = [("init", &self.init), ("cleanup", &self.cleanup)];
+
+        if let Some(docs) = self.docs.as_ref().filter(|docs| escapes(docs)) {
+            return Some((
+                key(&["docs"]),
+                format!("docs {} must be a relative path inside the module", docs),
+            ));
+        }
+
+        if self.init_once && self.init.is_none() {
+            return Some((
+                key(&["init_once"]),
+                "init_once is set without an init script".to_owned(),
+            ));
+        }
+
+        for &(kind, script) in &scripts {
+            let path = script.as_ref().and_then(|script| script.script.as_ref());
+            if let Some(path) = path.filter(|path| escapes(path)) {
+                return Some((
+                    key(&[kind, "script"]),
+                    format!("script {} must be a relative path inside the module", path),
+                ));
+            }
+            let timeout = script.as_ref().and_then(|script| script.timeout.as_ref());
+            if let Some(timeout) = timeout.filter(|timeout| parse_duration(timeout).is_none()) {
+                return Some((
+                    key(&[kind, "timeout"]),
+                    format!("{} has an invalid timeout {}", kind, timeout),
+                ));
+            }
+        }
+
+        if let Some(ref umask) = self.script_env.umask {
+            if self.script_env.umask().is_none() {
+                return Some((
+                    key(&["script_env", "umask"]),
+                    format!("script_env has an invalid umask {}", umask),
+                ));
+            }
+        }
+
+        if let Some(ref wait) = self.wait_for {
+            if wait.unit.is_none() && wait.tcp.is_none() {
+                return Some((
+                    key(&["wait_for"]),
+                    "wait_for needs a unit or tcp address".to_owned(),
+                ));
+            }
+            if parse_duration(&wait.timeout).is_none() {
+                return Some((
+                    key(&["wait_for", "timeout"]),
+                    format!("wait_for has an invalid timeout {}", wait.timeout),
+                ));
+            }
+        }
+
+        let artifact = self
+            .artifacts
+            .iter()
+            .find_map(|artifact| artifact_problem(artifact).map(|problem| (artifact, problem)));
+        if let Some((artifact, problem)) = artifact {
+            return Some((
+                key(&["artifacts"]),
+                format!("artifact {:?} {}", artifact, problem),
+            ));
+        }
+
+        let mut resources: Vec<_> = self.resources.iter().collect();
+        resources.sort_by_key(|&(resource, _)| resource);
+        for (resource, definition) in resources {
+            let message = if definition.link_children && definition.encrypted {
+                format!(
+                    "resource {} can't both be encrypted and link_children",
+                    resource
+                )
+            } else if definition.relative_link.is_some() && definition.encrypted {
+                format!(
+                    "resource {} is encrypted, so it isn't linked and can't have relative_link",
+                    resource
+                )
+            } else {
+                continue;
+            };
+            return Some((key(&["resources", resource]), message));
+        }
+        None
+    }
+}
+
+/// Remove the list of included configs from a parsed config and return it. Included configs
+/// are relative to the including one, and merged in order before it, so later definitions
+/// override earlier ones.
+pub fn take_includes(config: &mut toml::Value) -> Result<Vec<String>, toml::de::Error> {
+    let include = match config
+        .as_table_mut()
+        .and_then(|table| table.remove("include"))
+    {
+        Some(include) => include,
+        None => return Ok(Vec::new()),
+    };
+    include
+        .try_into()
+        .map_err(|_| toml::de::Error::custom("include must be a list of paths"))
+}
+
+/// Merge `overlay` into `base`: tables are merged key by key, anything else in `overlay`
+/// replaces what is in `base`
+pub fn merge(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (&mut toml::Value::Table(ref mut base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Why an artifact can't be deleted safely, if it can't: it must name something below the home
+/// (or an XDG base) directory, not the directory itself or anything outside it
+pub fn artifact_problem(artifact: &str) -> Option<&'static str> {
+    let relative = artifact
+        .strip_prefix("xdg-")
+        .and_then(|rest| rest.split_once(':'))
+        .map_or(artifact, |(_, relative)| relative);
+    let components: Vec<Component> = Path::new(relative).components().collect();
+    if components
+        .iter()
+        .any(|c| matches!(c, Component::RootDir | Component::Prefix(_)))
+    {
+        Some("must be relative to the home directory")
+    } else if components.contains(&Component::ParentDir) {
+        Some("can't contain ..")
+    } else if !components.iter().any(|c| matches!(c, Component::Normal(_))) {
+        Some("would delete a whole base directory")
+    } else {
+        None
+    }
+}
+
+// Whether a path from the config points outside the module
+fn escapes(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Rewrite a config to the newest schema, keeping its comments and layout. Returns `None` if it
+/// is already up to date.
+pub fn migrate(source: &str) -> Result<Option<String>, toml::de::Error> {
+    let definition = ModuleDef::parse(source.as_bytes())?;
+    if definition.version == SCHEMA_VERSION {
+        return Ok(None);
+    }
+
+    let script = Regex::new(r"^(\s*)(init|cleanup)(\s*=\s*)true\b").unwrap();
+    let version = Regex::new(r"^\s*version\s*=").unwrap();
+    let mut top_level = true;
+    let mut version_index = None;
+    let mut lines: Vec<String> = Vec::new();
+    for line in source.lines() {
+        if line.trim_start().starts_with('[') {
+            top_level = false;
+        }
+        if top_level && version.is_match(line) {
+            version_index = Some(lines.len());
+        }
+        if !top_level {
+            lines.push(line.to_owned());
+            continue;
+        }
+        lines.push(
+            script
+                .replace(line, |captures: &regex::Captures| {
+                    let default = if &captures[2] == "init" {
+                        INIT_SCRIPT
+                    } else {
+                        CLEANUP_SCRIPT
+                    };
+                    format!(
+                        "{}{}{}{{ script = \"{}\" }}",
+                        &captures[1], &captures[2], &captures[3], default
+                    )
+                })
+                .into_owned(),
+        );
+    }
+    // Replace the old version, otherwise declare it first
+    let version_line = format!("version = {}", SCHEMA_VERSION);
+    match version_index {
+        Some(i) => lines[i] = version_line,
+        None => lines.insert(0, version_line),
+    }
+    let mut migrated = lines.join("\n");
+    if source.ends_with('\n') {
+        migrated.push('\n');
+    }
+
+    ModuleDef::parse(migrated.as_bytes())?;
+    Ok(Some(migrated))
+}
+
+/// Check that `source` is a valid module config, without looking at any module files
+pub fn validate(source: &str) -> Result<(), toml::de::Error> {
+    ModuleDef::parse(source.as_bytes()).map(|_| ())
+}
+
+/// A deprecated form in a module config and what replaces it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deprecation {
+    /// The key the form is at
+    pub key: Vec<String>,
+    pub form: String,
+    pub replacement: String,
+    /// The schema version that no longer reads the form
+    pub removed_in: u32,
+}
+
+impl fmt::Display for Deprecation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "`{}` is deprecated and not allowed from version {}; Write `{}` instead",
+            self.form, self.removed_in, self.replacement
+        )
+    }
+}
+
+/// Deprecated forms in `source`, a module config, that its version still reads, and where each
+/// is. Configs that fail to parse have none, as `diagnose` reports them.
+pub fn deprecations(source: &str) -> Vec<Diagnostic> {
+    let module_definition: ModuleDef = match toml::from_str(source) {
+        Ok(module_definition) => module_definition,
+        Err(_) => return Vec::new(),
+    };
+    module_definition
+        .deprecations()
+        .into_iter()
+        .filter(|deprecation| module_definition.version < deprecation.removed_in)
+        .map(|deprecation| Diagnostic {
+            line_col: locate(source, &deprecation.key),
+            message: deprecation.to_string(),
+        })
+        .collect()
+}
+
+/// A problem in a module config, and where it is (1-based) if that is known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub line_col: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line_col {
+            Some((line, col)) => write!(f, "{}:{}: {}", line, col, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Check that `source` is a valid module config like `validate`, but point at the problem in it.
+/// Configs that include others are only checked for syntax, as the rest depends on the includes.
+pub fn diagnose(source: &str) -> Result<(), Diagnostic> {
+    let mut value: toml::Value =
+        toml::from_str(source).map_err(|err| toml_diagnostic(source, &err))?;
+    if value.get("include").is_some() {
+        return take_includes(&mut value)
+            .map(|_| ())
+            .map_err(|err| Diagnostic {
+                line_col: locate(source, &["include".to_owned()]),
+                message: err.to_string(),
+            });
+    }
+    // Parsed again from the source, as only that tells which key a type error is about
+    let module_definition: ModuleDef =
+        toml::from_str(source).map_err(|err| toml_diagnostic(source, &err))?;
+    match module_definition.problem() {
+        Some((key, message)) => Err(Diagnostic {
+            line_col: locate(source, &key),
+            message,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// JSON Schema of module configs, for editors and other tools
+pub fn schema() -> serde_json::Value {
+    let string = serde_json::json!({ "type": "string" });
+    let strings = serde_json::json!({ "type": "array", "items": { "type": "string" } });
+    let script = serde_json::json!({
+        "description": "A script to run, `init.sh` or `cleanup.sh` unless given",
+        "type": "object",
+        "properties": {
+            "script": { "type": "string", "description": "Script inside the module" },
+            "interpreter": { "type": "string", "description": "Program to run the script with" },
+            "timeout": {
+                "type": "string",
+                "pattern": "^[0-9]+[smh]?$",
+                "description": "How long the script may run before it is killed"
+            },
+            "workdir": {
+                "type": "string",
+                "description": "Directory to run the script in, relative to the module"
+            }
+        },
+        "additionalProperties": false
+    });
+    let condition =
+        |description: &str| serde_json::json!({ "type": "string", "description": description });
+    let resource = serde_json::json!({
+        "type": "object",
+        "properties": {
+            "target": { "type": "string" },
+            "encrypted": { "type": "boolean", "description": "Decrypted with age or gpg" },
+            "if_os": condition("Only install on this OS"),
+            "if_hostname": condition("Only install on the machine with this hostname"),
+            "if_env": condition("Only install if `VAR` is set, or `VAR=value` holds"),
+            "if_command": condition("Only install if the program is on the PATH"),
+            "if_app": {
+                "type": "boolean",
+                "description": "Only install if the program named after the target is on the PATH"
+            },
+            "link_children": {
+                "type": "boolean",
+                "description": "Link the files in the directory one by one"
+            },
+            "relative_link": {
+                "type": "boolean",
+                "description": "Link with a path relative to the target's directory"
+            }
+        },
+        "required": ["target"],
+        "additionalProperties": false
+    });
+    serde_json::json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "modman module config",
+        "type": "object",
+        "properties": {
+            "version": { "type": "integer", "minimum": 1, "maximum": SCHEMA_VERSION },
+            "include": {
+                "description": "Configs merged in order before this one, relative to it",
+                "type": "array",
+                "items": { "type": "string" }
+            },
+            "description": string,
+            "license": string,
+            "source_url": string,
+            "author": string,
+            "docs": { "type": "string", "description": "Documentation inside the module" },
+            "app": { "type": "string", "description": "Catalog entry of the program configured" },
+            "reload": { "type": "string", "description": "Shell command run after links change" },
+            "probe": {
+                "type": "string",
+                "description": "Shell command checking the program still starts after install"
+            },
+            "init": { "oneOf": [{ "type": "boolean" }, script] },
+            "init_once": { "type": "boolean" },
+            "cleanup": { "oneOf": [{ "type": "boolean" }, script] },
+            "packages": strings,
+            "systemd": {
+                "type": "object",
+                "properties": { "units": strings }
+            },
+            "artifacts": {
+                "type": "array",
+                "items": { "type": "string", "minLength": 1 },
+                "description": "Files the scripts generate, below the home or an XDG base directory"
+            },
+            "wait_for": {
+                "type": "object",
+                "properties": {
+                    "unit": { "type": "string" },
+                    "tcp": { "type": "string", "description": "host:port" },
+                    "timeout": { "type": "string", "pattern": "^[0-9]+[smh]?$" }
+                },
+                "anyOf": [{ "required": ["unit"] }, { "required": ["tcp"] }],
+                "additionalProperties": false
+            },
+            "script_env": {
+                "type": "object",
+                "properties": {
+                    "locale": { "type": "string", "description": "Set as LANG and LC_ALL" },
+                    "umask": { "type": "string", "pattern": "^[0-7]{1,3}$" },
+                    "path": {
+                        "description": "Directories put in front of PATH, relative to the module",
+                        "type": "array",
+                        "items": { "type": "string" }
+                    }
+                },
+                "additionalProperties": false
+            },
+            "resources": {
+                "description": "Module files and where they are installed",
+                "type": "object",
+                "additionalProperties": {
+                    "oneOf": [{ "type": "string", "minLength": 1 }, resource]
+                }
+            }
+        },
+        "required": ["resources"]
+    })
+}
+
+// Syntax errors know their position, others at most the key they are about
+fn toml_diagnostic(source: &str, err: &toml::de::Error) -> Diagnostic {
+    let at_line = Regex::new(r" at line \d+$").unwrap();
+    let message = at_line.replace(&err.to_string(), "").into_owned();
+    let line_col = match err.line_col() {
+        Some((line, col)) => Some((line + 1, col + 1)),
+        None => Regex::new(r" for key `([^`]+)`$")
+            .unwrap()
+            .captures(&message)
+            .and_then(|captures| {
+                let key: Vec<String> = captures[1].split('.').map(str::to_owned).collect();
+                locate(source, &key)
+            }),
+    };
+    Diagnostic { line_col, message }
+}
+
+/// Find where the dotted `key` is defined in a config, or the closest enclosing key that is. Keys
+/// are only looked for in table headers and at the start of lines, which covers the usual layout.
+pub(crate) fn locate(source: &str, key: &[String]) -> Option<(usize, usize)> {
+    let header = Regex::new(r"^\s*\[\s*([^\[\]]+?)\s*\]").unwrap();
+    let assignment = Regex::new(r#"^\s*("[^"]*"|[A-Za-z0-9_-]+)\s*="#).unwrap();
+    let parts = |dotted: &str| -> Vec<String> {
+        dotted
+            .split('.')
+            .map(|part| part.trim().trim_matches('"').to_owned())
+            .collect()
+    };
+
+    let mut table: Vec<String> = Vec::new();
+    let mut best: Option<(usize, (usize, usize))> = None;
+    for (i, line) in source.lines().enumerate() {
+        let (path, col) = if let Some(captures) = header.captures(line) {
+            table = parts(&captures[1]);
+            (table.clone(), captures.get(1).unwrap().start())
+        } else if let Some(captures) = assignment.captures(line) {
+            let mut path = table.clone();
+            path.push(captures[1].trim_matches('"').to_owned());
+            (path, captures.get(1).unwrap().start())
+        } else {
+            continue;
+        };
+        let depth = path.len();
+        if key.starts_with(&path) && best.is_none_or(|(best_depth, _)| depth > best_depth) {
+            best = Some((depth, (i + 1, col + 1)));
+        }
+    }
+    best.map(|(_, line_col)| line_col)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(include_str!("../example.toml")).is_ok());
+        // Accepted since before configs were validated on their own
+        assert!(validate("[resources]\n\"../escape\" = \".escape\"").is_ok());
+        assert!(validate("[resources]\nfile = \"\"").is_ok());
+        assert!(validate("resources = 1").is_err());
+        assert!(validate("init = { script = \"../init.sh\" }\n[resources]").is_err());
+        assert!(validate("docs = \"/etc/motd\"\n[resources]").is_err());
+        assert!(validate("init_once = true\n[resources]").is_err());
+        assert!(validate("version = 3\n[resources]").is_err());
+        assert!(validate("version = 2\ninit = true\n[resources]").is_err());
+        assert!(validate("wait_for = {}\n[resources]").is_err());
+        for artifact in &[
+            "",
+            ".",
+            "./",
+            "..",
+            "a/../..",
+            "/",
+            "/etc",
+            "xdg-cache:",
+            "xdg-cache:.",
+        ] {
+            let config = format!("artifacts = [{:?}]\n[resources]", artifact);
+            assert!(validate(&config).is_err(), "{}", artifact);
+        }
+        assert!(validate("artifacts = [\"xdg-cache:zsh\", \".terminfo\"]\n[resources]").is_ok());
+        assert!(validate("wait_for = { tcp = \"a:1\", timeout = \"1d\" }\n[resources]").is_err());
+    }
+
+    #[test]
+    fn test_diagnose() {
+        assert_eq!(diagnose(include_str!("../example.toml")), Ok(()));
+        let at = |source: &str| diagnose(source).unwrap_err().line_col;
+        assert_eq!(at("init = {\n[resources]"), Some((1, 9)), "syntax error");
+        assert_eq!(at("[resources]\nrc = 1"), Some((2, 1)), "type error");
+        assert_eq!(
+            at("[resources]\nrc = \".rc\"\nx = { target = \".x\", encrypted = true, link_children = true }"),
+            Some((3, 1))
+        );
+        assert_eq!(
+            at("[wait_for]\nunit = \"a\"\ntimeout = \"1d\"\n[resources]"),
+            Some((3, 1))
+        );
+        assert_eq!(
+            at("packages = []"),
+            None,
+            "missing resources has no position"
+        );
+        assert_eq!(diagnose("include = [\"base.toml\"]"), Ok(()));
+    }
+
+    #[test]
+    fn test_schema_covers_example() {
+        let schema = schema();
+        let example: toml::Value = toml::from_str(include_str!("../example.toml")).unwrap();
+        for key in example.as_table().unwrap().keys() {
+            assert!(schema["properties"].get(key).is_some(), "{} missing", key);
+        }
+        let resource = &schema["properties"]["resources"]["additionalProperties"]["oneOf"][1];
+        for definition in example["resources"].as_table().unwrap().values() {
+            for key in definition
+                .as_table()
+                .into_iter()
+                .flat_map(|table| table.keys())
+            {
+                assert!(resource["properties"].get(key).is_some(), "{} missing", key);
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut base: toml::Value = toml::from_str(
+            "include = [\"a.toml\"]\npackages = [\"zsh\"]\n[resources]\nrc = \".rc\"",
+        )
+        .unwrap();
+        assert_eq!(take_includes(&mut base).unwrap(), vec!["a.toml"]);
+        let overlay = toml::from_str("packages = [\"fish\"]\n[resources]\nenv = \".env\"").unwrap();
+        merge(&mut base, overlay);
+        let merged = ModuleDef::from_value(base).unwrap();
+        assert_eq!(merged.packages, vec!["fish"]);
+        assert_eq!(merged.resources.len(), 2);
+        assert!(take_includes(&mut toml::from_str("include = 1").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_migrate() {
+        let old = "# Shell\ninit = true # Set up\ncleanup=false\n[resources]\ninit = \"x\"\n";
+        assert_eq!(
+            migrate(old).unwrap().unwrap(),
+            "version = 2\n# Shell\ninit = { script = \"init.sh\" } # Set up\ncleanup=false\n\
+             [resources]\ninit = \"x\"\n"
+        );
+        assert!(migrate("version = 2\n[resources]").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_deprecations() {
+        let found = deprecations("# Shell\ninit = true\n[resources]\n");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].line_col, Some((2, 1)));
+        assert!(found[0].message.contains("init = { script = \"init.sh\" }"));
+        assert!(deprecations("version = 2\ninit = true\n[resources]").is_empty());
+        assert!(deprecations("init = false\n[resources]").is_empty());
+    }
+}
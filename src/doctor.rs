@@ -0,0 +1,274 @@
+//! `modman doctor`: checks of the environment modman works in, each with a suggested fix.
+use dirs;
+use gc;
+use modman::catalog::Catalog;
+use modman::config::Config;
+use modman::module::{Module, ModuleDirs, ModuleError};
+use modman::policy::Policy;
+use modman::state::State;
+use output::{paint, Style};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    Ok,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    name: &'static str,
+    severity: Severity,
+    message: String,
+    fix: Option<String>,
+}
+
+impl Check {
+    fn ok<S: Into<String>>(name: &'static str, message: S) -> Check {
+        Check {
+            name,
+            severity: Severity::Ok,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    fn problem<S: Into<String>, F: Into<String>>(
+        name: &'static str,
+        severity: Severity,
+        message: S,
+        fix: F,
+    ) -> Check {
+        Check {
+            name,
+            severity,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// Run every check and print the results, looking for orphaned links below `roots`. Returns
+/// whether none of them failed.
+pub fn run(module_dirs: &ModuleDirs, roots: &[PathBuf]) -> bool {
+    let mut checks = vec![check_home()];
+    checks.extend(module_dirs.iter().map(check_modules_dir));
+    checks.extend(check_configs());
+    let modules = load_modules(module_dirs, &mut checks);
+    checks.extend(check_state(module_dirs));
+    checks.push(check_orphans(module_dirs, &modules, roots));
+
+    let width = checks
+        .iter()
+        .map(|check| check.name.len())
+        .max()
+        .unwrap_or(0);
+    for check in &checks {
+        let status = match check.severity {
+            Severity::Ok => paint(Style::Ok, " OK "),
+            Severity::Warn => paint(Style::Warn, "WARN"),
+            Severity::Fail => paint(Style::Fail, "FAIL"),
+        };
+        println!(
+            "[{}] {:width$}  {}",
+            status,
+            check.name,
+            check.message,
+            width = width
+        );
+        if let Some(ref fix) = check.fix {
+            println!(
+                "       {:width$}  {}",
+                "",
+                paint(Style::Dim, fix),
+                width = width
+            );
+        }
+    }
+    checks.iter().all(|check| check.severity != Severity::Fail)
+}
+
+fn check_home() -> Check {
+    match dirs::home_dir() {
+        Some(ref home) if home.is_dir() => Check::ok("Home", home.display().to_string()),
+        _ => Check::problem(
+            "Home",
+            Severity::Fail,
+            "The home directory can't be found",
+            "Set $HOME to your home directory",
+        ),
+    }
+}
+
+fn check_modules_dir(module_dir: &Path) -> Check {
+    match fs::read_dir(module_dir) {
+        Ok(_) => Check::ok("Modules", module_dir.display().to_string()),
+        Err(err) => Check::problem(
+            "Modules",
+            Severity::Fail,
+            format!("{}: {}", module_dir.display(), err),
+            "Create it, pass --modules-dir or set modules_dir with `modman init`",
+        ),
+    }
+}
+
+fn check_configs() -> Vec<Check> {
+    let config = match Config::load() {
+        Ok(_) => Check::ok("Config", "OK"),
+        Err(err) => Check::problem(
+            "Config",
+            Severity::Fail,
+            err.to_string(),
+            "Fix the config or recreate it with `modman init`",
+        ),
+    };
+    let policy = match Policy::get() {
+        Ok(_) => Check::ok("Policy", "OK"),
+        Err(err) => Check::problem(
+            "Policy",
+            Severity::Fail,
+            err,
+            format!("Ask the administrator to fix {}", Policy::path().display()),
+        ),
+    };
+    let catalog = match Catalog::load() {
+        Ok(_) => Check::ok("Catalog", "OK"),
+        Err(err) => Check::problem(
+            "Catalog",
+            Severity::Warn,
+            err.to_string(),
+            "Fix the catalog; Only the built-in one is used meanwhile",
+        ),
+    };
+    vec![config, policy, catalog]
+}
+
+// All modules, noting how many are broken
+fn load_modules(
+    module_dirs: &ModuleDirs,
+    checks: &mut Vec<Check>,
+) -> Vec<Result<Module, ModuleError>> {
+    let modules = match module_dirs.list() {
+        Ok(modules) => modules,
+        Err(_) => return Vec::new(),
+    };
+    let total = modules.len();
+    let invalid = modules.iter().filter(|module| module.is_err()).count();
+    checks.push(if invalid == 0 {
+        Check::ok("Verification", format!("{} modules are valid", total))
+    } else {
+        Check::problem(
+            "Verification",
+            Severity::Warn,
+            format!("{} of {} modules are invalid", invalid, total),
+            "Run `modman list --verify` to see why",
+        )
+    });
+    modules
+}
+
+fn check_state(module_dirs: &ModuleDirs) -> Vec<Check> {
+    let state = match State::load() {
+        Ok(state) => state,
+        Err(err) => {
+            return vec![Check::problem(
+                "State",
+                Severity::Fail,
+                err.to_string(),
+                "Delete the state file; Quarantines and recorded links will be forgotten",
+            )]
+        }
+    };
+    let mut checks = Vec::new();
+    if state.recovered {
+        checks.push(Check::problem(
+            "State",
+            Severity::Warn,
+            "The state file was torn and recovered from the last consistent copy",
+            "Run `modman gc` to save the recovered state",
+        ));
+    }
+
+    let mut deleted = Vec::new();
+    let mut drifted = Vec::new();
+    let mut quarantined = Vec::new();
+    for (name, module) in &state.modules {
+        let path = module_dirs.path(name);
+        if !path.is_dir() {
+            deleted.push(name.as_str());
+            continue;
+        }
+        if module.quarantined {
+            quarantined.push(name.as_str());
+        }
+        if !module.intact(&path) {
+            drifted.push(name.as_str());
+        }
+    }
+
+    if !deleted.is_empty() {
+        checks.push(Check::problem(
+            "State",
+            Severity::Warn,
+            format!("Entries for deleted modules {}", deleted.join(", ")),
+            "Run `modman gc`",
+        ));
+    }
+    if !drifted.is_empty() {
+        checks.push(Check::problem(
+            "Links",
+            Severity::Warn,
+            format!(
+                "Links or copies of {} were removed or changed",
+                drifted.join(", ")
+            ),
+            format!(
+                "Run `modman sync {}`, or `modman drift` for changed copies",
+                drifted.join(" ")
+            ),
+        ));
+    }
+    if !quarantined.is_empty() {
+        checks.push(Check::problem(
+            "Quarantine",
+            Severity::Warn,
+            format!("{} quarantined", quarantined.join(", ")),
+            format!(
+                "Fix them, then run `modman unquarantine {}`",
+                quarantined.join(" ")
+            ),
+        ));
+    }
+    if checks.is_empty() {
+        checks.push(Check::ok("State", "Consistent with the links on disk"));
+    }
+    checks
+}
+
+// Symlinks into the modules directory that no module installs
+fn check_orphans(
+    module_dirs: &ModuleDirs,
+    modules: &[Result<Module, ModuleError>],
+    roots: &[PathBuf],
+) -> Check {
+    let state = State::load().unwrap_or_default();
+    let orphans = gc::find_orphans(module_dirs, modules, &state, roots);
+    if orphans.is_empty() {
+        return Check::ok(
+            "Orphans",
+            "No links into the modules directory from unknown modules",
+        );
+    }
+    let list = orphans
+        .iter()
+        .map(|orphan| orphan.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    Check::problem(
+        "Orphans",
+        Severity::Warn,
+        format!("Links no module installs: {}", list),
+        "Run `modman prune`, or restore the modules they came from",
+    )
+}
@@ -0,0 +1,61 @@
+//! Asking before a batch links resources into system locations, such as `/etc`, with sudo.
+use modman::module::Module;
+use output::{self, confirm, paint, Style};
+use std::io;
+use std::path::PathBuf;
+
+/// Let the modules use sudo for the resources whose targets the user can't write, if `system` is
+/// set or the user agrees to the list of them. Otherwise, when `installing`, those resources are
+/// left out so the rest of each module can still be installed; uninstalling such a module fails
+/// instead, as leaving them out would leave their links behind.
+pub fn resolve(modules: &mut [Module], system: bool, installing: bool) -> io::Result<()> {
+    let privileged: Vec<Vec<(String, PathBuf)>> = modules
+        .iter()
+        .map(|module| {
+            module
+                .privileged_resources()
+                .into_iter()
+                .map(|(resource, system_file)| (resource.to_owned(), system_file))
+                .collect()
+        })
+        .collect();
+    let count: usize = privileged.iter().map(Vec::len).sum();
+    if count == 0 {
+        return Ok(());
+    }
+
+    let escalate = system || {
+        println!(
+            "{} resources are in system locations, which need sudo:",
+            count
+        );
+        for (module, resources) in modules.iter().zip(&privileged) {
+            for (resource, system_file) in resources {
+                println!(
+                    "  {}/{} -> {}",
+                    module.name(),
+                    resource,
+                    paint(Style::Warn, system_file.display())
+                );
+            }
+        }
+        output::can_ask() && confirm("Use sudo for these resources?", false)?
+    };
+    for (module, resources) in modules.iter_mut().zip(privileged) {
+        if resources.is_empty() {
+            continue;
+        }
+        if escalate {
+            module.allow_escalation();
+        } else if installing {
+            let targets: Vec<PathBuf> = resources.into_iter().map(|(_, target)| target).collect();
+            module.exclude_targets(&targets);
+        }
+    }
+    if !escalate && installing {
+        println!("Skipping them; Pass --system to use sudo without asking");
+    } else if !escalate {
+        println!("Leaving their modules installed; Pass --system to use sudo without asking");
+    }
+    Ok(())
+}
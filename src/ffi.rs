@@ -0,0 +1,139 @@
+//! Minimal C API over the module operations, enabled with the `ffi` feature.
+//!
+//! Strings returned by the library are owned by the caller and must be released with
+//! `modman_string_free`. Functions returning `int` use negative values for errors, with the
+//! message available from `modman_last_error` on the same thread.
+//! See `include/modman.h` for the C declarations.
+
+use cancel::Cancel;
+use module::{Module, Status};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::path::Path;
+use std::ptr;
+
+pub const MODMAN_NOT_INSTALLED: c_int = 0;
+pub const MODMAN_PARTIAL: c_int = 1;
+pub const MODMAN_INSTALLED: c_int = 2;
+pub const MODMAN_ERROR: c_int = -1;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error<S: ToString>(err: S) {
+    let message = CString::new(err.to_string().replace('\0', "")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+unsafe fn to_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        set_last_error("Unexpected null pointer");
+        return None;
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Some(s),
+        Err(err) => {
+            set_last_error(err);
+            None
+        }
+    }
+}
+
+unsafe fn load(modules_dir: *const c_char, module: *const c_char) -> Option<Module> {
+    let modules_dir = to_str(modules_dir)?;
+    let module = to_str(module)?;
+    match Module::new(Path::new(modules_dir).join(module)) {
+        Ok(module) => Some(module),
+        Err(err) => {
+            set_last_error(err);
+            None
+        }
+    }
+}
+
+/// Newline separated names of the valid modules in `modules_dir`, or NULL on error.
+///
+/// # Safety
+/// String arguments must be NULL or valid NUL terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn modman_list(modules_dir: *const c_char) -> *mut c_char {
+    let modules_dir = match to_str(modules_dir) {
+        Some(modules_dir) => modules_dir,
+        None => return ptr::null_mut(),
+    };
+    match Module::list(modules_dir) {
+        Ok(modules) => {
+            let names: Vec<String> = modules
+                .into_iter()
+                .filter_map(|module| module.ok())
+                .map(|module| module.name().replace('\0', ""))
+                .collect();
+            CString::new(names.join("\n")).unwrap().into_raw()
+        }
+        Err(err) => {
+            set_last_error(err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// One of `MODMAN_INSTALLED`, `MODMAN_PARTIAL` or `MODMAN_NOT_INSTALLED`, or `MODMAN_ERROR`.
+///
+/// # Safety
+/// String arguments must be NULL or valid NUL terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn modman_status(modules_dir: *const c_char, module: *const c_char) -> c_int {
+    match load(modules_dir, module) {
+        Some(module) => match module.status() {
+            Status::Installed => MODMAN_INSTALLED,
+            Status::Partial => MODMAN_PARTIAL,
+            Status::NotInstalled => MODMAN_NOT_INSTALLED,
+        },
+        None => MODMAN_ERROR,
+    }
+}
+
+/// Install a module, returning 0 on success or `MODMAN_ERROR`.
+///
+/// # Safety
+/// String arguments must be NULL or valid NUL terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn modman_install(
+    modules_dir: *const c_char,
+    module: *const c_char,
+    force: c_int,
+) -> c_int {
+    let module = match load(modules_dir, module) {
+        Some(module) => module,
+        None => return MODMAN_ERROR,
+    };
+    match module.install(force != 0, false, true, &Cancel::new()) {
+        Ok(()) => 0,
+        Err(err) => {
+            set_last_error(err);
+            MODMAN_ERROR
+        }
+    }
+}
+
+/// Message for the last error on this thread, or NULL if there was none.
+#[no_mangle]
+pub extern "C" fn modman_last_error() -> *mut c_char {
+    LAST_ERROR.with(|last| match *last.borrow() {
+        Some(ref message) => message.clone().into_raw(),
+        None => ptr::null_mut(),
+    })
+}
+
+/// Release a string returned by the library.
+///
+/// # Safety
+/// `s` must be NULL or a string returned by this library that has not already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn modman_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
@@ -0,0 +1,191 @@
+//! Cleaning up what builds up over time: script logs, the state of deleted modules and links
+//! no module installs anymore.
+use modman::module::{self, Module, ModuleDirs, ModuleError};
+use modman::state::State;
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// How deep to look for orphaned links below each target root
+static ORPHAN_DEPTH: usize = 2;
+
+/// Everything that can be cleaned up
+#[derive(Debug, Default)]
+pub struct Garbage {
+    /// Script logs older than the retention, and their sizes
+    pub logs: Vec<(PathBuf, u64)>,
+    /// Other script logs of deleted modules, and their sizes
+    pub orphaned_logs: Vec<(PathBuf, u64)>,
+    /// Deleted modules the state still has entries for
+    pub modules: Vec<String>,
+}
+
+impl Garbage {
+    /// Find logs of deleted modules or older than `retention` (never, if it is `None`), and
+    /// state entries of deleted modules
+    pub fn find(
+        module_dirs: &ModuleDirs,
+        state: &State,
+        retention: Option<Duration>,
+    ) -> io::Result<Garbage> {
+        let mut garbage = Garbage::default();
+        let logs = module::script_log_dir().and_then(|dir| fs::read_dir(dir).ok());
+        for entry in logs.into_iter().flatten() {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let module = name
+                .trim_end_matches(".log")
+                .rsplit_once('-')
+                .map_or(name.as_str(), |(module, _)| module);
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.elapsed().ok());
+            let expired = match (age, retention) {
+                (Some(age), Some(retention)) => age > retention,
+                _ => false,
+            };
+            if expired {
+                garbage.logs.push((entry.path(), metadata.len()));
+            } else if !module_dirs.contains(module) {
+                garbage.orphaned_logs.push((entry.path(), metadata.len()));
+            }
+        }
+
+        garbage.modules = state
+            .modules
+            .keys()
+            .chain(state.pending.iter())
+            .filter(|name| !module_dirs.contains(name))
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        Ok(garbage)
+    }
+
+    /// Bytes freed by removing the garbage
+    pub fn size(&self) -> u64 {
+        self.logs
+            .iter()
+            .chain(&self.orphaned_logs)
+            .map(|&(_, size)| size)
+            .sum()
+    }
+
+    /// Delete only the expired logs. A module that looks deleted may just be in a modules
+    /// directory that wasn't given this time, so nothing of it is touched.
+    pub fn remove_expired(&self) -> io::Result<()> {
+        for (log, _) in &self.logs {
+            fs::remove_file(log)?;
+        }
+        Ok(())
+    }
+
+    /// Delete the logs and forget the modules. The state still has to be saved.
+    pub fn remove(&self, module_dirs: &ModuleDirs, state: &mut State) -> io::Result<()> {
+        self.remove_expired()?;
+        for (log, _) in &self.orphaned_logs {
+            fs::remove_file(log)?;
+        }
+        if !self.modules.is_empty() {
+            state.reconcile(module_dirs);
+        }
+        Ok(())
+    }
+}
+
+/// Symlinks below `roots` into the module directories that no module installs or has recorded in
+/// `state`, e.g. because the resource was renamed or dropped. Links into modules that fail to verify are left alone, as
+/// their resources are unknown.
+pub fn find_orphans(
+    module_dirs: &ModuleDirs,
+    modules: &[Result<Module, ModuleError>],
+    state: &State,
+    roots: &[PathBuf],
+) -> Vec<PathBuf> {
+    let mut claimed: HashSet<PathBuf> = HashSet::new();
+    let mut unknown: Vec<PathBuf> = Vec::new();
+    for module in modules {
+        match *module {
+            Ok(ref module) => {
+                claimed.extend(module.targets());
+                claimed.extend(module.left_out_targets());
+                claimed.extend(state.links(module.name()).iter().cloned());
+            }
+            Err(ref err) => unknown.extend(err.module().map(|name| module_dirs.path(name))),
+        }
+    }
+    let mut orphans = Vec::new();
+    for root in roots {
+        walk(root, module_dirs, ORPHAN_DEPTH, &mut |link, actual| {
+            let known = claimed.contains(link) || unknown.iter().any(|dir| actual.starts_with(dir));
+            if module_dirs.root_of(actual).is_some() && !known {
+                orphans.push(link.to_owned());
+            }
+        });
+    }
+    orphans.sort();
+    orphans.dedup();
+    orphans
+}
+
+/// Symlinks into `old_dir`, where a modules directory was before it was moved, and where each
+/// points. Both the links recorded in the state and those below `roots` are found.
+pub fn find_moved_links(
+    old_dir: &Path,
+    module_dirs: &ModuleDirs,
+    state: &State,
+    roots: &[PathBuf],
+) -> Vec<(PathBuf, PathBuf)> {
+    let mut links = Vec::new();
+    let recorded = state
+        .modules
+        .values()
+        .flat_map(|module| module.links.iter());
+    for link in recorded {
+        if let Ok(actual) = module::link_destination(link) {
+            if actual.starts_with(old_dir) {
+                links.push((link.clone(), actual));
+            }
+        }
+    }
+    for root in roots {
+        walk(root, module_dirs, ORPHAN_DEPTH, &mut |link, actual| {
+            if actual.starts_with(old_dir) {
+                links.push((link.to_owned(), actual.to_owned()));
+            }
+        });
+    }
+    links.sort();
+    links.dedup();
+    links
+}
+
+// Call `found` with each symlink and where it points, without descending into module directories
+fn walk<F: FnMut(&Path, &Path)>(dir: &Path, module_dirs: &ModuleDirs, depth: usize, found: &mut F) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if module_dirs.iter().any(|module_dir| path == module_dir) {
+            continue;
+        }
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_symlink() => {
+                if let Ok(actual) = module::link_destination(&path) {
+                    found(&path, &actual);
+                }
+            }
+            Ok(file_type) if file_type.is_dir() && depth > 0 => {
+                walk(&path, module_dirs, depth - 1, found)
+            }
+            _ => {}
+        }
+    }
+}
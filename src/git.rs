@@ -0,0 +1,160 @@
+//! What modman needs to know about module directories kept in git, and backing up files it
+//! deletes into a git repository.
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+
+/// The branch files are backed up to
+pub static BACKUP_BRANCH: &str = "modman-backups";
+// Backups are staged here, inside the repository's git directory, not in its index
+static BACKUP_INDEX: &str = "modman-backups.index";
+// Backups are committed as modman, whoever is set up in git
+static BACKUP_IDENTITY: &[&str] = &[
+    "-c",
+    "user.name=modman",
+    "-c",
+    "user.email=modman@localhost",
+];
+
+/// The state of the git repository a module directory is in
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RepoStatus {
+    /// Files below the directory with uncommitted changes, including untracked ones
+    pub changed: Vec<String>,
+    /// Commits the upstream branch has that the checkout doesn't, as of the last fetch
+    pub behind: u32,
+    /// The upstream branch, if one is set
+    pub upstream: Option<String>,
+}
+
+impl RepoStatus {
+    pub fn is_clean(&self) -> bool {
+        self.changed.is_empty() && self.behind == 0
+    }
+}
+
+/// The status of the repository `dir` is in, or `None` if it isn't in one or git can't be run.
+/// Nothing is fetched, so how far behind it is only as fresh as the last fetch.
+pub fn status(dir: &Path) -> Option<RepoStatus> {
+    let changed = git(dir, &["status", "--porcelain", "--", "."])?;
+    let upstream = git(dir, &["rev-parse", "--abbrev-ref", "@{upstream}"])
+        .map(|upstream| upstream.trim().to_owned());
+    let behind = match upstream {
+        Some(_) => git(dir, &["rev-list", "--count", "HEAD..@{upstream}"])
+            .and_then(|count| count.trim().parse().ok())
+            .unwrap_or(0),
+        None => 0,
+    };
+    Some(RepoStatus {
+        changed: changed
+            .lines()
+            .filter_map(|line| line.get(3..))
+            .map(str::to_owned)
+            .collect(),
+        behind,
+        upstream,
+    })
+}
+
+/// The commit checked out in the repository `dir` is in
+pub fn head(dir: &Path) -> Option<String> {
+    git(dir, &["rev-parse", "HEAD"]).map(|sha| sha.trim().to_owned())
+}
+
+/// The contents of `file`, relative to `dir`, as of `revision`, if it existed then
+pub fn show(dir: &Path, revision: &str, file: &str) -> Option<String> {
+    git(dir, &["show", &format!("{}:./{}", revision, file)])
+}
+
+/// Commit copies of `files` to `BACKUP_BRANCH` in the repository `repo` is in, without touching
+/// its work tree, index or checked out branch, and return the commit. Each file is stored at its
+/// absolute path, e.g. `home/me/.zshrc`, on top of the files backed up before. A `repo` that
+/// doesn't exist yet is created as a bare repository.
+pub fn back_up(repo: &Path, files: &[PathBuf], message: &str) -> io::Result<String> {
+    if !repo.exists() {
+        fs::create_dir_all(repo)?;
+        run(repo, &["init", "-q", "--bare"], None, None)?;
+    }
+    let index =
+        PathBuf::from(run(repo, &["rev-parse", "--git-path", BACKUP_INDEX], None, None)?.trim());
+    let index = repo.join(index);
+    let branch = format!("refs/heads/{}", BACKUP_BRANCH);
+    let parent =
+        git(repo, &["rev-parse", "--verify", "-q", &branch]).map(|sha| sha.trim().to_owned());
+
+    let result = (|| {
+        match parent {
+            Some(ref parent) => run(repo, &["read-tree", parent], None, Some(&index))?,
+            None => run(repo, &["read-tree", "--empty"], None, Some(&index))?,
+        };
+        for file in files {
+            let blob = run(repo, &["hash-object", "-w", "--"], Some(file), None)?;
+            let cacheinfo = format!("100644,{},{}", blob.trim(), tree_path(file));
+            run(
+                repo,
+                &["update-index", "--add", "--cacheinfo", &cacheinfo],
+                None,
+                Some(&index),
+            )?;
+        }
+        let tree = run(repo, &["write-tree"], None, Some(&index))?;
+        let mut args = vec!["commit-tree", tree.trim(), "-m", message];
+        if let Some(ref parent) = parent {
+            args.extend(["-p", parent]);
+        }
+        let commit = run(repo, &args, None, None)?;
+        run(repo, &["update-ref", &branch, commit.trim()], None, None)?;
+        Ok(commit.trim().to_owned())
+    })();
+    let _ = fs::remove_file(&index);
+    result
+}
+
+// Where a file is backed up in the tree: its absolute path without the root (or drive)
+fn tree_path(file: &Path) -> String {
+    file.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Run a git command in `dir`, with an extra path argument and a separate index if given, and
+// return its output
+fn run(dir: &Path, args: &[&str], path: Option<&Path>, index: Option<&Path>) -> io::Result<String> {
+    let mut command = process::Command::new("git");
+    command.arg("-C").arg(dir).args(BACKUP_IDENTITY).args(args);
+    if let Some(path) = path {
+        command.arg(path);
+    }
+    if let Some(index) = index {
+        command.env("GIT_INDEX_FILE", index);
+    }
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// The output of a git command run in `dir`, if it succeeds
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stderr(process::Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,120 @@
+//! `modman status`: a health score for each installed module, as a dashboard for large setups.
+use modman::module::{Module, ModuleDirs, ModuleError, Status};
+use modman::state::State;
+use output::{self, paint, Style};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// Installs older than this no longer count as verified recently
+static RECENT: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// What is right with a module; each check is worth a quarter of the score
+struct Health {
+    /// Every resource is linked
+    links: bool,
+    /// Its scripts and verification haven't failed since the last success
+    scripts: bool,
+    /// No links were changed or left behind since the last install
+    drift: bool,
+    /// It was installed successfully within `RECENT`
+    recent: bool,
+}
+
+impl Health {
+    fn check(module: &Module, state: &State) -> Health {
+        let links = state.links(module.name());
+        let module_state = state.modules.get(module.name());
+        let intact = module_state.is_none_or(|state| state.intact(module.path()));
+        Health {
+            links: module.status() == Status::Installed,
+            scripts: module_state.is_none_or(|module| module.failures == 0 && !module.quarantined),
+            drift: intact && module.stale_links(links).is_empty(),
+            recent: state
+                .installed_at(module.name())
+                .and_then(|time| time.elapsed().ok())
+                .is_some_and(|age| age < RECENT),
+        }
+    }
+
+    fn score(&self) -> u32 {
+        [self.links, self.scripts, self.drift, self.recent]
+            .iter()
+            .filter(|&&ok| ok)
+            .count() as u32
+            * 25
+    }
+
+    fn problems(&self) -> Vec<&'static str> {
+        let checks = [
+            (self.links, "links missing"),
+            (self.scripts, "scripts failing"),
+            (self.drift, "drifted"),
+            (self.recent, "not installed recently"),
+        ];
+        checks
+            .iter()
+            .filter(|&&(ok, _)| !ok)
+            .map(|&(_, problem)| problem)
+            .collect()
+    }
+}
+
+fn style(score: u32) -> Style {
+    match score {
+        100 => Style::Ok,
+        50..=99 => Style::Warn,
+        _ => Style::Fail,
+    }
+}
+
+/// Print the score of every module that is installed or was installed before, and a summary line
+pub fn run(module_dirs: &ModuleDirs, state: &State) -> Result<(), ModuleError> {
+    let mut rows = Vec::new();
+    let mut counts = [0; 3];
+    for mut module in module_dirs.list()? {
+        let (name, score, problems) = match module {
+            Ok(ref mut module) => {
+                let copies = state
+                    .checksums(module.name())
+                    .into_iter()
+                    .flat_map(BTreeMap::keys);
+                module.set_copies(copies.cloned());
+                if module.status() == Status::NotInstalled && state.links(module.name()).is_empty()
+                {
+                    continue;
+                }
+                let health = Health::check(module, state);
+                (module.name(), health.score(), health.problems().join(", "))
+            }
+            // A broken module that was installed is as unhealthy as it gets
+            Err(ref err) => match err.module() {
+                Some(name) if !state.links(name).is_empty() => (name, 0, "invalid".to_owned()),
+                _ => continue,
+            },
+        };
+        counts[match score {
+            100 => 0,
+            50..=99 => 1,
+            _ => 2,
+        }] += 1;
+        rows.push(vec![
+            name.to_owned(),
+            paint(style(score), format!("{:>3}%", score)),
+            paint(Style::Dim, problems),
+        ]);
+    }
+
+    if rows.is_empty() {
+        println!("No modules installed");
+        return Ok(());
+    }
+    output::table(&["MODULE", "HEALTH", "PROBLEMS"], &rows);
+    println!(
+        "{} modules: {}, {}, {}",
+        rows.len(),
+        paint(Style::Ok, format!("{} healthy", counts[0])),
+        paint(Style::Warn, format!("{} degraded", counts[1])),
+        paint(Style::Fail, format!("{} failing", counts[2]))
+    );
+    Ok(())
+}
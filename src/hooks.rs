@@ -0,0 +1,66 @@
+//! Repository hooks: scripts in the `hooks` directory of a modules directory, run once around a
+//! whole install, uninstall or sync rather than for each module, e.g. to run `fc-cache` once.
+//!
+//! `pre_<operation>_all.sh` runs before any module and stops the operation if it fails.
+//! `post_<operation>_all.sh` runs after the last one, where failing is only a warning. Both get
+//! the modules in `$MODMAN_MODULES`, separated by spaces: all of them before, and those that
+//! succeeded after.
+use modman::module::{self, IoContext, Module, ModuleDirs, ModuleError};
+use std::env;
+use std::process;
+
+static MODULES_ENV: &str = "MODMAN_MODULES";
+
+/// Run the pre hooks of `operation` for `modules`, stopping at the first that fails
+pub fn pre(
+    module_dirs: &ModuleDirs,
+    operation: &str,
+    modules: &[Module],
+) -> Result<(), ModuleError> {
+    let names: Vec<&str> = modules.iter().map(Module::name).collect();
+    run(module_dirs, &format!("pre_{}_all.sh", operation), &names)
+}
+
+/// Run the post hooks of `operation` for the modules that succeeded, warning if one fails
+pub fn post(
+    module_dirs: &ModuleDirs,
+    operation: &str,
+    results: &[(&Module, Result<(), ModuleError>)],
+) {
+    let names: Vec<&str> = results
+        .iter()
+        .filter(|(_, result)| result.is_ok())
+        .map(|(module, _)| module.name())
+        .collect();
+    if let Err(err) = run(module_dirs, &format!("post_{}_all.sh", operation), &names) {
+        warn!("{}", err);
+    }
+}
+
+// Run the hook called `hook` in each modules directory that has one
+fn run(module_dirs: &ModuleDirs, hook: &str, modules: &[&str]) -> Result<(), ModuleError> {
+    if modules.is_empty() {
+        return Ok(());
+    }
+    for dir in module_dirs.iter() {
+        // Absolute, as the hook runs in the modules directory
+        let dir = env::current_dir().context(module::HOOKS_DIR)?.join(dir);
+        let path = dir.join(module::HOOKS_DIR).join(hook);
+        if !path.is_file() {
+            continue;
+        }
+        info!("Run hook {}", path.display());
+        let status = process::Command::new(&path)
+            .current_dir(&dir)
+            .env(MODULES_ENV, modules.join(" "))
+            .status()
+            .context(module::HOOKS_DIR)?;
+        if !status.success() {
+            return Err(ModuleError::Exec(
+                module::HOOKS_DIR.to_owned(),
+                hook.to_owned(),
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,456 @@
+//! Converting what other dotfile managers track, chezmoi's source directory or yadm's repository,
+//! into modules: one per program, with a resource per file.
+//!
+//! Neither tool's templates can be carried over, as modman has none. Files that only apply to
+//! some machines become resources with conditions instead (`if_os`, `if_hostname`, `if_env`),
+//! and everything else that can't be converted is reported.
+extern crate regex;
+extern crate toml;
+
+use self::regex::Regex;
+use catalog::Catalog;
+use module::{self, IoContext, Module, ModuleError};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use target;
+
+// Attribute prefixes of chezmoi source names that don't matter for a link
+static CHEZMOI_IGNORED: &[&str] = &[
+    "create_",
+    "empty_",
+    "exact_",
+    "executable_",
+    "private_",
+    "readonly_",
+];
+// Prefixes of chezmoi entries that aren't plain files or directories
+static CHEZMOI_UNSUPPORTED: &[&str] =
+    &["external_", "modify_", "remove_", "run_", "symlink_"];
+// Where yadm keeps its own files in the home directory
+static YADM_DIRS: &[&str] = &[".config/yadm", ".local/share/yadm"];
+
+/// What to put where, and on which machines
+struct Entry {
+    contents: Contents,
+    target: String,
+    conditions: Vec<(&'static str, String)>,
+    encrypted: bool,
+}
+
+enum Contents {
+    File(PathBuf),
+    Text(String),
+}
+
+/// Modules an import created with how many resources each has, and the files it left out with why
+#[derive(Debug, Default)]
+pub struct Import {
+    pub modules: Vec<(String, usize)>,
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+/// Import a chezmoi source directory (honouring `.chezmoiroot`) into modules in `module_dir`.
+/// Templates whose whole content is conditional on the OS or hostname become resources with that
+/// condition; other templates, scripts and special entries are skipped.
+pub fn chezmoi<P: AsRef<Path>>(
+    source_dir: &Path,
+    module_dir: P,
+    catalog: &Catalog,
+) -> Result<Import, ModuleError> {
+    let mut root = source_dir.to_path_buf();
+    if let Ok(subdir) = fs::read_to_string(source_dir.join(".chezmoiroot")) {
+        root.push(subdir.trim());
+    }
+    let mut import = Import::default();
+    let mut entries = Vec::new();
+    walk_chezmoi(&root, "", &mut entries, &mut import.skipped).context("chezmoi")?;
+    write_modules(module_dir.as_ref(), catalog, entries, &mut import)?;
+    Ok(import)
+}
+
+/// Import the files tracked by a yadm repository (a bare git repository whose work tree is the
+/// home directory) into modules in `module_dir`. Alternate files for an OS, hostname or user
+/// become resources with that condition; a default alternate is only used without others.
+pub fn yadm<P: AsRef<Path>>(
+    repo: &Path,
+    home: &Path,
+    module_dir: P,
+    catalog: &Catalog,
+) -> Result<Import, ModuleError> {
+    let output = process::Command::new("git")
+        .arg("--git-dir")
+        .arg(repo)
+        .arg("--work-tree")
+        .arg(home)
+        .args(["ls-files", "-z"])
+        .output()
+        .context("yadm")?;
+    if !output.status.success() {
+        return Err(ModuleError::Exec(
+            "yadm".to_owned(),
+            "git ls-files".to_owned(),
+        ));
+    }
+
+    let mut import = Import::default();
+    let mut alternates: BTreeMap<String, Vec<(PathBuf, String)>> = BTreeMap::new();
+    for file in String::from_utf8_lossy(&output.stdout).split_terminator('\0') {
+        if YADM_DIRS.iter().any(|dir| Path::new(file).starts_with(dir)) {
+            continue;
+        }
+        let (base, conditions) = match file.split_once("##") {
+            Some((base, conditions)) => (base, conditions),
+            None => (file, ""),
+        };
+        alternates
+            .entry(base.to_owned())
+            .or_default()
+            .push((home.join(file), conditions.to_owned()));
+    }
+
+    let mut entries = Vec::new();
+    for (base, variants) in alternates {
+        let alone = variants.len() == 1;
+        for (source, conditions) in variants {
+            match yadm_conditions(&conditions, alone) {
+                Ok(conditions) => entries.push(Entry {
+                    contents: Contents::File(source),
+                    target: target::from_home(&base),
+                    conditions,
+                    encrypted: false,
+                }),
+                Err(reason) => import.skipped.push((source, reason)),
+            }
+        }
+    }
+    write_modules(module_dir.as_ref(), catalog, entries, &mut import)?;
+    Ok(import)
+}
+
+// Files below a chezmoi source directory, `relative` being where `dir` is in the home directory
+fn walk_chezmoi(
+    dir: &Path,
+    relative: &str,
+    entries: &mut Vec<Entry>,
+    skipped: &mut Vec<(PathBuf, String)>,
+) -> io::Result<()> {
+    let mut children: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    children.sort_by_key(|entry| entry.file_name());
+    for entry in children {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // chezmoi itself ignores these, e.g. .git and its own .chezmoi* files
+        if name.starts_with('.') {
+            continue;
+        }
+        let source = entry.path();
+        let is_dir = entry.file_type()?.is_dir();
+        let (name, encrypted, template) = match chezmoi_name(&name, is_dir) {
+            Ok(decoded) => decoded,
+            Err(reason) => {
+                skipped.push((source, reason));
+                continue;
+            }
+        };
+        let path = if relative.is_empty() {
+            name
+        } else {
+            format!("{}/{}", relative, name)
+        };
+        if is_dir {
+            walk_chezmoi(&source, &path, entries, skipped)?;
+            continue;
+        }
+
+        let mut entry = Entry {
+            contents: Contents::File(source.clone()),
+            target: target::from_home(&path),
+            conditions: Vec::new(),
+            encrypted,
+        };
+        if template {
+            match convert_template(&fs::read_to_string(&source)?) {
+                Some((contents, conditions)) => {
+                    entry.contents = Contents::Text(contents);
+                    entry.conditions = conditions;
+                }
+                None => {
+                    skipped.push((source, "template; modman has no templates".to_owned()));
+                    continue;
+                }
+            }
+        }
+        entries.push(entry);
+    }
+    Ok(())
+}
+
+// The name a chezmoi source name stands for, whether it is encrypted and whether it is a template,
+// or why it can't be imported
+fn chezmoi_name(name: &str, is_dir: bool) -> Result<(String, bool, bool), String> {
+    let mut rest = name;
+    let mut encrypted = false;
+    let mut dot = false;
+    loop {
+        if let Some(prefix) = CHEZMOI_UNSUPPORTED
+            .iter()
+            .find(|prefix| rest.starts_with(*prefix))
+        {
+            return Err(format!("{} entry", prefix.trim_end_matches('_')));
+        }
+        if let Some(prefix) = CHEZMOI_IGNORED
+            .iter()
+            .find(|prefix| rest.starts_with(*prefix))
+        {
+            rest = &rest[prefix.len()..];
+        } else if let Some(stripped) = rest.strip_prefix("encrypted_") {
+            encrypted = true;
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix("dot_") {
+            dot = true;
+            rest = stripped;
+            break;
+        } else if let Some(stripped) = rest.strip_prefix("literal_") {
+            rest = stripped;
+            break;
+        } else {
+            break;
+        }
+    }
+
+    let mut template = false;
+    if !is_dir {
+        if let Some(stripped) = rest.strip_suffix(".literal") {
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_suffix(".tmpl") {
+            template = true;
+            rest = stripped;
+        }
+        if encrypted {
+            rest = rest
+                .strip_suffix(".age")
+                .or_else(|| rest.strip_suffix(".asc"))
+                .unwrap_or(rest);
+        }
+    }
+    let name = if dot {
+        format!(".{}", rest)
+    } else {
+        rest.to_owned()
+    };
+    Ok((name, encrypted, template))
+}
+
+// A chezmoi template as a plain file with conditions, if it has no template actions or is
+// entirely wrapped in `{{ if eq .chezmoi.os "..." }}` (or `.chezmoi.hostname`)
+fn convert_template(template: &str) -> Option<(String, Vec<(&'static str, String)>)> {
+    if !template.contains("{{") {
+        return Some((template.to_owned(), Vec::new()));
+    }
+    let wrapped = Regex::new(
+        r#"(?s)^\{\{-?\s*if\s+eq\s+\.chezmoi\.(os|hostname)\s+"([^"]*)"\s*-?\}\}\r?\n?(.*?)\{\{-?\s*end\s*-?\}\}\s*$"#,
+    )
+    .unwrap();
+    let captures = wrapped.captures(template)?;
+    let body = &captures[3];
+    if body.contains("{{") {
+        return None;
+    }
+    let condition = match &captures[1] {
+        "os" => ("if_os", os_name(&captures[2])),
+        _ => ("if_hostname", captures[2].to_owned()),
+    };
+    Some((body.to_owned(), vec![condition]))
+}
+
+// Conditions of a yadm alternate file, from what follows `##` in its name, or why it can't be
+// imported. A default alternate is only kept if it is the only variant, as modman has no
+// condition for "no other alternate applies".
+fn yadm_conditions(conditions: &str, alone: bool) -> Result<Vec<(&'static str, String)>, String> {
+    let mut converted = Vec::new();
+    for condition in conditions
+        .split(',')
+        .filter(|condition| !condition.is_empty())
+    {
+        let (kind, value) = condition.split_once('.').unwrap_or((condition, ""));
+        match kind {
+            "os" | "o" => converted.push(("if_os", os_name(value))),
+            "hostname" | "h" => converted.push(("if_hostname", value.to_owned())),
+            "user" | "u" => converted.push(("if_env", format!("USER={}", value))),
+            "default" if alone => {}
+            "default" => {
+                return Err("default alternate; modman can't tell when no other applies".to_owned())
+            }
+            "template" | "t" => return Err("template; modman has no templates".to_owned()),
+            "extension" | "e" => {}
+            _ => return Err(format!("{} condition; modman has no equivalent", kind)),
+        }
+    }
+    Ok(converted)
+}
+
+// `if_os` value of an OS as `uname -s` or chezmoi names it
+fn os_name(os: &str) -> String {
+    match os.to_lowercase().as_str() {
+        "darwin" => "macos".to_owned(),
+        os => os.to_owned(),
+    }
+}
+
+// Resource for a target inside a module: its path below the home or XDG base directory, without
+// leading dots so nothing is hidden
+fn resource_path(target: &str) -> String {
+    let relative = target.split_once(':').map_or(target, |(_, rest)| rest);
+    relative
+        .split('/')
+        .map(|component| component.trim_start_matches('.'))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// The program a target configures: the catalog's name for it, or else one derived from the target
+fn program(catalog: &Catalog, target: &str) -> String {
+    catalog
+        .apps()
+        .iter()
+        .find(|&(_, app)| {
+            app.targets.iter().any(|app_target| {
+                target
+                    .strip_prefix(app_target.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+        })
+        .map(|(name, _)| name.clone())
+        .or_else(|| target::app_name(target))
+        .unwrap_or_else(|| "misc".to_owned())
+}
+
+// Create a module per program for the entries, skipping those of modules that already exist
+fn write_modules(
+    module_dir: &Path,
+    catalog: &Catalog,
+    entries: Vec<Entry>,
+    import: &mut Import,
+) -> Result<(), ModuleError> {
+    let mut programs: BTreeMap<String, Vec<Entry>> = BTreeMap::new();
+    for entry in entries {
+        let program = program(catalog, &entry.target);
+        programs.entry(program).or_default().push(entry);
+    }
+
+    for (name, entries) in programs {
+        let path = module_dir.join(&name);
+        if path.exists() {
+            for entry in entries {
+                if let Contents::File(source) = entry.contents {
+                    import
+                        .skipped
+                        .push((source, format!("module {} already exists", name)));
+                }
+            }
+            continue;
+        }
+
+        let mut config = format!("version = {}\n\n[resources]\n", ::SCHEMA_VERSION);
+        let mut taken = HashSet::new();
+        let count = entries.len();
+        for entry in entries {
+            let mut base = resource_path(&entry.target);
+            // Keep the extension that tells modman to decrypt with age
+            if let Contents::File(ref source) = entry.contents {
+                if entry.encrypted && source.extension().is_some_and(|ext| ext == "age") {
+                    base.push_str(".age");
+                }
+            }
+            let mut resource = base.clone();
+            for i in 2.. {
+                if taken.insert(resource.clone()) {
+                    break;
+                }
+                resource = format!("{}{}", base, i);
+            }
+            let dest = path.join(&resource);
+            fs::create_dir_all(dest.parent().unwrap()).context(&name)?;
+            match entry.contents {
+                Contents::File(ref source) => module::copy_tree(source, &dest),
+                Contents::Text(ref text) => fs::write(&dest, text),
+            }
+            .context(&name)?;
+
+            let target = toml::Value::String(entry.target);
+            let definition = if entry.conditions.is_empty() && !entry.encrypted {
+                target.to_string()
+            } else {
+                let mut fields = vec![format!("target = {}", target)];
+                if entry.encrypted {
+                    fields.push("encrypted = true".to_owned());
+                }
+                for (key, value) in entry.conditions {
+                    fields.push(format!("{} = {}", key, toml::Value::String(value)));
+                }
+                format!("{{ {} }}", fields.join(", "))
+            };
+            config.push_str(&format!(
+                "{} = {}\n",
+                toml::Value::String(resource),
+                definition
+            ));
+        }
+        fs::write(path.join(module::CONFIG_FILE), config).context(&name)?;
+        Module::new(path)?;
+        import.modules.push((name, count));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chezmoi_name() {
+        assert_eq!(
+            chezmoi_name("private_dot_ssh", true),
+            Ok((".ssh".to_owned(), false, false))
+        );
+        assert_eq!(
+            chezmoi_name("encrypted_private_dot_netrc.age", false),
+            Ok((".netrc".to_owned(), true, false))
+        );
+        assert_eq!(
+            chezmoi_name("dot_gitconfig.tmpl", false),
+            Ok((".gitconfig".to_owned(), false, true))
+        );
+        assert_eq!(
+            chezmoi_name("literal_dot_x.tmpl.literal", false),
+            Ok(("dot_x.tmpl".to_owned(), false, false))
+        );
+        assert!(chezmoi_name("run_once_install.sh", false).is_err());
+    }
+
+    #[test]
+    fn test_convert_template() {
+        let (contents, conditions) =
+            convert_template("{{ if eq .chezmoi.os \"darwin\" }}\nset -g mouse on\n{{ end }}\n")
+                .unwrap();
+        assert_eq!(contents, "set -g mouse on\n");
+        assert_eq!(conditions, [("if_os", "macos".to_owned())]);
+        assert!(convert_template("name = {{ .name }}").is_none());
+    }
+
+    #[test]
+    fn test_yadm_conditions() {
+        assert_eq!(
+            yadm_conditions("os.Linux,hostname.work", false),
+            Ok(vec![
+                ("if_os", "linux".to_owned()),
+                ("if_hostname", "work".to_owned())
+            ])
+        );
+        assert_eq!(yadm_conditions("default", true), Ok(Vec::new()));
+        assert!(yadm_conditions("default", false).is_err());
+        assert!(yadm_conditions("class.Work", false).is_err());
+    }
+}
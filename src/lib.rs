@@ -0,0 +1,49 @@
+#[cfg(not(target_arch = "wasm32"))]
+extern crate dirs;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate thiserror;
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+
+// Only config parsing is available on wasm32, everything else needs a filesystem
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bootstrap;
+pub mod cancel;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod catalog;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod config;
+mod definition;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod git;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod import;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod module;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod platform;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod policy;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod state;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod stats;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod target;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(target_os = "linux")]
+pub mod watch;
+
+pub use definition::{
+    deprecations, diagnose, migrate, parse_duration, schema, validate, Deprecation, Diagnostic,
+    SCHEMA_VERSION,
+};
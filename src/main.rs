@@ -1,65 +1,230 @@
+extern crate anyhow;
 #[macro_use]
 extern crate clap;
 extern crate dirs;
 #[macro_use]
 extern crate log;
-#[macro_use]
-extern crate failure;
-#[macro_use]
-extern crate serde_derive;
+extern crate modman;
+extern crate regex;
+extern crate serde_json;
 
-mod module;
+mod conflicts;
+mod doctor;
+mod escalation;
+mod gc;
+mod health;
+mod hooks;
+mod output;
+mod runlog;
+mod support;
+#[cfg(feature = "tui")]
+mod tui;
+mod wizard;
 
-use self::module::{Module, ModuleError};
+use self::gc::Garbage;
+use self::output::{paint, ColorChoice, Progress, Style};
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
-use log::{Level, Metadata, Record};
+use modman::bootstrap::{self, Plan};
+use modman::cancel::Cancel;
+use modman::catalog::Catalog;
+use modman::config::Config;
+use modman::git;
+use modman::import::{self, Import};
+use modman::module::{self, IoContext, Mode, Module, ModuleDirs, ModuleError, ResourceStatus};
+use modman::parse_duration;
+use modman::platform;
+use modman::state::State;
+use modman::stats::{Operation, Stats};
+use modman::target;
+#[cfg(target_os = "linux")]
+use modman::watch::Watcher;
+use regex::Regex;
+use std::collections::BTreeMap;
+#[cfg(target_os = "linux")]
+use std::collections::HashMap;
 use std::collections::HashSet;
-use std::path::Path;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant, SystemTime};
 
-static LOGGER: SimpleLogger = SimpleLogger;
+// Exit codes; invalid arguments exit through clap with 1, and a failure of a single kind with the
+// code of its `ErrorKind`, listed by `--help-exit-codes`
+static EXIT_PARTIAL: i32 = 2;
+static EXIT_FAILED: i32 = 3;
+// Uncommitted files named when warning about a dirty modules directory
+static CHANGED_FILES_SHOWN: usize = 3;
+// Where a module's changelog is, whose new entries sync shows
+static CHANGELOG_FILE: &str = "CHANGELOG.md";
 
-struct SimpleLogger;
+// Subcommands that never modify the system, and so are allowed in read-only mode
+static READ_ONLY_COMMANDS: &[&str] = &[
+    "list",
+    "show",
+    "audit",
+    "stats",
+    "doctor",
+    "decisions",
+    "status",
+    "support-bundle",
+    "schema",
+    "check",
+    "is-installed",
+    "is-clean",
+    "resources",
+    "info",
+    // Changes are made by the modman runs it starts
+    "tui",
+];
 
-impl log::Log for SimpleLogger {
-    fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Info
+fn main() {
+    if env::args().skip(1).any(|arg| arg == "--help-exit-codes") {
+        print_exit_codes();
+        return;
     }
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => CliError::from(err).exit(),
+    };
 
-    fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            println!("{}", record.args());
-        }
+    let platform_dir = platform::default_modules_dir()
+        .expect("HOME directory could not be determined.")
+        .into_os_string();
+    let default_dir = iter::once(
+        config
+            .modules_dir
+            .clone()
+            .unwrap_or_else(|| platform_dir.to_str().unwrap().to_owned()),
+    )
+    .chain(config.extra_modules_dirs.iter().cloned())
+    .collect::<Vec<_>>()
+    .join(platform::PATH_LIST_SEPARATOR);
+    let app = build(&default_dir).get_matches();
+    let color = if app.is_present("no-color") {
+        ColorChoice::Never
+    } else {
+        ColorChoice::parse(app.value_of("color").unwrap()).unwrap()
+    };
+    output::init(app.is_present("verbose"), color);
+    // Targets are resolved all over, and scripts should see the same root, so it goes through
+    // the environment
+    if let Some(dir) = app.value_of("target-dir") {
+        let dir = env::current_dir()
+            .map(|cwd| cwd.join(dir))
+            .unwrap_or_else(|_| PathBuf::from(dir));
+        env::set_var(target::TARGET_ENV, dir);
     }
 
-    fn flush(&self) {}
-}
+    if read_only(&app) && !READ_ONLY_COMMANDS.contains(&app.subcommand_name().unwrap()) {
+        clap::Error::with_description(
+            &format!(
+                "{} is not allowed in read-only mode",
+                app.subcommand_name().unwrap()
+            ),
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    if !READ_ONLY_COMMANDS.contains(&app.subcommand_name().unwrap())
+        && !app.is_present("allow-remote-source")
+    {
+        for dir in module_dirs(&app).iter() {
+            if let Some(mount) = platform::unreliable_mount(dir) {
+                warn!(
+                    "Modules directory {} is on {}; Links into it break while it is unmounted (--allow-remote-source silences this)",
+                    dir.display(),
+                    mount
+                );
+            }
+        }
+    }
 
-fn main() {
-    let home = dirs::home_dir()
-        .expect("HOME directory could not be determined.")
-        .join(".dotfiles")
-        .into_os_string();
-    let app = initialise(home.to_str().unwrap());
-    log::set_logger(&LOGGER).unwrap();
-    match app.is_present("verbose") {
-        true => log::set_max_level(log::LevelFilter::Info),
-        _ => log::set_max_level(log::LevelFilter::Warn),
+    if !READ_ONLY_COMMANDS.contains(&app.subcommand_name().unwrap()) && config.run_log_kb > 0 {
+        if let Err(err) = runlog::open(u64::from(config.run_log_kb) * 1024) {
+            warn!("Run log: {}", err);
+        }
     }
 
-    let err = match app.subcommand() {
-        ("list", Some(sub)) => list(&sub),
-        ("install", Some(sub)) => install(&sub),
-        ("uninstall", Some(sub)) => uninstall(&sub),
+    // Held until modman exits, so runs that change anything take turns with the state
+    let _state_lock = if READ_ONLY_COMMANDS.contains(&app.subcommand_name().unwrap()) {
+        None
+    } else {
+        State::lock(config.state_lock_timeout()).unwrap_or_else(|err| {
+            let err = CliError::from(err);
+            runlog::exit(err.code, Some(&err.error));
+            err.exit()
+        })
+    };
+
+    let result = match app.subcommand() {
+        ("list", Some(sub)) => list(&sub, &config).map(|()| 0),
+        ("show", Some(sub)) => show(&sub).map(|()| 0),
+        ("resources", Some(sub)) => resources(&sub, &config).map(|()| 0),
+        ("info", Some(sub)) => info(&sub, &config).map(|()| 0),
+        ("audit", Some(sub)) => audit(&sub),
+        ("install", Some(sub)) => install(&sub, &config),
+        ("uninstall", Some(sub)) => uninstall(&sub, &config),
+        ("stats", Some(_)) => stats(&config).map(|()| 0),
+        ("edit", Some(sub)) => edit(&sub).map(|()| 0),
+        ("absorb", Some(sub)) => absorb(&sub, &config),
+        ("watch", Some(sub)) => watch(&sub),
+        ("sync", Some(sub)) => sync(&sub, &config),
+        ("drift", Some(sub)) => drift(&sub, &config),
+        ("enable", Some(sub)) => set_disabled(&sub, false).map(|()| 0),
+        ("disable", Some(sub)) => set_disabled(&sub, true).map(|()| 0),
+        ("unquarantine", Some(sub)) => unquarantine(&sub).map(|()| 0),
+        ("decisions", Some(_)) => decisions().map(|()| 0),
+        ("forget", Some(sub)) => forget(&sub).map(|()| 0),
+        ("migrate", Some(sub)) => migrate(&sub),
+        ("schema", Some(_)) => schema().map(|()| 0),
+        ("check", Some(sub)) => check(&sub),
+        ("doctor", Some(sub)) => Ok(doctor(&sub, &config)),
+        ("status", Some(sub)) => health::run(&module_dirs(&sub), &open_state(&sub))
+            .map(|()| 0)
+            .map_err(CliError::from),
+        ("is-installed", Some(sub)) => Ok(query(&sub, |module, _| {
+            module.status() == module::Status::Installed
+        })),
+        ("is-clean", Some(sub)) => Ok(query(&sub, |module, state| {
+            module.is_clean(state.links(module.name()))
+        })),
+        ("prune", Some(sub)) => prune(&sub, &config).map(|()| 0),
+        ("retarget", Some(sub)) => retarget(&sub, &config).map(|()| 0),
+        ("gc", Some(sub)) => gc(&sub, &config).map(|()| 0),
+        ("support-bundle", Some(sub)) => support_bundle(&sub).map(|()| 0),
+        ("archive", Some(sub)) => archive(&sub, &config).map(|()| 0),
+        ("unarchive", Some(sub)) => unarchive(&sub).map(|()| 0),
+        ("rename", Some(sub)) => rename(&sub).map(|()| 0),
+        ("scan-home", Some(sub)) => scan_home(&sub, &config).map(|()| 0),
+        ("new", Some(sub)) => new(&sub, &config).map(|()| 0),
+        ("import-stow", Some(sub)) => import_stow(&sub).map(|()| 0),
+        ("import-chezmoi", Some(sub)) => import_chezmoi(&sub).map(|()| 0),
+        ("import-yadm", Some(sub)) => import_yadm(&sub).map(|()| 0),
+        ("init", Some(_)) => wizard::run(build(&default_dir), platform_dir.to_str().unwrap())
+            .map(|()| 0)
+            .context("init")
+            .map_err(CliError::from),
+        ("bootstrap", Some(sub)) => bootstrap(&sub, &config, &default_dir),
+        ("tui", Some(sub)) => tui(&sub, &config).map(|()| 0),
         _ => unreachable!(),
     };
 
-    match err {
-        Err(err) => err.exit(),
-        _ => (),
+    match result {
+        Ok(code) => {
+            runlog::exit(code, None);
+            process::exit(code)
+        }
+        Err(err) => {
+            runlog::exit(err.code, Some(&err.error));
+            err.exit()
+        }
     }
 }
 
-fn initialise(default_dir: &str) -> ArgMatches {
+fn build<'a, 'b>(default_dir: &'a str) -> App<'a, 'b> {
     App::new("modman")
         .version(crate_version!())
         .author("Reeto C. <me@ree.to>")
@@ -71,15 +236,57 @@ fn initialise(default_dir: &str) -> ArgMatches {
                 .short("m")
                 .long("modules-dir")
                 .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_delimiter(platform::PATH_LIST_SEPARATOR)
                 .global(true)
                 .default_value(default_dir)
-                .help("Specify the module directory"),
+                .help("Specify the module directory; Repeat it to source modules from several"),
         ).arg(
             Arg::with_name("verbose")
                 .short("v")
                 .long("verbose")
                 .global(true)
                 .help("Enable verbose output"),
+        ).arg(
+            Arg::with_name("color")
+                .long("color")
+                .takes_value(true)
+                .possible_values(&["auto", "always", "never"])
+                .default_value("auto")
+                .global(true)
+                .help("When to colour output"),
+        ).arg(
+            Arg::with_name("no-color")
+                .long("no-color")
+                .global(true)
+                .help("Disable coloured output, like --color never"),
+        ).arg(
+            Arg::with_name("help-exit-codes")
+                .long("help-exit-codes")
+                .help("Print what each exit code means"),
+        ).arg(
+            Arg::with_name("yes")
+                .short("y")
+                .long("yes")
+                .global(true)
+                .help("Delete files without asking for confirmation first"),
+        ).arg(
+            Arg::with_name("target-dir")
+                .long("target-dir")
+                .takes_value(true)
+                .global(true)
+                .help("Install into this directory instead of the home directory (also MODMAN_TARGET)"),
+        ).arg(
+            Arg::with_name("allow-remote-source")
+                .long("allow-remote-source")
+                .global(true)
+                .help("Don't warn about a modules directory on a network or removable filesystem"),
+        ).arg(
+            Arg::with_name("read-only")
+                .long("read-only")
+                .global(true)
+                .help("Only allow commands that do not modify the system (also MODMAN_READONLY=1)"),
         ).subcommand(
             SubCommand::with_name("list")
                 .about("List installable modules")
@@ -87,6 +294,41 @@ fn initialise(default_dir: &str) -> ArgMatches {
                     Arg::with_name("verify")
                         .long("verify")
                         .help("List all modules with verification status"),
+                ).arg(
+                    Arg::with_name("archived")
+                        .long("archived")
+                        .conflicts_with("verify")
+                        .help("List archived modules and why they were archived"),
+                ).arg(
+                    Arg::with_name("filter")
+                        .long("filter")
+                        .takes_value(true)
+                        .help("Only list modules whose name or description matches this regex"),
+                ).arg(
+                    Arg::with_name("stats")
+                        .long("stats")
+                        .conflicts_with_all(&["verify", "archived"])
+                        .help("List resource counts, sizes, last install times and verification"),
+                ).arg(
+                    Arg::with_name("installed")
+                        .long("installed")
+                        .conflicts_with("not-installed")
+                        .help("Only list (partially) installed modules"),
+                ).arg(
+                    Arg::with_name("not-installed")
+                        .long("not-installed")
+                        .help("Only list modules that are not installed"),
+                ).arg(
+                    Arg::with_name("print0")
+                        .short("0")
+                        .long("print0")
+                        .conflicts_with_all(&["verify", "stats"])
+                        .help("Only print names, each ended by a NUL character, for xargs -0"),
+                ).arg(
+                    Arg::with_name("tree")
+                        .long("tree")
+                        .conflicts_with_all(&["verify", "archived", "stats", "print0"])
+                        .help("List each module's resources beneath it, coloured by status"),
                 ),
         ).subcommand(
             SubCommand::with_name("install")
@@ -96,11 +338,42 @@ fn initialise(default_dir: &str) -> ArgMatches {
                         .short("a")
                         .long("all")
                         .help("Install all modules"),
+                ).arg(
+                    Arg::with_name("require-clean")
+                        .long("require-clean")
+                        .help("Refuse to go on if a modules directory has uncommitted changes or is behind its upstream"),
+                ).arg(
+                    Arg::with_name("max-duration")
+                        .long("max-duration")
+                        .takes_value(true)
+                        .validator(|val| {
+                            parse_duration(&val)
+                                .map(|_| ())
+                                .ok_or_else(|| "expected a duration like 90s, 2m or 1h".to_owned())
+                        })
+                        .help("Stop starting new modules after this long"),
                 ).arg(
                     Arg::with_name("force")
                         .short("f")
                         .long("force")
                         .help("Delete existing system files"),
+                ).arg(
+                    Arg::with_name("install-packages")
+                        .long("install-packages")
+                        .help("Install missing packages with pacman"),
+                ).arg(
+                    Arg::with_name("system")
+                        .long("system")
+                        .help("Link resources in system locations with sudo without asking"),
+                ).arg(
+                    Arg::with_name("trace")
+                        .long("trace")
+                        .conflicts_with("all")
+                        .help("Show every decision made about the modules and why, e.g. for bug reports"),
+                ).arg(
+                    Arg::with_name("rerun-init")
+                        .long("rerun-init")
+                        .help("Run init scripts even if they are marked init_once and already ran"),
                 ).arg(
                     Arg::with_name("EXCLUDE")
                         .short("e")
@@ -123,11 +396,33 @@ fn initialise(default_dir: &str) -> ArgMatches {
                         .short("a")
                         .long("all")
                         .help("Uninstall all modules"),
+                ).arg(
+                    Arg::with_name("require-clean")
+                        .long("require-clean")
+                        .help("Refuse to go on if a modules directory has uncommitted changes or is behind its upstream"),
+                ).arg(
+                    Arg::with_name("max-duration")
+                        .long("max-duration")
+                        .takes_value(true)
+                        .validator(|val| {
+                            parse_duration(&val)
+                                .map(|_| ())
+                                .ok_or_else(|| "expected a duration like 90s, 2m or 1h".to_owned())
+                        })
+                        .help("Stop starting new modules after this long"),
                 ).arg(
                     Arg::with_name("force")
                         .short("f")
                         .long("force")
                         .help("Delete existing system files"),
+                ).arg(
+                    Arg::with_name("remove-artifacts")
+                        .long("remove-artifacts")
+                        .help("Also delete the files the modules' scripts generated"),
+                ).arg(
+                    Arg::with_name("system")
+                        .long("system")
+                        .help("Remove links in system locations with sudo without asking"),
                 ).arg(
                     Arg::with_name("EXCLUDE")
                         .short("e")
@@ -142,94 +437,2438 @@ fn initialise(default_dir: &str) -> ArgMatches {
                         .multiple(true)
                         .required_unless_one(&["all", "EXCLUDE"]),
                 ),
-        ).get_matches()
+        ).subcommand(SubCommand::with_name("stats").about("Show local usage statistics"))
+        .subcommand(
+            SubCommand::with_name("edit")
+                .about("Edit a module config or resource and verify the module")
+                .arg(Arg::with_name("MODULE").required(true))
+                .arg(Arg::with_name("RESOURCE").help("Resource to edit instead of the config")),
+        ).subcommand(
+            SubCommand::with_name("absorb")
+                .about("Copy changes made to installed copies back into their module")
+                .arg(Arg::with_name("MODULE").required(true))
+                .arg(
+                    Arg::with_name("RESOURCE")
+                        .help("Resource to absorb, instead of all of the module's resources"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("watch")
+                .about("Update links of installed modules whenever their configs change")
+                .arg(
+                    Arg::with_name("MODULES")
+                        .takes_value(true)
+                        .multiple(true)
+                        .help("Modules to watch, instead of all installed modules"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("sync")
+                .about("Repair, add and remove links of installed modules to match their configs")
+                .arg(
+                    Arg::with_name("MODULES")
+                        .takes_value(true)
+                        .multiple(true)
+                        .help("Modules to sync, instead of all installed modules"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("drift")
+                .about("List decrypted copies that were changed since they were installed")
+                .arg(
+                    Arg::with_name("pull")
+                        .long("pull")
+                        .conflicts_with("overwrite")
+                        .help("Encrypt the changed copies back into their modules"),
+                ).arg(
+                    Arg::with_name("overwrite")
+                        .long("overwrite")
+                        .help("Replace the changed copies with freshly decrypted ones"),
+                ).arg(
+                    Arg::with_name("MODULES")
+                        .takes_value(true)
+                        .multiple(true)
+                        .help("Modules to check, instead of all installed modules"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("enable")
+                .about("Let bulk operations include disabled modules again")
+                .arg(
+                    Arg::with_name("MODULES")
+                        .takes_value(true)
+                        .multiple(true)
+                        .required(true),
+                ),
+        ).subcommand(
+            SubCommand::with_name("disable")
+                .about("Leave modules out of bulk operations on this machine")
+                .arg(
+                    Arg::with_name("MODULES")
+                        .takes_value(true)
+                        .multiple(true)
+                        .required(true),
+                ),
+        ).subcommand(
+            SubCommand::with_name("unquarantine")
+                .about("Let bulk operations include quarantined modules again")
+                .arg(
+                    Arg::with_name("MODULES")
+                        .takes_value(true)
+                        .multiple(true)
+                        .required(true),
+                ),
+        ).subcommand(
+            SubCommand::with_name("decisions")
+                .about("List the remembered decisions about files in the way of links"),
+        ).subcommand(
+            SubCommand::with_name("forget")
+                .about("Forget the decisions about files in the way of links, to be asked again")
+                .arg(
+                    Arg::with_name("FILES")
+                        .takes_value(true)
+                        .multiple(true)
+                        .required(true),
+                ),
+        ).subcommand(
+            SubCommand::with_name("migrate")
+                .about("Rewrite module configs to the newest schema, keeping comments")
+                .arg(
+                    Arg::with_name("MODULES")
+                        .takes_value(true)
+                        .multiple(true)
+                        .help("Modules to migrate, instead of all modules"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("schema").about("Print the JSON Schema of module configs"),
+        ).subcommand(
+            SubCommand::with_name("check")
+                .about("Check module configs, pointing at the line of each problem")
+                .arg(
+                    Arg::with_name("MODULES")
+                        .takes_value(true)
+                        .multiple(true)
+                        .required(true)
+                        .help("Module names, module directories or config files"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("gc")
+                .about("Delete stale script logs and forget deleted modules")
+                .arg(
+                    Arg::with_name("dry-run")
+                        .short("n")
+                        .long("dry-run")
+                        .help("Only report what would be cleaned up"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("prune")
+                .about("Remove links into the modules directory that no module installs")
+                .arg(
+                    Arg::with_name("dry-run")
+                        .short("n")
+                        .long("dry-run")
+                        .help("Only report the links that would be removed"),
+                ).arg(
+                    Arg::with_name("print0")
+                        .short("0")
+                        .long("print0")
+                        .requires("dry-run")
+                        .help("Only print paths, each ended by a NUL character, for xargs -0"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("retarget")
+                .about("Point links into where the modules directory used to be at its new location")
+                .arg(
+                    Arg::with_name("OLD_DIR")
+                        .required(true)
+                        .help("Where the modules directory was before it was moved"),
+                ).arg(
+                    Arg::with_name("dry-run")
+                        .short("n")
+                        .long("dry-run")
+                        .help("Only report the links that would be changed"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("is-installed")
+                .about("Exit with 0 if a module is fully installed, 1 otherwise, printing nothing")
+                .arg(Arg::with_name("MODULE").required(true)),
+        ).subcommand(
+            SubCommand::with_name("is-clean")
+                .about("Exit with 0 if syncing a module would change nothing, 1 otherwise, printing nothing")
+                .arg(Arg::with_name("MODULE").required(true)),
+        ).subcommand(
+            SubCommand::with_name("status")
+                .about("Score the health of each installed module"),
+        ).subcommand(
+            SubCommand::with_name("doctor")
+                .about("Check the environment and suggest fixes for problems"),
+        ).subcommand(
+            SubCommand::with_name("support-bundle")
+                .about("Collect redacted config, state and environment info for a bug report")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .default_value(".")
+                        .help("Directory to write the bundle to"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("show")
+                .about("Show a module's details, provenance and resources")
+                .arg(Arg::with_name("MODULE").required(true))
+                .arg(
+                    Arg::with_name("artifacts")
+                        .long("artifacts")
+                        .help("Also list the files the module's scripts generate"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("tui")
+                .about("Browse, install and uninstall modules interactively"),
+        ).subcommand(
+            SubCommand::with_name("info")
+                .about("Show a module's documentation, resources, packages and hooks")
+                .arg(
+                    Arg::with_name("config")
+                        .long("config")
+                        .help("Print the config the module is installed with on this machine instead"),
+                ).arg(Arg::with_name("MODULE").required(true)),
+        ).subcommand(
+            SubCommand::with_name("resources")
+                .about("List each resource's source, target, mode and status")
+                .arg(
+                    Arg::with_name("all")
+                        .short("a")
+                        .long("all")
+                        .conflicts_with("MODULES")
+                        .help("List the resources of all modules"),
+                ).arg(
+                    Arg::with_name("tree")
+                        .long("tree")
+                        .help("List resources beneath their module instead of as a table"),
+                ).arg(
+                    Arg::with_name("MODULES")
+                        .takes_value(true)
+                        .multiple(true)
+                        .required_unless("all"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("audit")
+                .about("List the license, author and source of every module")
+                .arg(
+                    Arg::with_name("strict")
+                        .long("strict")
+                        .help("Fail if any module lacks a license or source URL"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("archive")
+                .about("Uninstall a module and move it to the archive")
+                .arg(Arg::with_name("MODULE").required(true))
+                .arg(
+                    Arg::with_name("reason")
+                        .short("r")
+                        .long("reason")
+                        .takes_value(true)
+                        .help("Why the module was archived, shown by list --archived"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("rename")
+                .about("Rename a module, keeping it installed")
+                .arg(Arg::with_name("OLD").required(true))
+                .arg(Arg::with_name("NEW").required(true)),
+        ).subcommand(
+            SubCommand::with_name("unarchive")
+                .about("Restore an archived module")
+                .arg(Arg::with_name("MODULE").required(true)),
+        ).subcommand(
+            SubCommand::with_name("scan-home")
+                .about("Move config of well-known programs in the home directory into new modules"),
+        ).subcommand(
+            SubCommand::with_name("new")
+                .about("Create a module")
+                .arg(Arg::with_name("MODULE").required(true))
+                .arg(
+                    Arg::with_name("for")
+                        .long("for")
+                        .takes_value(true)
+                        .help("Catalog program to take targets and reload command from"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("import-stow")
+                .about("Create a module from a GNU Stow package, copying its files")
+                .arg(Arg::with_name("PACKAGE").required(true))
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .takes_value(true)
+                        .help("Name of the module, instead of the package's"),
+                ).arg(
+                    Arg::with_name("dotfiles")
+                        .long("dotfiles")
+                        .help("Files starting with dot- are dotfiles, as with stow --dotfiles"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("import-chezmoi")
+                .about("Create modules from a chezmoi source directory, copying its files")
+                .arg(
+                    Arg::with_name("SOURCE")
+                        .help("The source directory, instead of ~/.local/share/chezmoi"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("import-yadm")
+                .about("Create modules from the files a yadm repository tracks, copying them")
+                .arg(
+                    Arg::with_name("REPO")
+                        .help("The repository, instead of ~/.local/share/yadm/repo.git"),
+                ),
+        ).subcommand(SubCommand::with_name("init").about(
+            "Set up the global config, modules directory and shell completions interactively",
+        )).subcommand(
+            SubCommand::with_name("bootstrap")
+                .about("Clone a dotfiles repository into the modules directory and install it")
+                .arg(
+                    Arg::with_name("verify")
+                        .long("verify")
+                        .help("Install nothing unless every module verifies"),
+                ).arg(Arg::with_name("URL").required(true)),
+        )
+}
+
+fn read_only(app: &ArgMatches) -> bool {
+    app.is_present("read-only") || env::var("MODMAN_READONLY").is_ok_and(|val| val == "1")
+}
+
+/// Abort unless the user agrees to (or `--yes` allows) deleting `paths`
+fn confirm_deletion(app: &ArgMatches, paths: &[PathBuf]) -> Result<(), CliError> {
+    let confirmed = output::confirm_deletion(paths, app.is_present("yes")).context("confirm")?;
+    if confirmed {
+        Ok(())
+    } else {
+        Err(clap::Error::with_description(
+            "Aborted; Nothing was deleted",
+            clap::ErrorKind::InvalidValue,
+        )
+        .into())
+    }
+}
+
+fn module_dirs(app: &clap::ArgMatches) -> ModuleDirs {
+    ModuleDirs::new(app.values_of("modules-dir").unwrap().map(PathBuf::from))
+}
+
+/// A failed command, with the code to exit with
+struct CliError {
+    error: anyhow::Error,
+    code: i32,
 }
 
-fn wrap_module_err(err: module::ModuleError) -> clap::Error {
-    clap::Error::with_description(&err.to_string(), clap::ErrorKind::InvalidValue)
+impl CliError {
+    fn exit(self) -> ! {
+        let message = match self.error.downcast::<clap::Error>() {
+            Ok(err) => err.message,
+            Err(err) => {
+                clap::Error::with_description(&err.to_string(), clap::ErrorKind::InvalidValue)
+                    .message
+            }
+        };
+        eprintln!("{}", message);
+        process::exit(self.code)
+    }
+}
+
+// Module errors exit with the code of their kind, anything else with 1
+impl<E: Into<anyhow::Error>> From<E> for CliError {
+    fn from(error: E) -> CliError {
+        let error = error.into();
+        let code = error
+            .downcast_ref::<ModuleError>()
+            .map_or(1, |err| err.kind().exit_code());
+        CliError { error, code }
+    }
+}
+
+fn print_exit_codes() {
+    println!("Exit codes:");
+    println!("{:>4}  Success", 0);
+    println!(
+        "{:>4}  Invalid arguments, config or state; Nothing was changed",
+        1
+    );
+    println!("{:>4}  Some modules failed", EXIT_PARTIAL);
+    println!(
+        "{:>4}  All modules failed, with different kinds of error",
+        EXIT_FAILED
+    );
+    for kind in module::ErrorKind::all() {
+        println!("{:>4}  {}", kind.exit_code(), kind.description());
+    }
 }
 
-fn list(app: &clap::ArgMatches) -> Result<(), clap::Error> {
-    match Module::list(app.value_of("modules-dir").unwrap()) {
+fn list(app: &clap::ArgMatches, config: &Config) -> Result<(), CliError> {
+    let module_dirs = module_dirs(app);
+    if app.is_present("archived") && app.is_present("print0") {
+        for (name, _) in module_dirs.iter().flat_map(Module::list_archived) {
+            output::print0(name);
+        }
+        return Ok(());
+    }
+    if app.is_present("archived") {
+        let rows: Vec<(String, String)> = module_dirs
+            .iter()
+            .flat_map(Module::list_archived)
+            .map(|(name, reason)| (name, paint(Style::Dim, reason)))
+            .collect();
+        output::columns(&rows);
+        return Ok(());
+    }
+    // Patterns that aren't valid regexes are matched as plain substrings
+    let filter = app.value_of("filter").map(|pattern| {
+        Regex::new(pattern).unwrap_or_else(|_| Regex::new(&regex::escape(pattern)).unwrap())
+    });
+    let state = open_state(app);
+    // With several module directories, each module is labelled with the one it comes from
+    let several = module_dirs.iter().nth(1).is_some();
+    let label = |module: &Module, text: String| {
+        let text = match module_dirs.root_of(module.path()) {
+            Some(root) if several => format!("{} {}", text, paint(Style::Dim, root.display())),
+            _ => text,
+        };
+        if state.is_disabled(module.name()) {
+            paint(Style::Dim, format!("{} (disabled)", text))
+        } else {
+            text
+        }
+    };
+    match module_dirs.list() {
         Ok(modules) => {
-            if app.is_present("verify") {
-                for module in modules {
-                    match module {
-                        Ok(module) => println!("{} - OK", module.name()),
-                        Err(err) => println!{"{}", err},
-                    }
-                }
-            } else if app.is_present("verbose") {
-                for module in modules {
-                    match module {
-                        Ok(module) => println!("{}", module),
-                        _ => continue,
-                    }
+            let modules: Vec<_> = modules
+                .into_iter()
+                .map(|module| {
+                    module.map(|mut module| {
+                        recall(&mut module, &state);
+                        module
+                    })
+                })
+                .filter(|module| list_filter(app, filter.as_ref(), module))
+                .collect();
+            if app.is_present("stats") {
+                list_stats(&state, &modules);
+            } else if app.is_present("verify") {
+                let rows: Vec<(String, String)> = modules
+                    .into_iter()
+                    .flat_map(|module| match module {
+                        Ok(module) => vec![(module.name().to_owned(), paint(Style::Ok, "OK"))],
+                        Err(err) => verify_rows(&err),
+                    })
+                    .collect();
+                output::columns(&rows);
+            } else if app.is_present("print0") {
+                for module in modules.into_iter().filter_map(Result::ok) {
+                    output::print0(module.name());
                 }
+            } else if app.is_present("tree") {
+                let nodes: Vec<(String, Vec<String>)> = modules
+                    .into_iter()
+                    .filter_map(Result::ok)
+                    .map(|mut module| {
+                        configure(&mut module, config);
+                        let status = status_label(module.status(), None);
+                        let node =
+                            format!("{}  {}", label(&module, module.name().to_owned()), status);
+                        (node, resource_tree(&module))
+                    })
+                    .collect();
+                output::tree(&nodes);
             } else {
-                for module in modules {
-                    match module {
-                        Ok(module) => println!("{}", module.name()),
-                        _ => continue,
-                    }
-                }
+                let rows: Vec<(String, String)> = modules
+                    .into_iter()
+                    .filter_map(Result::ok)
+                    .map(|module| {
+                        let description =
+                            module.description().filter(|_| app.is_present("verbose"));
+                        let status = status_label(module.status(), description);
+                        (label(&module, module.name().to_owned()), status)
+                    })
+                    .collect();
+                output::columns(&rows);
             }
             Ok(())
         }
-        Err(err @ ModuleError::Directory) => return Err(wrap_module_err(err)),
+        Err(err @ ModuleError::Directory) => return Err(CliError::from(err)),
         _ => unreachable!(),
     }
 }
 
-fn resolve(app: &clap::ArgMatches) -> Result<std::vec::Vec<module::Module>, module::ModuleError> {
-    // if all, then list otherwise build up
-    let module_dir = Path::new(app.value_of("modules-dir").unwrap());
-    if app.is_present("all") {
-        let excluded_module_names: HashSet<&str> = match app.values_of("EXCLUDE") {
-            Some(vals) => vals.collect(),
-            None => HashSet::new(),
-        };
+fn show(app: &clap::ArgMatches) -> Result<(), CliError> {
+    let mut module = Module::new(module_dirs(app).path(app.value_of("MODULE").unwrap()))?;
+    recall(&mut module, &open_state(app));
+    let unknown = || paint(Style::Dim, "unknown");
+    let mut rows = vec![("Name".to_owned(), module.name().to_owned())];
+    if let Some(description) = module.description() {
+        rows.push(("Description".to_owned(), description.to_owned()));
+    }
+    let provenance = [
+        ("License", module.license()),
+        ("Author", module.author()),
+        ("Source", module.source_url()),
+    ];
+    for &(key, value) in &provenance {
+        rows.push((key.to_owned(), value.map_or_else(unknown, str::to_owned)));
+    }
+    rows.push(("Status".to_owned(), format!("{:?}", module.status())));
+    output::columns(&rows);
 
-        return Ok(Module::list(module_dir)?
-            .into_iter()
-            .filter_map(|m| m.ok())
-            .filter(|m| !excluded_module_names.contains(m.name()))
-            .collect());
-    } else {
-        let module_names = app.values_of("MODULES").unwrap();
-        let mut modules: std::vec::Vec<module::Module> = match module_names.size_hint() {
-            (_, Some(n)) => Vec::with_capacity(n),
-            _ => Vec::new(),
-        };
-        for module_name in module_names {
-            match Module::new(module_dir.join(module_name)) {
-                Ok(module) => modules.push(module),
-                Err(err) => return Err(err),
+    println!("Resources:");
+    let mut rows: Vec<(String, String)> = module
+        .resources()
+        .into_iter()
+        .map(|(resource, target)| (format!("  {}", resource), target.display().to_string()))
+        .collect();
+    rows.extend(module.skipped().iter().map(|(resource, app)| {
+        let reason = format!("(skipped; {} isn't installed)", app);
+        (format!("  {}", resource), paint(Style::Dim, reason))
+    }));
+    output::columns(&rows);
+
+    if app.is_present("artifacts") {
+        println!("Artifacts:");
+        for artifact in module.artifacts() {
+            if artifact.exists() {
+                println!("  {}", artifact.display());
+            } else {
+                println!(
+                    "  {} {}",
+                    artifact.display(),
+                    paint(Style::Dim, "(missing)")
+                );
             }
         }
-
-        return Ok(modules);
     }
+    Ok(())
 }
 
-fn install(app: &clap::ArgMatches) -> Result<(), clap::Error> {
-    let modules = resolve(app).map_err(|err| wrap_module_err(err))?;
-    for module in modules {
-        match module.install(app.is_present("force")) {
-            Ok(()) => println!("Module {}: Installed", module.name()),
-            Err(err) => println!("{}", err.to_string()),
+fn info(app: &clap::ArgMatches, config: &Config) -> Result<(), CliError> {
+    let mut module = Module::new(module_dirs(app).path(app.value_of("MODULE").unwrap()))?;
+    configure(&mut module, config);
+    recall(&mut module, &open_state(app));
+    if app.is_present("config") {
+        print!("{}", module.effective_config(&open_catalog()));
+        for (resource, condition) in module.left_out() {
+            println!(
+                "# {} is left out, as its {} doesn't hold",
+                resource, condition
+            );
+        }
+        for (resource, program) in module.skipped() {
+            println!("# {} is skipped, as {} isn't installed", resource, program);
         }
+        return Ok(());
+    }
+    println!("{}", module);
+    if let Some(docs) = module.docs() {
+        let text = fs::read_to_string(&docs).context(module.name())?;
+        println!("\n{}", text.trim_end());
+    }
+
+    println!("\nResources:");
+    let rows: Vec<(String, String)> = module
+        .resource_states()
+        .into_iter()
+        .map(|resource| {
+            let mode = paint(Style::Dim, format!("({})", resource.mode));
+            (
+                format!("  {}", resource.resource),
+                format!("{} {}", resource.target.display(), mode),
+            )
+        })
+        .collect();
+    output::columns(&rows);
+    if !module.packages().is_empty() {
+        println!("Packages: {}", module.packages().join(", "));
+    }
+    let hooks = module.hooks(&open_catalog());
+    if !hooks.is_empty() {
+        println!("Hooks:");
+        let rows: Vec<(String, String)> = hooks
+            .into_iter()
+            .map(|(hook, description)| (format!("  {}", hook), description))
+            .collect();
+        output::columns(&rows);
     }
     Ok(())
 }
 
-fn uninstall(app: &clap::ArgMatches) -> Result<(), clap::Error> {
-    let modules = resolve(app).map_err(|err| wrap_module_err(err))?;
-    for module in modules {
-        match module.uninstall(app.is_present("force")) {
-            Ok(()) => println!("Module {}: Uninstalled", module.name()),
-            Err(err) => println!("{}", err.to_string()),
+fn resources(app: &clap::ArgMatches, config: &Config) -> Result<(), CliError> {
+    let module_dirs = module_dirs(app);
+    let mut modules = if app.is_present("all") {
+        module_dirs
+            .list()?
+            .into_iter()
+            .filter_map(|module| module.map_err(|err| warn!("{}", err)).ok())
+            .collect()
+    } else {
+        app.values_of("MODULES")
+            .unwrap()
+            .map(|name| Module::new(module_dirs.path(name)))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    let state = open_state(app);
+    if app.is_present("tree") {
+        let nodes: Vec<(String, Vec<String>)> = modules
+            .iter_mut()
+            .map(|module| {
+                configure(module, config);
+                recall(module, &state);
+                let status = status_label(module.status(), None);
+                (
+                    format!("{}  {}", module.name(), status),
+                    resource_tree(module),
+                )
+            })
+            .collect();
+        output::tree(&nodes);
+        return Ok(());
+    }
+    let mut rows = Vec::new();
+    for module in &mut modules {
+        configure(module, config);
+        recall(module, &state);
+        for resource in module.resource_states() {
+            let status = match resource.status {
+                ResourceStatus::Installed => paint(Style::Ok, &resource.status),
+                ResourceStatus::Missing => paint(Style::Dim, &resource.status),
+                _ => paint(Style::Warn, &resource.status),
+            };
+            rows.push(vec![
+                module.name().to_owned(),
+                status,
+                resource.mode.to_string(),
+                resource.source.display().to_string(),
+                resource.target.display().to_string(),
+            ]);
+        }
+        for (resource, program) in module.skipped() {
+            let reason = format!("skipped; {} isn't installed", program);
+            rows.push(vec![
+                module.name().to_owned(),
+                paint(Style::Dim, reason),
+                String::new(),
+                module.path().join(resource).display().to_string(),
+                String::new(),
+            ]);
         }
     }
+    output::table(&["MODULE", "STATUS", "MODE", "SOURCE", "TARGET"], &rows);
+    Ok(())
+}
+
+// A line for each resource of `module` in a tree, coloured by its status, which is also spelled
+// out unless the resource is installed
+fn resource_tree(module: &Module) -> Vec<String> {
+    let mut lines: Vec<String> = module
+        .resource_states()
+        .into_iter()
+        .map(|resource| {
+            let line = format!("{} -> {}", resource.resource, resource.target.display());
+            match resource.status {
+                ResourceStatus::Installed => paint(Style::Ok, line),
+                ResourceStatus::Missing => paint(Style::Dim, format!("{} (missing)", line)),
+                ref status => paint(Style::Warn, format!("{} ({})", line, status)),
+            }
+        })
+        .collect();
+    lines.extend(module.skipped().iter().map(|(resource, program)| {
+        paint(
+            Style::Dim,
+            format!("{} (skipped; {} isn't installed)", resource, program),
+        )
+    }));
+    lines
+}
+
+fn audit(app: &clap::ArgMatches) -> Result<i32, CliError> {
+    let modules: Vec<Module> = module_dirs(app)
+        .list()?
+        .into_iter()
+        .filter_map(|module| module.map_err(|err| warn!("{}", err)).ok())
+        .collect();
+
+    // Pad before painting, as escape codes have no width
+    let license_width = modules
+        .iter()
+        .map(|module| module.license().unwrap_or("unknown").chars().count())
+        .max()
+        .unwrap_or(0);
+    let author_width = modules
+        .iter()
+        .map(|module| module.author().unwrap_or("unknown").chars().count())
+        .max()
+        .unwrap_or(0);
+    let field = |value: Option<&str>, width: usize| match value {
+        Some(value) => format!("{:width$}", value, width = width),
+        None => paint(Style::Warn, format!("{:width$}", "unknown", width = width)),
+    };
+    // Generated files on the system are listed under the module they came from
+    let rows: Vec<(String, String)> = modules
+        .iter()
+        .flat_map(|module| {
+            let provenance = format!(
+                "{}  {}  {}",
+                field(module.license(), license_width),
+                field(module.author(), author_width),
+                field(module.source_url(), 0)
+            );
+            let artifacts = module
+                .artifacts()
+                .into_iter()
+                .filter(|artifact| artifact.exists())
+                .map(|artifact| {
+                    let row = format!("  artifact {}", artifact.display());
+                    (String::new(), paint(Style::Dim, row))
+                });
+            iter::once((module.name().to_owned(), provenance)).chain(artifacts)
+        })
+        .collect();
+    output::columns(&rows);
+
+    let incomplete = modules
+        .iter()
+        .filter(|module| module.license().is_none() || module.source_url().is_none())
+        .count();
+    if incomplete == 0 {
+        return Ok(0);
+    }
+    println!("{} modules lack a license or source URL", incomplete);
+    Ok(if app.is_present("strict") { 1 } else { 0 })
+}
+
+fn list_stats(state: &State, modules: &[Result<Module, ModuleError>]) {
+    let rows: Vec<Vec<String>> = modules
+        .iter()
+        .filter_map(|module| {
+            let (name, resources, size, verified) = match *module {
+                Ok(ref module) => {
+                    let size = module
+                        .resources()
+                        .iter()
+                        .map(|&(resource, _)| disk_usage(&module.path().join(resource)))
+                        .sum();
+                    (
+                        module.name(),
+                        module.resources().len().to_string(),
+                        format_size(size),
+                        paint(Style::Ok, "OK"),
+                    )
+                }
+                Err(ref err) => (
+                    err.module()?,
+                    "-".to_owned(),
+                    "-".to_owned(),
+                    paint(Style::Fail, "FAIL"),
+                ),
+            };
+            let installed = match state.installed_at(name) {
+                Some(time) => format_age(time),
+                None => "never".to_owned(),
+            };
+            Some(vec![name.to_owned(), resources, size, installed, verified])
+        })
+        .collect();
+    output::table(
+        &["MODULE", "RESOURCES", "SIZE", "INSTALLED", "VERIFIED"],
+        &rows,
+    );
+}
+
+// Total size of the files in a directory, or of a file
+fn disk_usage(path: &Path) -> u64 {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return 0,
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| disk_usage(&entry.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+// How much of a module is installed, followed by the description if there is one
+fn status_label(status: module::Status, description: Option<&str>) -> String {
+    let (text, style) = match status {
+        module::Status::Installed => ("installed", Style::Ok),
+        module::Status::Partial => ("partial", Style::Warn),
+        module::Status::NotInstalled => ("not installed", Style::Dim),
+    };
+    match description {
+        Some(description) => format!("{}  {}", paint(style, format!("{:13}", text)), description),
+        None => paint(style, text),
+    }
+}
+
+// How long ago a time was, roughly
+fn format_age(time: SystemTime) -> String {
+    let secs = time.elapsed().map(|age| age.as_secs()).unwrap_or(0);
+    match secs {
+        0..=59 => "just now".to_owned(),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+fn list_filter(
+    app: &clap::ArgMatches,
+    filter: Option<&Regex>,
+    module: &Result<Module, ModuleError>,
+) -> bool {
+    let matches = |text: &str| filter.is_none_or(|filter| filter.is_match(text));
+    match *module {
+        Ok(ref module) => {
+            let status_matches = match module.status() {
+                module::Status::NotInstalled => !app.is_present("installed"),
+                _ => !app.is_present("not-installed"),
+            };
+            status_matches && (matches(module.name()) || module.description().is_some_and(matches))
+        }
+        // The install status of broken modules is unknown
+        Err(ref err) => {
+            err.module().is_some_and(matches)
+                && !app.is_present("installed")
+                && !app.is_present("not-installed")
+        }
+    }
+}
+
+// One row per issue, with the module name only on the first
+fn verify_rows(err: &ModuleError) -> Vec<(String, String)> {
+    if let ModuleError::Invalid(ref name, ref issues) = *err {
+        return issues
+            .0
+            .iter()
+            .enumerate()
+            .map(|(i, issue)| {
+                (
+                    if i == 0 { name.clone() } else { String::new() },
+                    format!("{}  {}", paint(Style::Fail, "FAIL"), issue),
+                )
+            })
+            .collect();
+    }
+
+    let message = err.to_string();
+    vec![match err.module() {
+        Some(name) => {
+            let prefix = format!("Module {}: ", name);
+            let reason = message.trim_start_matches(prefix.as_str());
+            (
+                name.to_owned(),
+                format!("{}  {}", paint(Style::Fail, "FAIL"), reason),
+            )
+        }
+        None => (
+            String::new(),
+            format!("{}  {}", paint(Style::Fail, "FAIL"), message),
+        ),
+    }]
+}
+
+fn resolve(
+    app: &clap::ArgMatches,
+    config: &Config,
+    state: &mut State,
+) -> Result<std::vec::Vec<module::Module>, module::ModuleError> {
+    // if all, then list otherwise build up
+    let module_dirs = module_dirs(app);
+    if app.is_present("all") {
+        let excluded_module_names: HashSet<&str> = match app.values_of("EXCLUDE") {
+            Some(vals) => vals.collect(),
+            None => HashSet::new(),
+        };
+
+        let mut modules = Vec::new();
+        for module in module_dirs.list()? {
+            match module {
+                Ok(ref m) if excluded_module_names.contains(m.name()) => continue,
+                Ok(ref m) if state.is_disabled(m.name()) => {
+                    info!("Module {}: Disabled, skipping", m.name());
+                }
+                Ok(ref m) if state.is_quarantined(m.name()) => {
+                    warn!(
+                        "Module {}: Quarantined, skipping; Run `modman unquarantine {}` once fixed",
+                        m.name(),
+                        m.name()
+                    );
+                }
+                Ok(m) => modules.push(m),
+                Err(err) => match err.module() {
+                    Some(name) if !excluded_module_names.contains(name) => {
+                        record_failure(config, state, name, &err)
+                    }
+                    _ => continue,
+                },
+            }
+        }
+        // Start with the modules a time-limited run did not get to last time
+        modules.sort_by_key(|m| !state.pending.iter().any(|name| name == m.name()));
+        return Ok(modules);
+    } else {
+        let module_names = read_module_names(app).context("stdin")?.unwrap();
+        let mut modules: std::vec::Vec<module::Module> = Vec::with_capacity(module_names.len());
+        for module_name in &module_names {
+            match Module::new(module_dirs.path(module_name)) {
+                Ok(module) => modules.push(module),
+                Err(err) => {
+                    record_failure(config, state, module_name, &err);
+                    return Err(err);
+                }
+            }
+        }
+
+        return Ok(modules);
+    }
+}
+
+/// Module names given on the command line. `-` reads more from stdin, one per line, taking the
+/// first word of each so the output of `modman list` can be piped in.
+fn read_module_names(app: &clap::ArgMatches) -> io::Result<Option<Vec<String>>> {
+    let args = match app.values_of("MODULES") {
+        Some(args) => args,
+        None => return Ok(None),
+    };
+    let mut names = Vec::new();
+    for arg in args {
+        if arg != "-" {
+            names.push(arg.to_owned());
+            continue;
+        }
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            names.extend(line?.split_whitespace().next().map(str::to_owned));
+        }
+    }
+    Ok(Some(names))
+}
+
+fn module_names(app: &clap::ArgMatches) -> Result<Option<Vec<String>>, CliError> {
+    read_module_names(app)
+        .context("stdin")
+        .map_err(CliError::from)
+}
+
+fn exit_code<T>(results: &[(T, Result<(), ModuleError>)]) -> i32 {
+    let kinds = results
+        .iter()
+        .filter_map(|result| result.1.as_ref().err())
+        .map(ModuleError::kind)
+        .collect::<Vec<_>>();
+    if kinds.is_empty() {
+        0
+    } else if kinds.len() < results.len() {
+        EXIT_PARTIAL
+    } else if kinds.iter().all(|&kind| kind == kinds[0]) {
+        kinds[0].exit_code()
+    } else {
+        EXIT_FAILED
+    }
+}
+
+fn open_stats(config: &Config) -> Option<Stats> {
+    if !config.stats {
+        return None;
+    }
+    match Stats::load() {
+        Ok(stats) => Some(stats),
+        Err(err) => {
+            warn!("{}", err);
+            None
+        }
+    }
+}
+
+fn close_stats(stats: Option<Stats>) {
+    if let Some(stats) = stats {
+        if let Err(err) = stats.save() {
+            warn!("{}", err);
+        }
+    }
+}
+
+/// Cancel `stop` once `max_duration` has passed since `start`, so no further modules are started
+fn check_budget(max_duration: Option<Duration>, start: Instant, stop: &Cancel) {
+    if max_duration.is_some_and(|max_duration| start.elapsed() >= max_duration) {
+        stop.cancel();
+    }
+}
+
+/// Remember the modules a time-limited run stopped before, for the next run to start with
+fn record_pending(
+    app: &clap::ArgMatches,
+    state: &mut State,
+    modules: &[Module],
+    results: &[(&Module, Result<(), ModuleError>)],
+) {
+    if !app.is_present("max-duration") {
+        return;
+    }
+    state.pending = modules[results.len()..]
+        .iter()
+        .map(|module| module.name().to_owned())
+        .collect();
+    if !state.pending.is_empty() {
+        warn!(
+            "Stopped after {}; Not started: {}",
+            app.value_of("max-duration").unwrap(),
+            state.pending.join(", ")
+        );
+    }
+}
+
+// Apply the global config's defaults for what modules don't set themselves
+fn configure(module: &mut Module, config: &Config) {
+    module.prefer_relative_links(config.relative_links);
+    module.set_script_timeout(config.script_timeout());
+}
+
+// Tell the module which decrypted copies it installed, so it leaves other files alone
+fn recall(module: &mut Module, state: &State) {
+    let copies = state
+        .checksums(module.name())
+        .into_iter()
+        .flat_map(BTreeMap::keys);
+    module.set_copies(copies.cloned());
+}
+
+fn open_catalog() -> Catalog {
+    Catalog::load().unwrap_or_else(|err| {
+        warn!("{}", err);
+        Catalog::builtin()
+    })
+}
+
+fn reload(catalog: &Catalog, module: &Module) {
+    if let Err(err) = module.reload(catalog) {
+        warn!("{}", err);
+    }
+}
+
+fn open_state(app: &clap::ArgMatches) -> State {
+    let mut state = State::load().unwrap_or_else(|err| {
+        warn!("{}", err);
+        State::default()
+    });
+    if state.recovered {
+        state.reconcile(&module_dirs(app));
+    }
+    state
+}
+
+fn close_state(state: &State) {
+    if let Err(err) = state.save() {
+        warn!("{}", err);
+    }
+}
+
+fn record_failure(config: &Config, state: &mut State, name: &str, err: &ModuleError) {
+    if err.is_module_fault() && state.record_failure(name, config.quarantine_after) {
+        warn!(
+            "Module {}: Quarantined after {} failures; Bulk operations skip it until `modman unquarantine {}`",
+            name, config.quarantine_after, name
+        );
+    }
+}
+
+fn record_result(
+    config: &Config,
+    state: &mut State,
+    action: &str,
+    name: &str,
+    result: &Result<(), ModuleError>,
+) {
+    runlog::record(action, name, result);
+    match *result {
+        Ok(()) => state.record_success(name),
+        Err(ref err) => record_failure(config, state, name, err),
+    }
+}
+
+/// Commit the regular files among `paths`, which a forced `command` is about to delete, to the
+/// backup branch if `git_backups` is set. Nothing is deleted if that fails.
+fn back_up(
+    app: &clap::ArgMatches,
+    config: &Config,
+    command: &str,
+    paths: &[PathBuf],
+) -> Result<(), CliError> {
+    let files: Vec<PathBuf> = paths
+        .iter()
+        .filter(|path| fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_file()))
+        .cloned()
+        .collect();
+    if !config.git_backups || files.is_empty() {
+        return Ok(());
+    }
+    let repo = match config.backup_repo {
+        Some(ref repo) => PathBuf::from(repo),
+        None => module_dirs(app).primary().to_owned(),
+    };
+    if config.backup_repo.is_none() && git::status(&repo).is_none() {
+        return Err(clap::Error::with_description(
+            &format!(
+                "Modules directory {} isn't in a git repository to back up to; Set backup_repo",
+                repo.display()
+            ),
+            clap::ErrorKind::InvalidValue,
+        )
+        .into());
+    }
+    let listing: Vec<String> = files
+        .iter()
+        .map(|file| file.display().to_string())
+        .collect();
+    let message = format!(
+        "Before modman {} --force\n\n{}",
+        command,
+        listing.join("\n")
+    );
+    let commit = git::back_up(&repo, &files, &message).context("backup")?;
+    println!(
+        "Backed up {} files to {} in {} ({})",
+        files.len(),
+        git::BACKUP_BRANCH,
+        repo.display(),
+        &commit[..commit.len().min(7)]
+    );
+    Ok(())
+}
+
+/// Warn about modules directories with uncommitted changes or behind their upstream, which would
+/// deploy half-edited or stale configs, or refuse to go on over them with `--require-clean`
+fn check_repos(app: &clap::ArgMatches) -> Result<(), CliError> {
+    let mut problems = Vec::new();
+    for dir in module_dirs(app).iter() {
+        let status = match git::status(dir) {
+            Some(status) => status,
+            None => continue,
+        };
+        if !status.changed.is_empty() {
+            let mut files: Vec<&str> = status
+                .changed
+                .iter()
+                .take(CHANGED_FILES_SHOWN)
+                .map(String::as_str)
+                .collect();
+            if status.changed.len() > CHANGED_FILES_SHOWN {
+                files.push("...");
+            }
+            problems.push(format!(
+                "Modules directory {} has uncommitted changes ({})",
+                dir.display(),
+                files.join(", ")
+            ));
+        }
+        if status.behind > 0 {
+            problems.push(format!(
+                "Modules directory {} is {} commits behind {}",
+                dir.display(),
+                status.behind,
+                status.upstream.unwrap_or_default()
+            ));
+        }
+    }
+    if app.is_present("require-clean") && !problems.is_empty() {
+        return Err(clap::Error::with_description(
+            &format!(
+                "{}; Commit or pull first, or drop --require-clean",
+                problems.join("; ")
+            ),
+            clap::ErrorKind::InvalidValue,
+        )
+        .into());
+    }
+    for problem in problems {
+        warn!("{} (--require-clean refuses to go on)", problem);
+    }
+    Ok(())
+}
+
+fn install(app: &clap::ArgMatches, config: &Config) -> Result<i32, CliError> {
+    if app.is_present("trace") {
+        output::trace(app.values_of("MODULES").into_iter().flatten());
+    }
+    check_repos(app)?;
+    let mut state = open_state(app);
+    let modules = resolve(app, config, &mut state);
+    close_state(&state);
+    let mut modules = modules?;
+    for module in &mut modules {
+        configure(module, config);
+        recall(module, &state);
+    }
+    Module::check_conflicts(&modules)?;
+    if app.is_present("force") {
+        let paths: Vec<PathBuf> = modules.iter().flat_map(Module::obstructions).collect();
+        confirm_deletion(app, &paths)?;
+        back_up(app, config, "install", &paths)?;
+    } else {
+        let paths = conflicts::remembered_overwrites(&modules, &state);
+        confirm_deletion(app, &paths)?;
+        back_up(app, config, "install", &paths)?;
+        conflicts::resolve(&mut modules, &mut state, true).context("conflicts")?;
+    }
+    escalation::resolve(&mut modules, app.is_present("system"), true).context("escalation")?;
+    let cancel = Cancel::new();
+    let stop = Cancel::new();
+    let max_duration = app.value_of("max-duration").and_then(parse_duration);
+    let budget_start = Instant::now();
+    let mut stats = open_stats(config);
+    let catalog = open_catalog();
+    hooks::pre(&module_dirs(app), "install", &modules)?;
+    let mut progress = Progress::new(modules.len());
+    let mut probe_failures = Vec::new();
+    let results = module::for_each(&modules, &stop, |module| {
+        let start = Instant::now();
+        progress.start(module.name());
+        let run_init =
+            !module.init_once() || !state.init_done(module.name()) || app.is_present("rerun-init");
+        let result = module.install_with_progress(
+            app.is_present("force"),
+            app.is_present("install-packages"),
+            run_init,
+            &cancel,
+            |done, total| progress.resources(done, total),
+        );
+        record_result(config, &mut state, "install", module.name(), &result);
+        if result.is_ok() {
+            state.record_links(module.name(), module.targets());
+            state.record_checksums(module.name(), module.checksums());
+            state.record_install(module.name());
+            if module.init_once() {
+                state.record_init(module.name());
+            }
+        }
+        if let Some(ref mut stats) = stats {
+            stats.record(
+                Operation::Install,
+                module.name(),
+                result.is_ok(),
+                start.elapsed(),
+            );
+        }
+        match result {
+            Ok(()) => {
+                progress.println(format!(
+                    "Module {}: {}",
+                    module.name(),
+                    paint(Style::Ok, "Installed")
+                ));
+                reload(&catalog, module);
+                if let Err(err) = module.probe() {
+                    probe_failures.push(err);
+                }
+            }
+            Err(ref err) => progress.println(paint(Style::Fail, err)),
+        }
+        check_budget(max_duration, budget_start, &stop);
+        result
+    });
+    drop(progress);
+    hooks::post(&module_dirs(app), "install", &results);
+    print_skipped(&modules);
+    if !probe_failures.is_empty() {
+        println!(
+            "{}",
+            paint(
+                Style::Warn,
+                format!(
+                    "{} modules failed their probe; Check they still start before logging out:",
+                    probe_failures.len()
+                )
+            )
+        );
+        for err in &probe_failures {
+            println!("  {}", err);
+        }
+    }
+    record_pending(app, &mut state, &modules, &results);
+    collect_garbage(app, config, &state);
+    close_state(&state);
+    close_stats(stats);
+    Ok(exit_code(&results))
+}
+
+/// Summarise the resources left out because the program they configure isn't installed
+fn print_skipped(modules: &[Module]) {
+    let skipped: Vec<String> = modules
+        .iter()
+        .flat_map(|module| {
+            module
+                .skipped()
+                .iter()
+                .map(move |(resource, app)| format!("{}/{} ({})", module.name(), resource, app))
+        })
+        .collect();
+    if !skipped.is_empty() {
+        println!(
+            "Skipped {} resources of programs that aren't installed: {}",
+            skipped.len(),
+            paint(Style::Dim, skipped.join(", "))
+        );
+    }
+}
+
+fn uninstall(app: &clap::ArgMatches, config: &Config) -> Result<i32, CliError> {
+    check_repos(app)?;
+    let mut state = open_state(app);
+    let modules = resolve(app, config, &mut state);
+    close_state(&state);
+    let mut modules = modules?;
+    for module in &mut modules {
+        configure(module, config);
+        recall(module, &state);
+    }
+    if app.is_present("force") {
+        let paths: Vec<PathBuf> = modules.iter().flat_map(Module::foreign_targets).collect();
+        confirm_deletion(app, &paths)?;
+        back_up(app, config, "uninstall", &paths)?;
+    }
+    if app.is_present("remove-artifacts") {
+        let artifacts: Vec<PathBuf> = modules
+            .iter()
+            .flat_map(Module::artifacts)
+            .filter(|artifact| fs::symlink_metadata(artifact).is_ok())
+            .collect();
+        confirm_deletion(app, &artifacts)?;
+    }
+    escalation::resolve(&mut modules, app.is_present("system"), false).context("escalation")?;
+    let cancel = Cancel::new();
+    let stop = Cancel::new();
+    let max_duration = app.value_of("max-duration").and_then(parse_duration);
+    let budget_start = Instant::now();
+    let mut stats = open_stats(config);
+    hooks::pre(&module_dirs(app), "uninstall", &modules)?;
+    let mut progress = Progress::new(modules.len());
+    let results = module::for_each(&modules, &stop, |module| {
+        let start = Instant::now();
+        progress.start(module.name());
+        let result = module
+            .uninstall(app.is_present("force"), &cancel)
+            .and_then(|()| {
+                if app.is_present("remove-artifacts") {
+                    module.remove_artifacts().map(|_| ())
+                } else {
+                    Ok(())
+                }
+            });
+        record_result(config, &mut state, "uninstall", module.name(), &result);
+        if result.is_ok() {
+            state.record_links(module.name(), Vec::new());
+            state.record_checksums(module.name(), BTreeMap::new());
+        }
+        if let Some(ref mut stats) = stats {
+            stats.record(
+                Operation::Uninstall,
+                module.name(),
+                result.is_ok(),
+                start.elapsed(),
+            );
+        }
+        match result {
+            Ok(()) => progress.println(format!(
+                "Module {}: {}",
+                module.name(),
+                paint(Style::Ok, "Uninstalled")
+            )),
+            Err(ref err) => progress.println(paint(Style::Fail, err)),
+        }
+        check_budget(max_duration, budget_start, &stop);
+        result
+    });
+    drop(progress);
+    hooks::post(&module_dirs(app), "uninstall", &results);
+    record_pending(app, &mut state, &modules, &results);
+    collect_garbage(app, config, &state);
+    close_state(&state);
+    close_stats(stats);
+    Ok(exit_code(&results))
+}
+
+fn stats(config: &Config) -> Result<(), CliError> {
+    if !config.stats {
+        println!(
+            "Statistics are disabled; Set `stats = true` in {} to enable",
+            Config::path()
+                .map(|path| path.display().to_string())
+                .unwrap_or_else(|| "the global config".to_owned())
+        );
+    }
+    let stats = Stats::load()?;
+    println!("{}", stats);
+    Ok(())
+}
+
+fn edit(app: &clap::ArgMatches) -> Result<(), CliError> {
+    let name = app.value_of("MODULE").unwrap();
+    let module_path = module_dirs(app).path(name);
+    if !module_path.is_dir() {
+        return Err(clap::Error::with_description(
+            &format!("Module {}: Not found", name),
+            clap::ErrorKind::InvalidValue,
+        )
+        .into());
+    }
+    let file = module_path.join(app.value_of("RESOURCE").unwrap_or(module::CONFIG_FILE));
+
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_owned());
+    let mut words = editor.split_whitespace();
+    let status = process::Command::new(words.next().unwrap())
+        .args(words)
+        .arg(&file)
+        .status()
+        .context(name)?;
+    if !status.success() {
+        return Err(CliError::from(ModuleError::Exec(
+            name.to_owned(),
+            editor.clone(),
+        )));
+    }
+
+    // Verify straight away rather than at the next install
+    Module::new(&module_path)?;
+    println!("Module {}: {}", name, paint(Style::Ok, "OK"));
+    Ok(())
+}
+
+/// Persist changes made to a module's installed files: copies are put back into the module,
+/// while links need nothing as they already point into it. Copies unchanged since they were
+/// installed are left alone unless named. Exits with 1 if any copy couldn't be absorbed.
+fn absorb(app: &clap::ArgMatches, config: &Config) -> Result<i32, CliError> {
+    let mut state = open_state(app);
+    let mut module = Module::new(module_dirs(app).path(app.value_of("MODULE").unwrap()))?;
+    configure(&mut module, config);
+    recall(&mut module, &state);
+    let mut resources = module.resource_states();
+    if let Some(resource) = app.value_of("RESOURCE") {
+        resources.retain(|state| state.resource == resource);
+        if resources.is_empty() {
+            return Err(clap::Error::with_description(
+                &format!("Module {}: No resource {}", module.name(), resource),
+                clap::ErrorKind::InvalidValue,
+            )
+            .into());
+        }
+    }
+
+    let mut checksums = state.checksums(module.name()).cloned().unwrap_or_default();
+    let drifted: HashSet<PathBuf> = module
+        .drifted(&checksums)
+        .into_iter()
+        .map(|(_, system_file)| system_file)
+        .collect();
+    let mut failed = false;
+    for resource in &resources {
+        let target = resource.target.display();
+        match (&resource.mode, &resource.status) {
+            (Mode::Copy, ResourceStatus::Installed) => {
+                let unchanged =
+                    checksums.contains_key(&resource.target) && !drifted.contains(&resource.target);
+                if unchanged && app.value_of("RESOURCE").is_none() {
+                    println!("Module {}: {} is unchanged", module.name(), target);
+                    continue;
+                }
+                match module.pull_copy(&resource.resource) {
+                    Ok(()) => {
+                        println!(
+                            "Module {}: Absorbed {} into {}",
+                            module.name(),
+                            target,
+                            resource.resource
+                        );
+                        checksums.extend(module.checksums().remove_entry(&resource.target));
+                    }
+                    Err(err) => {
+                        println!("{}", paint(Style::Fail, &err));
+                        failed = true;
+                    }
+                }
+            }
+            (Mode::Copy, _) | (_, ResourceStatus::Missing) => {
+                println!(
+                    "Module {}: {} isn't installed; Nothing to absorb",
+                    module.name(),
+                    target
+                );
+            }
+            (_, ResourceStatus::Installed) => {
+                println!(
+                    "Module {}: {} links into the module; Nothing to absorb",
+                    module.name(),
+                    target
+                );
+            }
+            _ => {
+                println!(
+                    "Module {}: {} isn't linked to the module; Move it over {} by hand to keep it",
+                    module.name(),
+                    target,
+                    resource.source.display()
+                );
+            }
+        }
+    }
+    state.record_checksums(module.name(), checksums);
+    close_state(&state);
+    Ok(if failed { 1 } else { 0 })
+}
+
+#[cfg(not(feature = "tui"))]
+fn tui(_app: &clap::ArgMatches, _config: &Config) -> Result<(), CliError> {
+    Err(clap::Error::with_description(
+        "modman was built without the tui feature; Rebuild it with `--features tui`",
+        clap::ErrorKind::InvalidValue,
+    )
+    .into())
+}
+
+#[cfg(feature = "tui")]
+fn tui(app: &clap::ArgMatches, config: &Config) -> Result<(), CliError> {
+    // Operations run as child modman processes, which need the same modules
+    let mut args: Vec<std::ffi::OsString> = Vec::new();
+    for dir in app.values_of_os("modules-dir").unwrap() {
+        args.push("--modules-dir".into());
+        args.push(dir.to_owned());
+    }
+    if read_only(app) {
+        args.push("--read-only".into());
+    }
+    if app.is_present("allow-remote-source") {
+        args.push("--allow-remote-source".into());
+    }
+    // The state is read again for each preview, as the operations run from the tui change it
+    let prepare = |module: &mut Module| {
+        configure(module, config);
+        recall(module, &State::load().unwrap_or_default());
+    };
+    tui::run(module_dirs(app), args, &prepare)
+        .context("tui")
+        .map_err(CliError::from)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch(_app: &clap::ArgMatches) -> Result<i32, CliError> {
+    Err(clap::Error::with_description(
+        "watch is only supported on Linux",
+        clap::ErrorKind::InvalidValue,
+    )
+    .into())
+}
+
+#[cfg(target_os = "linux")]
+fn watch(app: &clap::ArgMatches) -> Result<i32, CliError> {
+    let state = open_state(app);
+    let module_dirs = module_dirs(app);
+    let modules: Vec<Module> = match module_names(app)? {
+        Some(names) => names
+            .iter()
+            .map(|name| Module::new(module_dirs.path(name)))
+            .map(|module| {
+                module.map(|mut module| {
+                    recall(&mut module, &state);
+                    module
+                })
+            })
+            .collect::<Result<_, _>>()?,
+        None => module_dirs
+            .list()?
+            .into_iter()
+            .filter_map(|module| module.ok())
+            .map(|mut module| {
+                recall(&mut module, &state);
+                module
+            })
+            .filter(|module| module.status() != module::Status::NotInstalled)
+            .filter(|module| !skipped(&state, module))
+            .collect(),
+    };
+
+    let mut watcher = Watcher::new().context("watch").map_err(CliError::from)?;
+    for module in &modules {
+        watcher
+            .add(module.path())
+            .context("watch")
+            .map_err(CliError::from)?;
+    }
+    println!("Watching {} modules", modules.len());
+    let catalog = open_catalog();
+
+    let mut modules: HashMap<_, _> = modules
+        .into_iter()
+        .map(|module| (module.path().to_path_buf(), module))
+        .collect();
+    loop {
+        for path in watcher
+            .wait(Duration::from_millis(500))
+            .context("watch")
+            .map_err(CliError::from)?
+        {
+            // Keep the last good definition while the config is broken mid-edit
+            let module = match Module::new(&path) {
+                Ok(module) => module,
+                Err(err) => {
+                    println!("{}", paint(Style::Fail, err));
+                    continue;
+                }
+            };
+            match module.update_links(&modules[&path]) {
+                Ok(()) => {
+                    println!("Module {}: {}", module.name(), paint(Style::Ok, "Updated"));
+                    reload(&catalog, &module);
+                }
+                Err(err) => println!("{}", paint(Style::Fail, err)),
+            }
+            modules.insert(path, module);
+        }
+    }
+}
+
+/// Report decrypted copies changed since they were installed, or pull the changes into their
+/// modules or overwrite them. Exits with 1 if any changed copy is left as it is.
+fn drift(app: &clap::ArgMatches, config: &Config) -> Result<i32, CliError> {
+    let mut state = open_state(app);
+    let module_dirs = module_dirs(app);
+    let mut modules: Vec<Module> = match app.values_of("MODULES") {
+        Some(names) => names
+            .map(|name| Module::new(module_dirs.path(name)))
+            .collect::<Result<_, _>>()?,
+        None => module_dirs
+            .list()?
+            .into_iter()
+            .filter_map(|module| module.ok())
+            .filter(|module| {
+                state
+                    .checksums(module.name())
+                    .is_some_and(|checksums| !checksums.is_empty())
+            })
+            .collect(),
+    };
+
+    let mut left = 0;
+    for module in &mut modules {
+        configure(module, config);
+        recall(module, &state);
+        let mut checksums = match state.checksums(module.name()) {
+            Some(checksums) => checksums.clone(),
+            None => continue,
+        };
+        let current = module.checksums();
+        for (resource, system_file) in module.drifted(&checksums) {
+            let result = if app.is_present("pull") {
+                module.pull_copy(resource)
+            } else if app.is_present("overwrite") {
+                module.overwrite_copy(resource)
+            } else {
+                println!(
+                    "Module {}: {} changed since it was installed",
+                    module.name(),
+                    system_file.display()
+                );
+                left += 1;
+                continue;
+            };
+            match result {
+                Ok(()) if app.is_present("pull") => {
+                    println!(
+                        "Module {}: Pulled {} into {}",
+                        module.name(),
+                        system_file.display(),
+                        resource
+                    );
+                    checksums.insert(system_file.clone(), current[&system_file].clone());
+                }
+                Ok(()) => {
+                    println!(
+                        "Module {}: Overwrote {}",
+                        module.name(),
+                        system_file.display()
+                    );
+                    checksums.remove(&system_file);
+                    checksums.extend(module.checksums().remove_entry(&system_file));
+                }
+                Err(err) => {
+                    println!("{}", paint(Style::Fail, &err));
+                    left += 1;
+                }
+            }
+        }
+        state.record_checksums(module.name(), checksums);
+    }
+    close_state(&state);
+    Ok(if left > 0 { 1 } else { 0 })
+}
+
+fn sync(app: &clap::ArgMatches, config: &Config) -> Result<i32, CliError> {
+    let mut state = open_state(app);
+    let module_dirs = module_dirs(app);
+    let mut modules: Vec<Module> = match module_names(app)? {
+        Some(names) => names
+            .iter()
+            .map(|name| Module::new(module_dirs.path(name)))
+            .collect::<Result<_, _>>()?,
+        None => module_dirs
+            .list()?
+            .into_iter()
+            .filter_map(|module| module.ok())
+            .filter(|module| {
+                module.status() != module::Status::NotInstalled
+                    || !state.links(module.name()).is_empty()
+            })
+            .filter(|module| !skipped(&state, module))
+            .collect(),
+    };
+    for module in &mut modules {
+        configure(module, config);
+        recall(module, &state);
+    }
+    Module::check_conflicts(&modules)?;
+    let paths = conflicts::remembered_overwrites(&modules, &state);
+    confirm_deletion(app, &paths)?;
+    back_up(app, config, "sync", &paths)?;
+    conflicts::resolve(&mut modules, &mut state, false).context("conflicts")?;
+
+    let catalog = open_catalog();
+    let mut changed = 0;
+    hooks::pre(&module_dirs, "sync", &modules)?;
+    let results = module::for_each(&modules, &Cancel::new(), |module| {
+        let result = module.sync(state.links(module.name()));
+        let result = match result {
+            Ok(changes) => {
+                for change in &changes {
+                    println!("Module {}: {}", module.name(), change);
+                }
+                if !changes.is_empty() {
+                    changed += 1;
+                    reload(&catalog, module);
+                }
+                state.record_links(module.name(), module.targets());
+                state.add_checksums(module.name(), module.checksums());
+                if let Some(head) = git::head(module.path()) {
+                    if let Some(revision) = state.synced_revision(module.name()) {
+                        print_changelog(module, revision, &head);
+                    }
+                    state.record_synced_revision(module.name(), head);
+                }
+                Ok(())
+            }
+            Err(err) => {
+                println!("{}", paint(Style::Fail, &err));
+                Err(err)
+            }
+        };
+        record_result(config, &mut state, "sync", module.name(), &result);
+        result
+    });
+    hooks::post(&module_dirs, "sync", &results);
+    print_skipped(&modules);
+    collect_garbage(app, config, &state);
+    close_state(&state);
+    println!("{} of {} modules changed", changed, modules.len());
+    Ok(exit_code(&results))
+}
+
+// Show the entries added to a module's CHANGELOG.md between the `from` and `to` commits: the lines
+// that weren't there before
+fn print_changelog(module: &Module, from: &str, to: &str) {
+    if from == to {
+        return;
+    }
+    let current = match git::show(module.path(), to, CHANGELOG_FILE) {
+        Some(changelog) => changelog,
+        None => return,
+    };
+    let previous = git::show(module.path(), from, CHANGELOG_FILE).unwrap_or_default();
+    let known: HashSet<&str> = previous.lines().collect();
+    let added: Vec<&str> = current
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !known.contains(line))
+        .collect();
+    if added.is_empty() {
+        return;
+    }
+    println!("Module {}: New in {}:", module.name(), CHANGELOG_FILE);
+    for line in added {
+        println!("  {}", line);
+    }
+}
+
+// Whether a module is left out of watch and sync when they aren't given modules
+fn skipped(state: &State, module: &Module) -> bool {
+    if state.is_disabled(module.name()) {
+        info!("Module {}: Disabled, skipping", module.name());
+        true
+    } else if state.is_quarantined(module.name()) {
+        warn!("Module {}: Quarantined, skipping", module.name());
+        true
+    } else {
+        false
+    }
+}
+
+fn set_disabled(app: &clap::ArgMatches, disabled: bool) -> Result<(), CliError> {
+    let module_dirs = module_dirs(app);
+    let mut state = State::load()?;
+    for name in &module_names(app)?.unwrap() {
+        if !module_dirs.contains(name) {
+            return Err(clap::Error::with_description(
+                &format!("Module {}: Not found", name),
+                clap::ErrorKind::InvalidValue,
+            )
+            .into());
+        }
+        let status = if disabled { "Disabled" } else { "Enabled" };
+        if state.set_disabled(name, disabled) {
+            println!("Module {}: {}", name, paint(Style::Ok, status));
+        } else {
+            println!("Module {}: Already {}", name, status.to_lowercase());
+        }
+    }
+    Ok(state.save()?)
+}
+
+fn unquarantine(app: &clap::ArgMatches) -> Result<(), CliError> {
+    let mut state = State::load()?;
+    for name in &module_names(app)?.unwrap() {
+        if state.unquarantine(name) {
+            println!("Module {}: {}", name, paint(Style::Ok, "Unquarantined"));
+        } else {
+            println!("Module {}: Not quarantined", name);
+        }
+    }
+    Ok(state.save()?)
+}
+
+fn decisions() -> Result<(), CliError> {
+    let state = State::load()?;
+    if state.decisions.is_empty() {
+        println!("No decisions remembered");
+        return Ok(());
+    }
+    let rows: Vec<(String, String)> = state
+        .decisions
+        .iter()
+        .map(|(system_file, resolution)| {
+            let resolution = format!("{:?}", resolution).to_lowercase();
+            (system_file.display().to_string(), resolution)
+        })
+        .collect();
+    output::columns(&rows);
+    Ok(())
+}
+
+fn forget(app: &clap::ArgMatches) -> Result<(), CliError> {
+    let mut state = State::load()?;
+    let cwd = env::current_dir().context("forget")?;
+    for file in app.values_of("FILES").unwrap() {
+        let system_file = cwd.join(file);
+        if state.forget_decision(&system_file) {
+            println!(
+                "{}: {}",
+                system_file.display(),
+                paint(Style::Ok, "Forgotten")
+            );
+        } else {
+            println!("{}: No decision remembered", system_file.display());
+        }
+    }
+    Ok(state.save()?)
+}
+
+fn schema() -> Result<(), CliError> {
+    println!("{}", serde_json::to_string_pretty(&modman::schema())?);
+    Ok(())
+}
+
+/// Check module configs (given by name, directory or config file), printing problems as
+/// `<config>:<line>:<column>: <message>` for editors and pre-commit hooks
+fn check(app: &clap::ArgMatches) -> Result<i32, CliError> {
+    // Loading modules warns about deprecated forms too, which are listed below instead
+    log::set_max_level(log::LevelFilter::Error);
+    let module_dirs = module_dirs(app);
+    let mut failed = false;
+    for module in app.values_of("MODULES").unwrap() {
+        let path = Path::new(module);
+        let (module_path, config) = if path.is_file() {
+            let absolute = path.canonicalize().context(module)?;
+            (absolute.parent().unwrap().to_path_buf(), path.to_path_buf())
+        } else {
+            let module_path = if path.is_dir() {
+                path.to_path_buf()
+            } else {
+                module_dirs.path(module)
+            };
+            let config = module_path.join(module::CONFIG_FILE);
+            (module_path, config)
+        };
+
+        // Deprecated forms are still read, so they are only warned about
+        let source = fs::read_to_string(&config).unwrap_or_default();
+        for deprecation in modman::deprecations(&source) {
+            let position = deprecation
+                .line_col
+                .map_or(String::new(), |(line, col)| format!(":{}:{}", line, col));
+            println!(
+                "{}{}",
+                config.display(),
+                paint(
+                    Style::Warn,
+                    format!("{}: warning: {}", position, deprecation.message)
+                )
+            );
+        }
+
+        // Problems in the config itself have a position, those found loading the module don't
+        let problem = match fs::read_to_string(&config) {
+            Err(err) => Some(format!(": {}", err)),
+            Ok(source) => match modman::diagnose(&source) {
+                Err(diagnostic) if diagnostic.line_col.is_some() => {
+                    Some(format!(":{}", diagnostic))
+                }
+                Err(diagnostic) => Some(format!(": {}", diagnostic)),
+                Ok(()) => Module::new(&module_path)
+                    .err()
+                    .map(|err| format!(": {}", err)),
+            },
+        };
+        if let Some(problem) = problem {
+            println!("{}{}", config.display(), paint(Style::Fail, problem));
+            failed = true;
+        }
+    }
+    Ok(if failed {
+        module::ErrorKind::Invalid.exit_code()
+    } else {
+        0
+    })
+}
+
+fn migrate(app: &clap::ArgMatches) -> Result<i32, CliError> {
+    let module_dirs = module_dirs(app);
+    let names: Vec<String> = match module_names(app)? {
+        Some(names) => names,
+        // Modules that fail to load still need migrating
+        None => module_dirs
+            .list()?
+            .iter()
+            .filter_map(|module| match *module {
+                Ok(ref module) => Some(module.name().to_owned()),
+                Err(ref err) => err.module().map(str::to_owned),
+            })
+            .collect(),
+    };
+
+    let mut failed = 0;
+    for name in &names {
+        match Module::migrate(module_dirs.path(name)) {
+            Ok(true) => println!(
+                "Module {}: {}",
+                name,
+                paint(
+                    Style::Ok,
+                    format!("Migrated to version {}", modman::SCHEMA_VERSION)
+                )
+            ),
+            Ok(false) => println!("Module {}: Up to date", name),
+            Err(err) => {
+                println!("{}", paint(Style::Fail, err));
+                failed += 1;
+            }
+        }
+    }
+    Ok(match failed {
+        0 => 0,
+        n if n == names.len() => EXIT_FAILED,
+        _ => EXIT_PARTIAL,
+    })
+}
+
+fn gc(app: &clap::ArgMatches, config: &Config) -> Result<(), CliError> {
+    let module_dirs = module_dirs(app);
+    let dry_run = app.is_present("dry-run");
+    let mut state = State::load()?;
+    let garbage = Garbage::find(&module_dirs, &state, config.log_retention())
+        .context("gc")
+        .map_err(CliError::from)?;
+
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    for (log, size) in garbage.logs.iter().chain(&garbage.orphaned_logs) {
+        println!("{} {} ({})", verb, log.display(), format_size(*size));
+    }
+    if !garbage.modules.is_empty() {
+        let verb = if dry_run { "Would forget" } else { "Forgot" };
+        println!("{} deleted modules {}", verb, garbage.modules.join(", "));
+    }
+    if !dry_run {
+        garbage
+            .remove(&module_dirs, &mut state)
+            .context("gc")
+            .map_err(CliError::from)?;
+        state.save()?;
+    }
+    println!(
+        "{} {} in total",
+        if dry_run { "Would free" } else { "Freed" },
+        format_size(garbage.size())
+    );
+    Ok(())
+}
+
+/// Clean up after an operation by deleting expired logs. What belongs to modules that aren't in
+/// the modules directories is only reported, as they may be in one that wasn't given this time.
+fn collect_garbage(app: &clap::ArgMatches, config: &Config, state: &State) {
+    let module_dirs = module_dirs(app);
+    let result = Garbage::find(&module_dirs, state, config.log_retention()).and_then(|garbage| {
+        for (log, _) in &garbage.logs {
+            info!("Remove {}", log.display());
+        }
+        if !garbage.modules.is_empty() {
+            info!(
+                "Keeping the state of modules not found: {}; Run `modman gc` to forget them",
+                garbage.modules.join(", ")
+            );
+        }
+        garbage.remove_expired()
+    });
+    if let Err(err) = result {
+        warn!("Cleaning up: {}", err);
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in &["B", "KiB", "MiB"] {
+        if size < 1024.0 {
+            return format!("{:.1} {}", size, unit).replace(".0 B", " B");
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} GiB", size)
+}
+
+// Exit status of a yes/no question about a module, which is no if it can't be loaded
+fn query<F: Fn(&Module, &State) -> bool>(app: &clap::ArgMatches, question: F) -> i32 {
+    // Warnings, e.g. about deprecated configs, would break the silence scripts rely on
+    log::set_max_level(log::LevelFilter::Off);
+    let state = State::load().unwrap_or_default();
+    match Module::new(module_dirs(app).path(app.value_of("MODULE").unwrap())) {
+        Ok(mut module) => {
+            recall(&mut module, &state);
+            if question(&module, &state) {
+                0
+            } else {
+                1
+            }
+        }
+        _ => 1,
+    }
+}
+
+fn doctor(app: &clap::ArgMatches, config: &Config) -> i32 {
+    if doctor::run(&module_dirs(app), &config.target_roots()) {
+        0
+    } else {
+        1
+    }
+}
+
+fn prune(app: &clap::ArgMatches, config: &Config) -> Result<(), CliError> {
+    let module_dirs = module_dirs(app);
+    let modules = module_dirs.list()?;
+    let orphans = gc::find_orphans(
+        &module_dirs,
+        &modules,
+        &open_state(app),
+        &config.target_roots(),
+    );
+    if app.is_present("print0") {
+        for orphan in &orphans {
+            output::print0(orphan.display());
+        }
+        return Ok(());
+    }
+    if orphans.is_empty() {
+        println!("No orphaned links");
+        return Ok(());
+    }
+    if app.is_present("dry-run") {
+        for orphan in &orphans {
+            println!("Would remove {}", orphan.display());
+        }
+        return Ok(());
+    }
+
+    confirm_deletion(app, &orphans)?;
+    for orphan in &orphans {
+        fs::remove_file(orphan).context(orphan.display().to_string())?;
+        println!("Removed {}", orphan.display());
+    }
+    Ok(())
+}
+
+fn retarget(app: &clap::ArgMatches, config: &Config) -> Result<(), CliError> {
+    let module_dirs = module_dirs(app);
+    let old_dir = env::current_dir()
+        .context("retarget")?
+        .join(app.value_of("OLD_DIR").unwrap());
+    let old_dir = old_dir.as_path();
+    let state = open_state(app);
+    let links = gc::find_moved_links(old_dir, &module_dirs, &state, &config.target_roots());
+    if links.is_empty() {
+        println!("No links into {}", old_dir.display());
+        return Ok(());
+    }
+
+    let mut retargeted = 0;
+    for (link, actual) in &links {
+        // The first component below the old directory is the module
+        let relative = actual.strip_prefix(old_dir).unwrap();
+        let mut components = relative.components();
+        let module = match components.next() {
+            Some(module) => module.as_os_str().to_string_lossy(),
+            None => continue,
+        };
+        let resource = module_dirs.path(&module).join(components.as_path());
+        if fs::symlink_metadata(&resource).is_err() {
+            warn!(
+                "{}: {} is not in the modules directory; Left alone",
+                link.display(),
+                resource.display()
+            );
+            continue;
+        }
+        if app.is_present("dry-run") {
+            println!("Would point {} at {}", link.display(), resource.display());
+            continue;
+        }
+        // Relative links stay relative
+        let relative = fs::read_link(link).is_ok_and(|contents| contents.is_relative());
+        let contents = if relative {
+            module::relative_link(link, &resource)
+        } else {
+            resource.clone()
+        };
+        fs::remove_file(link)
+            .and_then(|()| platform::symlink(&contents, link))
+            .context(link.display().to_string())?;
+        println!("Pointed {} at {}", link.display(), resource.display());
+        retargeted += 1;
+    }
+    if !app.is_present("dry-run") {
+        println!("{} of {} links retargeted", retargeted, links.len());
+    }
+    Ok(())
+}
+
+fn support_bundle(app: &clap::ArgMatches) -> Result<(), CliError> {
+    let bundle = support::create(
+        &module_dirs(app),
+        Path::new(app.value_of("output").unwrap()),
+    )
+    .context("support-bundle")?;
+    println!(
+        "Wrote {}; Check it before attaching it to a bug report",
+        bundle.display()
+    );
+    Ok(())
+}
+
+fn archive(app: &clap::ArgMatches, config: &Config) -> Result<(), CliError> {
+    let mut module = Module::new(module_dirs(app).path(app.value_of("MODULE").unwrap()))?;
+    configure(&mut module, config);
+    let mut state = open_state(app);
+    recall(&mut module, &state);
+    let result = if module.status() != module::Status::NotInstalled {
+        let result = module.uninstall(false, &Cancel::new());
+        record_result(config, &mut state, "uninstall", module.name(), &result);
+        result
+    } else {
+        Ok(())
+    };
+    if result.is_ok() {
+        state.record_links(module.name(), Vec::new());
+        state.record_checksums(module.name(), BTreeMap::new());
+    }
+    close_state(&state);
+    result?;
+    module.archive(app.value_of("reason").unwrap_or(""))?;
+    println!("Module {}: {}", module.name(), paint(Style::Ok, "Archived"));
+    Ok(())
+}
+
+fn unarchive(app: &clap::ArgMatches) -> Result<(), CliError> {
+    let name = app.value_of("MODULE").unwrap();
+    // Archived modules stay beside the directory they came from
+    let module_dirs = module_dirs(app);
+    let module_dir = module_dirs
+        .iter()
+        .find(|dir| {
+            Module::list_archived(dir)
+                .iter()
+                .any(|archived| archived.0 == name)
+        })
+        .unwrap_or_else(|| module_dirs.primary());
+    let module = Module::unarchive(module_dir, name)?;
+    println!(
+        "Module {}: {}",
+        module.name(),
+        paint(Style::Ok, "Unarchived")
+    );
+    Ok(())
+}
+
+fn rename(app: &clap::ArgMatches) -> Result<(), CliError> {
+    let new_name = app.value_of("NEW").unwrap();
+    if new_name.is_empty() || new_name.starts_with('.') || new_name.contains(['/', '\\']) {
+        return Err(clap::Error::with_description(
+            &format!("{} is not a valid module name", new_name),
+            clap::ErrorKind::InvalidValue,
+        )
+        .into());
+    }
+    let module_dirs = module_dirs(app);
+    let module = Module::new(module_dirs.path(app.value_of("OLD").unwrap()))?;
+    let mut state = open_state(app);
+    let renamed = module.rename(new_name, state.links(module.name()));
+    // Even if repointing a link failed, the directory may have been renamed already
+    if !module.path().exists() {
+        state.rename_module(module.name(), new_name);
+    }
+    close_state(&state);
+    let renamed = renamed?;
+    println!(
+        "Module {}: {}",
+        module.name(),
+        paint(Style::Ok, format!("Renamed to {}", renamed.name()))
+    );
+    Ok(())
+}
+
+fn scan_home(app: &clap::ArgMatches, config: &Config) -> Result<(), CliError> {
+    let module_dirs = module_dirs(app);
+    let catalog = open_catalog();
+    let proposals: Vec<_> = catalog
+        .apps()
+        .iter()
+        .filter(|&(name, _)| !module_dirs.contains(name))
+        .map(|(name, entry)| (name, entry.unmanaged_targets()))
+        .filter(|proposal| !proposal.1.is_empty())
+        .collect();
+    if proposals.is_empty() {
+        println!("Nothing to adopt");
+        return Ok(());
+    }
+
+    for (name, targets) in proposals {
+        let question = format!("Move {} into module {}?", targets.join(", "), name);
+        if !output::confirm(&question, false)
+            .context("scan-home")
+            .map_err(CliError::from)?
+        {
+            continue;
+        }
+        let result = Module::create(module_dirs.primary(), name, Some(name), &targets).and_then(
+            |mut module| {
+                configure(&mut module, config);
+                module.install(false, false, true, &Cancel::new())
+            },
+        );
+        match result {
+            Ok(()) => println!("Module {}: {}", name, paint(Style::Ok, "Adopted")),
+            Err(err) => println!("{}", paint(Style::Fail, err)),
+        }
+    }
+    Ok(())
+}
+
+fn bootstrap(app: &clap::ArgMatches, config: &Config, default_dir: &str) -> Result<i32, CliError> {
+    let module_dirs = module_dirs(app);
+    let url = app.value_of("URL").unwrap();
+    bootstrap::clone(url, module_dirs.primary())?;
+    println!("Cloned {} into {}", url, module_dirs.primary().display());
+
+    if app.is_present("verify") {
+        let failures: Vec<ModuleError> = module_dirs
+            .list()?
+            .into_iter()
+            .filter_map(Result::err)
+            .collect();
+        if let Some(first) = failures.first() {
+            let rows: Vec<(String, String)> = failures.iter().flat_map(verify_rows).collect();
+            output::columns(&rows);
+            println!(
+                "Installing nothing, as {} modules failed to verify",
+                failures.len()
+            );
+            return Ok(first.kind().exit_code());
+        }
+    }
+
+    // Installing goes through the install command, as if it had been run next
+    let plan = Plan::load(module_dirs.primary())?;
+    let dirs: Vec<&str> = app.values_of("modules-dir").unwrap().collect();
+    let mut args = vec![
+        "modman".to_owned(),
+        "--modules-dir".to_owned(),
+        dirs.join(platform::PATH_LIST_SEPARATOR),
+        "install".to_owned(),
+    ];
+    if plan.modules.is_empty() {
+        args.push("--all".to_owned());
+    } else {
+        args.extend(plan.modules);
+    }
+    let install_app = build(default_dir).get_matches_from(args);
+    install(install_app.subcommand_matches("install").unwrap(), config)
+}
+
+fn import_stow(app: &clap::ArgMatches) -> Result<(), CliError> {
+    let package = app.value_of("PACKAGE").unwrap();
+    let package = match Path::new(package).canonicalize() {
+        Ok(ref path) if path.is_dir() && path.parent().is_some() => path.clone(),
+        _ => {
+            return Err(clap::Error::with_description(
+                &format!("{} is not a stow package directory", package),
+                clap::ErrorKind::InvalidValue,
+            )
+            .into())
+        }
+    };
+    let package_name = package.file_name().unwrap().to_string_lossy().into_owned();
+    let name = app.value_of("name").unwrap_or(&package_name);
+    let module = Module::import_stow(
+        module_dirs(app).primary(),
+        &package,
+        name,
+        app.is_present("dotfiles"),
+    )?;
+    println!(
+        "Module {}: {}",
+        name,
+        paint(
+            Style::Ok,
+            format!("Imported {} resources", module.resources().len())
+        )
+    );
+    println!(
+        "Unstow the package first (`stow -D {}` in {}), then run `modman install {}`",
+        package_name,
+        package.parent().unwrap().display(),
+        name
+    );
+    Ok(())
+}
+
+fn import_chezmoi(app: &clap::ArgMatches) -> Result<(), CliError> {
+    let source = match app.value_of("SOURCE") {
+        Some(source) => PathBuf::from(source),
+        None => dirs::data_dir().unwrap().join("chezmoi"),
+    };
+    if !source.is_dir() {
+        return Err(clap::Error::with_description(
+            &format!("{} is not a chezmoi source directory", source.display()),
+            clap::ErrorKind::InvalidValue,
+        )
+        .into());
+    }
+    let import = import::chezmoi(&source, module_dirs(app).primary(), &open_catalog())?;
+    print_import(&import);
+    Ok(())
+}
+
+fn import_yadm(app: &clap::ArgMatches) -> Result<(), CliError> {
+    let repo = match app.value_of("REPO") {
+        Some(repo) => PathBuf::from(repo),
+        None => dirs::data_dir().unwrap().join("yadm/repo.git"),
+    };
+    if !repo.is_dir() {
+        return Err(clap::Error::with_description(
+            &format!("{} is not a yadm repository", repo.display()),
+            clap::ErrorKind::InvalidValue,
+        )
+        .into());
+    }
+    let home = dirs::home_dir().unwrap();
+    let import = import::yadm(&repo, &home, module_dirs(app).primary(), &open_catalog())?;
+    print_import(&import);
+    Ok(())
+}
+
+fn print_import(import: &Import) {
+    for (name, resources) in &import.modules {
+        println!(
+            "Module {}: {}",
+            name,
+            paint(Style::Ok, format!("Imported {} resources", resources))
+        );
+    }
+    if !import.skipped.is_empty() {
+        println!(
+            "{}",
+            paint(
+                Style::Warn,
+                format!("Skipped {} files:", import.skipped.len())
+            )
+        );
+        for (file, reason) in &import.skipped {
+            println!("  {} ({})", file.display(), reason);
+        }
+    }
+    if !import.modules.is_empty() {
+        println!(
+            "Run `modman install` to link them; It asks what to do about the files in the way"
+        );
+    }
+}
+
+fn new(app: &clap::ArgMatches, config: &Config) -> Result<(), CliError> {
+    let module_dirs = module_dirs(app);
+    let name = app.value_of("MODULE").unwrap();
+    let catalog = open_catalog();
+    let entry = match app.value_of("for") {
+        Some(program) => Some((
+            program,
+            catalog.get(program).ok_or_else(|| {
+                clap::Error::with_description(
+                    &format!("{} is not in the catalog", program),
+                    clap::ErrorKind::InvalidValue,
+                )
+            })?,
+        )),
+        None => None,
+    };
+
+    // Adopt existing config, otherwise start from an empty first target
+    let (targets, adopted) = match entry {
+        Some((_, entry)) if !entry.unmanaged_targets().is_empty() => {
+            (entry.unmanaged_targets(), true)
+        }
+        Some((_, entry)) => (vec![entry.targets[0].as_str()], false),
+        None => (Vec::new(), false),
+    };
+    let mut module = Module::create(
+        module_dirs.primary(),
+        name,
+        entry.map(|entry| entry.0),
+        &targets,
+    )?;
+    configure(&mut module, config);
+    if adopted {
+        module.install(false, false, true, &Cancel::new())?;
+    }
+    println!("Module {}: {}", name, paint(Style::Ok, "Created"));
     Ok(())
 }
@@ -1,279 +1,2361 @@
-extern crate clap;
-extern crate failure;
 extern crate toml;
 
+use cancel::Cancel;
+use catalog::Catalog;
+use definition::{
+    self, parse_duration, ModuleDef, ResourceDef, ScriptDef, CLEANUP_SCRIPT, INIT_SCRIPT,
+};
 use dirs;
-use std::collections::HashMap;
+use log::Level;
+use platform;
+use policy::Policy;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::env;
 use std::fmt;
 use std::fs;
-use std::io::{self, Error};
-use std::os::unix::fs::{symlink, PermissionsExt};
-use std::path::{Path, PathBuf};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Component, Path, PathBuf};
 use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 use std::vec::Vec;
+use target;
 
-static CONFIG_FILE: &'static str = "config.toml";
-static INIT_SCRIPT: &'static str = "init.sh";
-static CLEANUP_SCRIPT: &'static str = "cleanup.sh";
+pub static CONFIG_FILE: &'static str = "config.toml";
+// Documentation of a module that doesn't name its own
+static README: &str = "README.md";
+/// Directory inside the module directory that archived modules are moved to
+pub static ARCHIVE_DIR: &str = "archive";
+/// Holds scripts run once around an operation on several modules, rather than a module
+pub static HOOKS_DIR: &str = "hooks";
+// Version control metadata in a modules directory, which is never a module
+static VCS_DIRS: &[&str] = &[".git", ".hg", ".svn"];
+// Why a module was archived, kept inside the archived module
+static ARCHIVE_REASON_FILE: &str = ".archived";
 static PERMISSIONS_RX: u32 = 0b101;
 static PERMISSIONS_R: u32 = 0b100;
+static AGE_EXTENSION: &str = "age";
+static AGE_IDENTITY_ENV: &str = "MODMAN_AGE_IDENTITY";
+// Armored gpg files, which are re-encrypted as such
+static ARMOR_EXTENSION: &str = "asc";
+// Pulled changes are encrypted here first, then renamed over the resource
+static PULL_SUFFIX: &str = "modman-new";
+// Output of failed scripts, relative to the user data directory
+static SCRIPT_LOG_DIR: &str = "modman/logs";
+// How often wait_for checks again, and how long a single connection attempt may take
+static WAIT_INTERVAL: Duration = Duration::from_millis(500);
+// How long a probe may take before it counts as failed, e.g. a shell waiting for input
+static PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+static PROBE_INTERVAL: Duration = Duration::from_millis(100);
+// How often a script is checked on
+static SCRIPT_INTERVAL: Duration = Duration::from_millis(100);
+// How long output is still collected once a script exits, as what it started in the background
+// keeps its output open, maybe forever
+static SCRIPT_DRAIN: Duration = Duration::from_secs(1);
+// Files in stow packages that stow ignores by default, anywhere and only at the top
+static STOW_IGNORE: &[&str] = &[
+    ".git",
+    ".gitignore",
+    ".gitmodules",
+    ".hg",
+    ".stow-local-ignore",
+    ".svn",
+    "CVS",
+    "RCS",
+    "_darcs",
+];
+static STOW_IGNORE_TOP_LEVEL: &[&str] = &["COPYING", "LICENSE", "README"];
+// Appended to files moved aside to make way for a link
+static BACKUP_SUFFIX: &str = "modman-bak";
+// Log targets of the decisions made about each module start with this
+static TRACE_TARGET_PREFIX: &str = "modman::trace::";
 
-#[derive(Deserialize, Debug)]
-struct ModuleDef {
-    description: Option<String>,
+// Log a decision made about a module and why, which `--trace` shows for the modules it names
+macro_rules! trace_module {
+    ($module:expr, $($arg:tt)+) => {
+        trace!(
+            target: &trace_target($module),
+            "Module {}: {}",
+            $module,
+            format_args!($($arg)+)
+        )
+    };
+}
+
+impl ScriptDef {
+    fn path<'a>(&'a self, default: &'a str) -> &'a str {
+        self.script.as_deref().unwrap_or(default)
+    }
 
-    #[serde(default)]
-    init: bool,
+    /// Where the script runs: the module directory, unless it sets a `workdir`
+    fn workdir(&self, module_dir: &Path) -> PathBuf {
+        match self.workdir.as_deref() {
+            Some("~") => dirs::home_dir().unwrap_or_default(),
+            Some(dir) => match (dir.strip_prefix("~/"), dirs::home_dir()) {
+                (Some(relative), Some(home)) => home.join(relative),
+                _ => module_dir.join(dir),
+            },
+            None => module_dir.to_owned(),
+        }
+    }
+}
 
-    #[serde(default)]
-    cleanup: bool,
+impl ResourceDef {
+    /// The first condition of the resource that doesn't hold on this machine
+    fn unmet_condition(&self) -> Option<&'static str> {
+        let os = self.if_os.as_ref().is_none_or(|os| os == env::consts::OS);
+        let hostname = self
+            .if_hostname
+            .as_ref()
+            .is_none_or(|hostname| platform::hostname().is_some_and(|actual| actual == *hostname));
+        let env = self
+            .if_env
+            .as_ref()
+            .is_none_or(|condition| match condition.split_once('=') {
+                Some((var, value)) => env::var(var).is_ok_and(|actual| actual == value),
+                None => env::var(condition).is_ok_and(|actual| !actual.is_empty()),
+            });
+        let command = self
+            .if_command
+            .as_ref()
+            .is_none_or(|command| on_path(command));
+        [
+            (os, "if_os"),
+            (hostname, "if_hostname"),
+            (env, "if_env"),
+            (command, "if_command"),
+        ]
+        .iter()
+        .find(|&&(holds, _)| !holds)
+        .map(|&(_, condition)| condition)
+    }
 
-    resources: HashMap<String, String>,
+    /// The program the target configures, if `if_app` requires it but it isn't on the `PATH`
+    fn missing_app(&self) -> Option<String> {
+        if !self.if_app {
+            return None;
+        }
+        target::app_name(&self.target).filter(|app| !on_path(app))
+    }
+}
+
+fn on_path(command: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| {
+        env::split_paths(&path).any(|dir| platform::is_executable(&dir.join(command)))
+    })
 }
 
 impl ModuleDef {
     fn new<P: AsRef<Path>>(module_path: P) -> Result<ModuleDef, ModuleError> {
-        let buf = fs::read(module_path.as_ref().join(CONFIG_FILE))
-            .map_err(|err| ModuleError::IO(file_name_to_string(module_path.as_ref()), err))?;
-        let module_definition: ModuleDef = toml::from_slice(&buf)
-            .map_err(|err| ModuleError::Parse(file_name_to_string(module_path.as_ref()), err))?;
+        let name = file_name_to_string(module_path.as_ref());
+        let config = module_path.as_ref().join(CONFIG_FILE);
+        let buf = fs::read(&config).context(&name)?;
+        let parsed: toml::Value =
+            toml::from_slice(&buf).map_err(|err| ModuleError::Parse(name.clone(), err))?;
+        // Without includes the config is parsed directly, which keeps line numbers in errors
+        let module_definition = if parsed.get("include").is_none() {
+            ModuleDef::parse(&buf)
+        } else {
+            ModuleDef::from_value(ModuleDef::load(&name, &config, &mut Vec::new())?)
+        }
+        .map_err(|err| ModuleError::Parse(name.clone(), err))?;
+        // Forms from included configs aren't in this one, so they have no position
+        let source = String::from_utf8_lossy(&buf);
+        for deprecation in module_definition.deprecations() {
+            let position = definition::locate(&source, &deprecation.key)
+                .map_or(String::new(), |(line, col)| format!(":{}:{}", line, col));
+            warn!(
+                "Module {}: {}{}: {}; Run `modman migrate {}`",
+                name, CONFIG_FILE, position, deprecation, name
+            );
+        }
         module_definition.verify(module_path)?;
         Ok(module_definition)
     }
 
+    /// Parse the config at `path` and merge it over the configs it includes, in order. `chain` holds
+    /// the configs that (indirectly) include it, to catch cycles.
+    fn load(name: &str, path: &Path, chain: &mut Vec<PathBuf>) -> Result<toml::Value, ModuleError> {
+        let include_err = |err: &dyn fmt::Display| {
+            ModuleError::Include(name.to_owned(), format!("{}: {}", path.display(), err))
+        };
+        let path = path.canonicalize().map_err(|err| include_err(&err))?;
+        if chain.contains(&path) {
+            return Err(include_err(&"Included in a cycle"));
+        }
+        let buf = fs::read(&path).map_err(|err| include_err(&err))?;
+        let mut config: toml::Value = toml::from_slice(&buf).map_err(|err| include_err(&err))?;
+        let includes = definition::take_includes(&mut config).map_err(|err| include_err(&err))?;
+
+        chain.push(path.clone());
+        let mut merged = toml::Value::Table(toml::value::Table::new());
+        for include in includes {
+            let included = ModuleDef::load(name, &path.parent().unwrap().join(include), chain)?;
+            definition::merge(&mut merged, included);
+        }
+        chain.pop();
+        definition::merge(&mut merged, config);
+        Ok(merged)
+    }
+
+    /// Check everything about the module, reporting all issues together
     fn verify<P: AsRef<Path>>(&self, module_path: P) -> Result<(), ModuleError> {
-        if self.init {
-            let init_script_path = module_path.as_ref().join(INIT_SCRIPT);
-            if !init_script_path.exists() || !check_permissions(
-                init_script_path.metadata().unwrap().permissions().mode(),
-                PERMISSIONS_RX,
-            ) {
-                return Err(ModuleError::Script(
-                    file_name_to_string(module_path.as_ref()),
-                    "init".to_owned(),
-                ));
+        let mut issues = self.verify_policy();
+
+        let scripts = [
+            ("init", &self.init, INIT_SCRIPT),
+            ("cleanup", &self.cleanup, CLEANUP_SCRIPT),
+        ];
+        for &(kind, script, default) in &scripts {
+            let script = match *script {
+                Some(ref script) => script,
+                None => continue,
+            };
+            // Scripts run through an interpreter only need to be readable
+            let permissions = match script.interpreter {
+                Some(_) => PERMISSIONS_R,
+                None => PERMISSIONS_RX,
+            };
+            let script_path = module_path.as_ref().join(script.path(default));
+            if !check_path_permissions(&script_path, permissions) {
+                issues.push(ModuleIssue::Script(kind.to_owned()));
+            }
+        }
+
+        let mut resources: Vec<&String> = self.resources.keys().collect();
+        resources.sort();
+        for resource in resources {
+            let resource_path = module_path.as_ref().join(resource);
+            if !check_path_permissions(&resource_path, PERMISSIONS_R) {
+                issues.push(ModuleIssue::Resource(resource.to_owned()));
+            } else if self.resources[resource].link_children && !resource_path.is_dir() {
+                issues.push(ModuleIssue::NotDirectory(resource.to_owned()));
+            }
+            if let Err(reason) = target::expand(&self.resources[resource].target) {
+                issues.push(ModuleIssue::Target(resource.to_owned(), reason));
+            }
+        }
+
+        // The config was checked as written, but variables can still make an artifact unsafe
+        for artifact in &self.artifacts {
+            let problem = match target::expand(artifact) {
+                Ok(expanded) => definition::artifact_problem(&expanded).map(str::to_owned),
+                Err(reason) => Some(format!("can't be expanded; {}", reason)),
+            };
+            if let Some(problem) = problem {
+                issues.push(ModuleIssue::Artifact(artifact.to_owned(), problem));
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(ModuleError::Invalid(
+                file_name_to_string(module_path.as_ref()),
+                ModuleIssues(issues),
+            ))
+        }
+    }
+
+    fn verify_policy(&self) -> Vec<ModuleIssue> {
+        let policy = match Policy::get() {
+            Ok(policy) => policy,
+            Err(err) => return vec![ModuleIssue::Policy(err.to_owned())],
+        };
+
+        let mut issues = Vec::new();
+        if !policy.allow_scripts && (self.init.is_some() || self.cleanup.is_some()) {
+            issues.push(ModuleIssue::Policy("scripts are not allowed".to_owned()));
+        }
+
+        // Artifacts are deleted, so they are held to the same rules as targets
+        let mut targets: Vec<&String> = self
+            .resources
+            .values()
+            .map(|definition| &definition.target)
+            .chain(&self.artifacts)
+            .collect();
+        targets.sort();
+        for target in targets {
+            if let Some(reason) = policy.check_target(&target::resolve(target)) {
+                issues.push(ModuleIssue::Policy(reason));
+            }
+        }
+        issues
+    }
+}
+
+/// A single problem found while verifying a module
+#[derive(Debug, Error)]
+pub enum ModuleIssue {
+    #[error("Resource {0} not found or incorrect permissions")]
+    Resource(String),
+    #[error("Resource {0} links its children but isn't a directory")]
+    NotDirectory(String),
+    #[error("{0} script not found or has incorrect permissions")]
+    Script(String),
+    #[error("Target of resource {0} can't be expanded; {1}")]
+    Target(String, String),
+    #[error("Artifact {0} {1}")]
+    Artifact(String, String),
+    #[error("Not allowed by policy; {0}")]
+    Policy(String),
+}
+
+/// Every problem found while verifying a module
+#[derive(Debug)]
+pub struct ModuleIssues(pub Vec<ModuleIssue>);
+
+impl fmt::Display for ModuleIssues {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, issue) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ModuleError {
+    #[error("Module {0}: {1}")]
+    Invalid(String, ModuleIssues),
+    #[error("Module {0}: {1} returned non-zero code")]
+    Exec(String, String),
+    #[error("Module {0}: Existing file {1} found; Use -f to force overwrite")]
+    Install(String, String),
+    #[error("Module {0}: {1} is not a directory; Use -f to force overwrite")]
+    InstallPath(String, String),
+    #[error("Module {0}: Broken link {1} to {2} found; Use -f to replace it")]
+    Dangling(String, String, String),
+    #[error("Module {0}: {1} is not a symlink or does not point to correct resource; Use -f to force deletion")]
+    Uninstall(String, String),
+    #[error("Module {0}: {1} is in a system location; Pass --system to remove it with sudo")]
+    Privileged(String, String),
+    #[error("Module {0}: Missing packages {1}; Use --install-packages to install them")]
+    MissingPackage(String, String),
+    #[error("Module {0}: {1}")]
+    Parse(String, #[source] toml::de::Error),
+    #[error("Module {0}: {1}")]
+    IO(String, #[source] io::Error),
+    #[error("Module directory not found or has invalid permissions")]
+    Directory,
+    #[error("Modules {0} and {1} both install {2}; Exclude one of them")]
+    Conflict(String, String, String),
+    #[error("Module {0}: Cancelled")]
+    Cancelled(String),
+    #[error("Module {0}: {1} already exists")]
+    Exists(String, String),
+    #[error("Module {0}: Timed out waiting for {1}")]
+    Timeout(String, String),
+    #[error("Module {0}: Include {1}")]
+    Include(String, String),
+    #[error("Module {0}: Link {1} is broken right after it was created; {2}")]
+    Verification(String, String, String),
+}
+
+/// Turns IO errors into `ModuleError::IO`, naming what they happened in, usually a module
+pub trait IoContext<T> {
+    fn context<S: Into<String>>(self, context: S) -> Result<T, ModuleError>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn context<S: Into<String>>(self, context: S) -> Result<T, ModuleError> {
+        self.map_err(|err| ModuleError::IO(context.into(), err))
+    }
+}
+
+impl ModuleError {
+    /// Name of the module the error relates to, if it relates to a single module
+    pub fn module(&self) -> Option<&str> {
+        match *self {
+            ModuleError::Invalid(ref name, _)
+            | ModuleError::Exec(ref name, _)
+            | ModuleError::Install(ref name, _)
+            | ModuleError::InstallPath(ref name, _)
+            | ModuleError::Dangling(ref name, _, _)
+            | ModuleError::Uninstall(ref name, _)
+            | ModuleError::Privileged(ref name, _)
+            | ModuleError::MissingPackage(ref name, _)
+            | ModuleError::Parse(ref name, _)
+            | ModuleError::IO(ref name, _)
+            | ModuleError::Cancelled(ref name)
+            | ModuleError::Exists(ref name, _)
+            | ModuleError::Timeout(ref name, _)
+            | ModuleError::Include(ref name, _)
+            | ModuleError::Verification(ref name, _, _) => Some(name),
+            ModuleError::Directory | ModuleError::Conflict(_, _, _) => None,
+        }
+    }
+
+    /// What kind of error this is, independent of its message
+    pub fn kind(&self) -> ErrorKind {
+        match *self {
+            ModuleError::Invalid(_, _) | ModuleError::Parse(_, _) | ModuleError::Include(_, _) => {
+                ErrorKind::Invalid
+            }
+            ModuleError::Exec(_, _) => ErrorKind::Script,
+            ModuleError::Install(_, _)
+            | ModuleError::InstallPath(_, _)
+            | ModuleError::Dangling(_, _, _)
+            | ModuleError::Uninstall(_, _)
+            | ModuleError::Privileged(_, _)
+            | ModuleError::Exists(_, _) => ErrorKind::Obstructed,
+            ModuleError::MissingPackage(_, _) => ErrorKind::MissingPackage,
+            ModuleError::IO(_, _) => ErrorKind::IO,
+            ModuleError::Directory => ErrorKind::Directory,
+            ModuleError::Conflict(_, _, _) => ErrorKind::Conflict,
+            ModuleError::Cancelled(_) => ErrorKind::Cancelled,
+            ModuleError::Timeout(_, _) => ErrorKind::Timeout,
+            ModuleError::Verification(_, _, _) => ErrorKind::Verification,
+        }
+    }
+
+    /// The first missing resource, if the module failed verification because of one
+    pub fn resource(&self) -> Option<&str> {
+        match *self {
+            ModuleError::Invalid(_, ref issues) => issues.0.iter().find_map(|issue| match *issue {
+                ModuleIssue::Resource(ref resource) => Some(resource.as_str()),
+                _ => None,
+            }),
+            ModuleError::Conflict(_, _, ref resource) => Some(resource),
+            _ => None,
+        }
+    }
+
+    /// The system path the error relates to, e.g. the file in the way of a link
+    pub fn path(&self) -> Option<&str> {
+        match *self {
+            ModuleError::Install(_, ref path)
+            | ModuleError::InstallPath(_, ref path)
+            | ModuleError::Dangling(_, ref path, _)
+            | ModuleError::Uninstall(_, ref path)
+            | ModuleError::Exists(_, ref path)
+            | ModuleError::Verification(_, ref path, _) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Whether the module itself is broken: it fails verification or one of its commands fails
+    pub fn is_module_fault(&self) -> bool {
+        matches!(
+            *self,
+            ModuleError::Invalid(_, _)
+                | ModuleError::Exec(_, _)
+                | ModuleError::Parse(_, _)
+                | ModuleError::Include(_, _)
+        )
+    }
+}
+
+/// Kinds of `ModuleError`, each with an exit code that stays the same between releases so scripts
+/// can tell failures apart without matching messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The module fails verification, or its config can't be parsed
+    Invalid,
+    /// A script of the module returned non-zero
+    Script,
+    /// A file or directory is in the way of a link, or isn't the link expected
+    Obstructed,
+    /// Packages the module requires aren't installed
+    MissingPackage,
+    IO,
+    /// The modules directory is missing
+    Directory,
+    /// Two modules install the same system path
+    Conflict,
+    Cancelled,
+    /// Waiting for a unit or port to come up timed out
+    Timeout,
+    /// A link didn't resolve to its readable resource once created
+    Verification,
+}
+
+static ERROR_KINDS: &[ErrorKind] = &[
+    ErrorKind::Invalid,
+    ErrorKind::Script,
+    ErrorKind::Obstructed,
+    ErrorKind::MissingPackage,
+    ErrorKind::IO,
+    ErrorKind::Directory,
+    ErrorKind::Conflict,
+    ErrorKind::Cancelled,
+    ErrorKind::Timeout,
+    ErrorKind::Verification,
+];
+
+impl ErrorKind {
+    pub fn all() -> &'static [ErrorKind] {
+        ERROR_KINDS
+    }
+
+    /// Exit code of the command line tool when every failure was of this kind
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Invalid => 10,
+            ErrorKind::Script => 11,
+            ErrorKind::Obstructed => 12,
+            ErrorKind::MissingPackage => 13,
+            ErrorKind::IO => 14,
+            ErrorKind::Directory => 15,
+            ErrorKind::Conflict => 16,
+            ErrorKind::Cancelled => 17,
+            ErrorKind::Timeout => 18,
+            ErrorKind::Verification => 19,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            ErrorKind::Invalid => "A module is invalid or its config can't be parsed",
+            ErrorKind::Script => "A module script returned non-zero",
+            ErrorKind::Obstructed => "A file is in the way of a link, or a link isn't modman's",
+            ErrorKind::MissingPackage => "Packages a module requires aren't installed",
+            ErrorKind::IO => "Reading or writing a file failed",
+            ErrorKind::Directory => "The modules directory is missing",
+            ErrorKind::Conflict => "Two modules install the same path",
+            ErrorKind::Cancelled => "The operation was cancelled",
+            ErrorKind::Timeout => "Waiting for a unit, port, script or probe timed out",
+            ErrorKind::Verification => "A link was broken right after it was created",
+        }
+    }
+}
+
+/// What to do with a file in the way of a resource's link
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    /// Leave the file alone and don't link the resource
+    Keep,
+    /// Delete the file
+    Overwrite,
+    /// Move the file aside, to `<name>.modman-bak`
+    Backup,
+    /// Move the file into the module, replacing the resource
+    Adopt,
+}
+
+/// How much of a module is currently linked into the system
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Installed,
+    Partial,
+    NotInstalled,
+}
+
+/// How a resource is put at its target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Symlink,
+    /// A symlink relative to its directory
+    RelativeSymlink,
+    /// A private copy, decrypted from the resource
+    Copy,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Mode::Symlink => "symlink",
+            Mode::RelativeSymlink => "relative symlink",
+            Mode::Copy => "copy",
+        })
+    }
+}
+
+/// Whether a resource is at its target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceStatus {
+    Installed,
+    Missing,
+    /// The target is taken by a file the module didn't put there
+    Foreign,
+    /// The target links somewhere else
+    Elsewhere(PathBuf),
+}
+
+impl fmt::Display for ResourceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ResourceStatus::Installed => f.write_str("installed"),
+            ResourceStatus::Missing => f.write_str("missing"),
+            ResourceStatus::Foreign => f.write_str("foreign file"),
+            ResourceStatus::Elsewhere(ref path) => write!(f, "links to {}", path.display()),
+        }
+    }
+}
+
+/// A resource of a module, where it installs to and how, and whether it is installed
+#[derive(Debug, Clone)]
+pub struct ResourceState {
+    pub resource: String,
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub mode: Mode,
+    pub status: ResourceStatus,
+}
+
+/// A change `Module::sync` made to the system
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncChange {
+    /// A missing resource was linked (or decrypted)
+    Linked(PathBuf),
+    /// A broken link, or one pointing elsewhere, was replaced
+    Repaired(PathBuf),
+    /// A link to a resource no longer in the module was deleted
+    Removed(PathBuf),
+}
+
+impl fmt::Display for SyncChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SyncChange::Linked(ref path) => write!(f, "Linked {}", path.display()),
+            SyncChange::Repaired(ref path) => write!(f, "Repaired {}", path.display()),
+            SyncChange::Removed(ref path) => write!(f, "Removed {}", path.display()),
+        }
+    }
+}
+
+/// A step of installing or uninstalling a module, as planned by `Module::plan_install` and
+/// `Module::plan_uninstall` and taken by `Module::execute`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Check the module's packages are installed, installing missing ones if `install` is set
+    CheckPackages { install: bool },
+    /// Delete a file, empty directory or broken link in the way of a resource
+    Remove(PathBuf),
+    /// Leave a resource that is already linked alone
+    Keep { resource: String, target: PathBuf },
+    /// Symlink a resource to its target, or decrypt it there if it is encrypted
+    Install { resource: String, target: PathBuf },
+    /// Run the init script
+    RunInit,
+    /// Enable and start the module's systemd user units
+    EnableUnits,
+    /// Stop and disable the module's systemd user units
+    DisableUnits,
+    /// Delete the link to a resource, or shred its decrypted copy
+    Uninstall { resource: String, target: PathBuf },
+    /// Run the cleanup script
+    RunCleanup,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Action::CheckPackages { install: false } => write!(f, "Check packages"),
+            Action::CheckPackages { install: true } => write!(f, "Install missing packages"),
+            Action::Remove(ref path) => write!(f, "Remove {}", path.display()),
+            Action::Keep { ref target, .. } => write!(f, "Keep {}", target.display()),
+            Action::Install {
+                ref resource,
+                ref target,
+            } => write!(f, "Install {} to {}", resource, target.display()),
+            Action::RunInit => write!(f, "Run init script"),
+            Action::EnableUnits => write!(f, "Enable units"),
+            Action::DisableUnits => write!(f, "Disable units"),
+            Action::Uninstall { ref target, .. } => write!(f, "Uninstall {}", target.display()),
+            Action::RunCleanup => write!(f, "Run cleanup script"),
+        }
+    }
+}
+
+/// Told about each step `Module::execute` takes, e.g. to show progress in a frontend. Both
+/// methods do nothing unless implemented, and `()` observes nothing.
+pub trait Observer {
+    /// `action` is about to be taken for `module`
+    fn started(&mut self, _module: &Module, _action: &Action) {}
+
+    /// `action` was taken for `module`, with `result`
+    fn finished(&mut self, _module: &Module, _action: &Action, _result: &Result<(), ModuleError>) {}
+}
+
+impl Observer for () {}
+
+// Counts resources done for `Module::install_with_progress`
+struct ResourceProgress<F> {
+    done: usize,
+    total: usize,
+    progress: F,
+}
+
+impl<F: FnMut(usize, usize)> Observer for ResourceProgress<F> {
+    fn finished(&mut self, _module: &Module, action: &Action, result: &Result<(), ModuleError>) {
+        match *action {
+            Action::Keep { .. } | Action::Install { .. } if result.is_ok() => {
+                self.done += 1;
+                (self.progress)(self.done, self.total);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Module {
+    path: PathBuf,
+    definition: ModuleDef,
+    /// Resources left out because the program they configure is missing, and that program
+    skipped: Vec<(String, String)>,
+    /// Resources left out because one of their conditions doesn't hold, and that condition
+    left_out: Vec<(String, &'static str)>,
+    /// The definitions of those resources, as an install from before their condition changed
+    /// may have linked them
+    left_out_resources: BTreeMap<String, ResourceDef>,
+    /// Resources the user can't write the targets of are linked and removed with sudo
+    escalate: bool,
+    /// Links are relative unless a resource says otherwise
+    relative_links: bool,
+    /// How long scripts that don't set their own timeout may run
+    script_timeout: Option<Duration>,
+    /// Decrypted copies the module wrote, as recorded in the state or since; Any other file at
+    /// the target of an encrypted resource is the user's
+    copies: RefCell<BTreeSet<PathBuf>>,
+    /// Links recorded in the state when the module was last installed or synced
+    links: Vec<PathBuf>,
+}
+
+/// The directories modules are sourced from, in order of precedence: a module hides any of the
+/// same name in later directories. New and archived modules go in the first, primary, one.
+#[derive(Debug, Clone)]
+pub struct ModuleDirs(Vec<PathBuf>);
+
+impl ModuleDirs {
+    /// Panics if `dirs` is empty
+    pub fn new<I: IntoIterator<Item = PathBuf>>(dirs: I) -> ModuleDirs {
+        let dirs: Vec<PathBuf> = dirs.into_iter().collect();
+        assert!(!dirs.is_empty(), "at least one module directory is needed");
+        ModuleDirs(dirs)
+    }
+
+    pub fn primary(&self) -> &Path {
+        &self.0[0]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Path> {
+        self.0.iter().map(PathBuf::as_path)
+    }
+
+    /// Path of the named module: in the first directory that has it, or else the primary one
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_dir())
+            .unwrap_or_else(|| self.primary().join(name))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.iter().any(|dir| dir.join(name).is_dir())
+    }
+
+    /// The directory a path (e.g. a module, or a link's target) is in, if any
+    pub fn root_of(&self, path: &Path) -> Option<&Path> {
+        self.iter().find(|dir| path.starts_with(dir))
+    }
+
+    /// Modules of every directory, leaving out those hidden by one of the same name earlier on
+    pub fn list(&self) -> Result<Vec<Result<Module, ModuleError>>, ModuleError> {
+        let mut seen = HashSet::new();
+        let mut modules = Vec::new();
+        for dir in self.iter() {
+            for module in Module::list(dir)? {
+                let name = match module {
+                    Ok(ref module) => Some(module.name().to_owned()),
+                    Err(ref err) => err.module().map(str::to_owned),
+                };
+                if name.is_none_or(|name| seen.insert(name)) {
+                    modules.push(module);
+                }
+            }
+        }
+        Ok(modules)
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.definition.description {
+            None => write!(f, "{}", self.name()),
+            Some(desc) => write!(f, "{} - {}", self.name(), desc),
+        }
+    }
+}
+
+impl Module {
+    pub fn new<P: AsRef<Path>>(module_path: P) -> Result<Module, ModuleError> {
+        let mut module_def = ModuleDef::new(module_path.as_ref())?;
+        let name = file_name_to_string(module_path.as_ref());
+        // Resources for other machines are verified, but otherwise ignored
+        let mut left_out = Vec::new();
+        let mut left_out_resources = BTreeMap::new();
+        module_def.resources.retain(|resource, definition| {
+            match definition.unmet_condition() {
+                Some(condition) => {
+                    trace_module!(
+                        &name,
+                        "Resource {} left out, as its {} doesn't hold",
+                        resource,
+                        condition
+                    );
+                    left_out.push((resource.clone(), condition));
+                    left_out_resources.insert(resource.clone(), definition.clone());
+                }
+                None => return true,
+            }
+            false
+        });
+        left_out.sort();
+        let mut skipped = Vec::new();
+        module_def.resources.retain(|resource, definition| {
+            match definition.missing_app() {
+                Some(app) => {
+                    trace_module!(
+                        &name,
+                        "Resource {} skipped, as {} isn't on the PATH",
+                        resource,
+                        app
+                    );
+                    skipped.push((resource.clone(), app))
+                }
+                None => return true,
+            }
+            false
+        });
+        skipped.sort();
+
+        // Directories linked file by file become a resource for each file
+        let parents: Vec<String> = module_def
+            .resources
+            .iter()
+            .filter(|&(_, definition)| definition.link_children)
+            .map(|(resource, _)| resource.clone())
+            .collect();
+        for resource in parents {
+            let definition = module_def.resources.remove(&resource).unwrap();
+            let dir = module_path.as_ref().join(&resource);
+            let children = list_files(&dir).context(file_name_to_string(module_path.as_ref()))?;
+            trace_module!(
+                &name,
+                "Resource {} is linked file by file: {}",
+                resource,
+                children.join(", ")
+            );
+            for child in children {
+                let child_def = ResourceDef {
+                    target: format!("{}/{}", definition.target.trim_end_matches('/'), child),
+                    link_children: false,
+                    ..definition.clone()
+                };
+                module_def
+                    .resources
+                    .insert(format!("{}/{}", resource, child), child_def);
+            }
+        }
+
+        Ok(Module {
+            path: module_path.as_ref().to_path_buf(),
+            definition: module_def,
+            skipped,
+            left_out,
+            left_out_resources,
+            escalate: false,
+            relative_links: false,
+            script_timeout: None,
+            copies: RefCell::new(BTreeSet::new()),
+            links: Vec::new(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.path.file_name().unwrap().to_str().unwrap()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.definition.description.as_deref()
+    }
+
+    /// Whether the init script should only run on the first install
+    pub fn init_once(&self) -> bool {
+        self.definition.init_once
+    }
+
+    pub fn license(&self) -> Option<&str> {
+        self.definition.license.as_deref()
+    }
+
+    pub fn source_url(&self) -> Option<&str> {
+        self.definition.source_url.as_deref()
+    }
+
+    pub fn author(&self) -> Option<&str> {
+        self.definition.author.as_deref()
+    }
+
+    /// The module's documentation, if it has any
+    pub fn docs(&self) -> Option<PathBuf> {
+        let docs = self.definition.docs.as_deref().unwrap_or(README);
+        Some(self.path.join(docs)).filter(|path| path.is_file())
+    }
+
+    /// Pacman packages the module needs
+    pub fn packages(&self) -> &[String] {
+        &self.definition.packages
+    }
+
+    /// What the module runs and when, as the hook and a description of the script or command
+    pub fn hooks(&self, catalog: &Catalog) -> Vec<(&'static str, String)> {
+        let describe = |script: &ScriptDef, default: &str| {
+            let mut description = script.path(default).to_owned();
+            if let Some(ref interpreter) = script.interpreter {
+                description += &format!(" with {}", interpreter);
+            }
+            if let Some(ref timeout) = script.timeout {
+                description += &format!(", {} timeout", timeout);
+            }
+            description
+        };
+        let mut hooks = Vec::new();
+        if let Some(ref script) = self.definition.init {
+            let hook = if self.init_once() {
+                "init once"
+            } else {
+                "init"
+            };
+            hooks.push((hook, describe(script, INIT_SCRIPT)));
+        }
+        if let Some(ref script) = self.definition.cleanup {
+            hooks.push(("cleanup", describe(script, CLEANUP_SCRIPT)));
+        }
+        if let Some(command) = self.reload_command(catalog) {
+            hooks.push(("reload", command.to_owned()));
+        }
+        if let Some(ref command) = self.definition.probe {
+            hooks.push(("probe", command.clone()));
+        }
+        hooks
+    }
+
+    /// Resources left out because the program they configure (`if_app`) is missing, with that
+    /// program, sorted by resource
+    pub fn skipped(&self) -> &[(String, String)] {
+        &self.skipped
+    }
+
+    /// Resources left out on this machine because one of their conditions doesn't hold, with that
+    /// condition, sorted by resource
+    pub fn left_out(&self) -> &[(String, &'static str)] {
+        &self.left_out
+    }
+
+    /// System locations of the resources left out on this machine, which links from an install
+    /// before their condition changed may still take
+    pub fn left_out_targets(&self) -> Vec<PathBuf> {
+        self.left_out_resources
+            .values()
+            .map(|definition| target::resolve(&definition.target))
+            .collect()
+    }
+
+    /// The config the module is installed with on this machine: merged with its includes,
+    /// without the resources left out here, and with the defaults and global settings filled in
+    pub fn effective_config(&self, catalog: &Catalog) -> toml::Value {
+        let mut definition = self.definition.clone();
+        definition.reload = self.reload_command(catalog).map(str::to_owned);
+        let fill = |script: &mut Option<ScriptDef>, default: &str| {
+            if let Some(ref mut script) = *script {
+                script.script.get_or_insert_with(|| default.to_owned());
+                if script.timeout.is_none() {
+                    script.timeout = self
+                        .script_timeout
+                        .map(|timeout| format!("{}s", timeout.as_secs()));
+                }
+            }
+        };
+        fill(&mut definition.init, INIT_SCRIPT);
+        fill(&mut definition.cleanup, CLEANUP_SCRIPT);
+        for resource in definition.resources.values_mut() {
+            if !resource.encrypted {
+                resource.relative_link.get_or_insert(self.relative_links);
+            }
+        }
+        toml::Value::try_from(&definition).expect("module configs can be serialized")
+    }
+
+    /// Resources and the system locations they install to, sorted by resource
+    pub fn resources(&self) -> Vec<(&str, PathBuf)> {
+        let mut resources: Vec<_> = self
+            .definition
+            .resources
+            .iter()
+            .map(|(resource, definition)| (resource.as_str(), target::resolve(&definition.target)))
+            .collect();
+        resources.sort();
+        resources
+    }
+
+    /// Each resource's source, target, mode and status, sorted by resource
+    pub fn resource_states(&self) -> Vec<ResourceState> {
+        let mut states: Vec<_> = self
+            .definition
+            .resources
+            .iter()
+            .map(|(resource, definition)| {
+                let source = self.path.join(resource);
+                let target = target::resolve(&definition.target);
+                let mode = if definition.encrypted {
+                    Mode::Copy
+                } else if definition.relative_link.unwrap_or(self.relative_links) {
+                    Mode::RelativeSymlink
+                } else {
+                    Mode::Symlink
+                };
+                let status = if self.is_installed(resource, definition) {
+                    ResourceStatus::Installed
+                } else if fs::symlink_metadata(&target).is_err() {
+                    ResourceStatus::Missing
+                } else {
+                    match link_destination(&target) {
+                        Ok(actual) if !definition.encrypted => ResourceStatus::Elsewhere(actual),
+                        _ => ResourceStatus::Foreign,
+                    }
+                };
+                ResourceState {
+                    resource: resource.clone(),
+                    source,
+                    target,
+                    mode,
+                    status,
+                }
+            })
+            .collect();
+        states.sort_by(|a, b| a.resource.cmp(&b.resource));
+        states
+    }
+
+    /// Checksums of the decrypted copies of encrypted resources that are installed, by target
+    pub fn checksums(&self) -> BTreeMap<PathBuf, String> {
+        self.definition
+            .resources
+            .iter()
+            .filter(|&(resource, definition)| {
+                definition.encrypted && self.is_installed(resource, definition)
+            })
+            .filter_map(|(_, definition)| {
+                let system_file = target::resolve(&definition.target);
+                checksum(&system_file).ok().map(|sum| (system_file, sum))
+            })
+            .collect()
+    }
+
+    /// Decrypted copies changed since they were installed with the `recorded` checksums, and
+    /// their resources, sorted by resource. Copies without a recorded checksum are left out.
+    pub fn drifted(&self, recorded: &BTreeMap<PathBuf, String>) -> Vec<(&str, PathBuf)> {
+        let current = self.checksums();
+        let mut drifted: Vec<_> = self
+            .definition
+            .resources
+            .iter()
+            .map(|(resource, definition)| (resource.as_str(), target::resolve(&definition.target)))
+            .filter(|(_, system_file)| {
+                match (recorded.get(system_file), current.get(system_file)) {
+                    (Some(recorded), Some(current)) => recorded != current,
+                    _ => false,
+                }
+            })
+            .collect();
+        drifted.sort();
+        drifted
+    }
+
+    /// Replace the decrypted copy of a resource with a fresh one, discarding changes made to it
+    pub fn overwrite_copy(&self, resource: &str) -> Result<(), ModuleError> {
+        let system_file = target::resolve(&self.definition.resources[resource].target);
+        info!(
+            "Module {}: Overwrite {}",
+            self.name(),
+            system_file.display()
+        );
+        self.shred(&system_file)?;
+        self.decrypt(&self.path.join(resource), &system_file)
+    }
+
+    /// Encrypt the decrypted copy of a resource back into the module, keeping changes made to
+    /// it. `age` encrypts to the recipient of `$MODMAN_AGE_IDENTITY`, `gpg` to the keys the
+    /// resource was encrypted to (or the default key, if they are hidden).
+    pub fn pull_copy(&self, resource: &str) -> Result<(), ModuleError> {
+        let system_file = target::resolve(&self.definition.resources[resource].target);
+        let resource = self.path.join(resource);
+        info!(
+            "Module {}: Pull {} into {}",
+            self.name(),
+            system_file.display(),
+            resource.display()
+        );
+        let mut pulled = resource.clone().into_os_string();
+        pulled.push(".");
+        pulled.push(PULL_SUFFIX);
+        let pulled = PathBuf::from(pulled);
+        self.encrypt(&system_file, &pulled, &resource)?;
+        fs::rename(&pulled, &resource).context(self.name())
+    }
+
+    fn read_dir<P: AsRef<Path>>(module_dir: P) -> Result<fs::ReadDir, ModuleError> {
+        fs::read_dir(module_dir).map_err(|_| ModuleError::Directory)
+    }
+
+    fn verify_module_creation<P: AsRef<Path>>(path: P) -> Result<(), PathBuf> {
+        for p in path.as_ref().ancestors() {
+            if p.exists() && !p.is_dir() {
+                return Err(p.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    /// System locations the module installs to
+    pub fn targets(&self) -> Vec<PathBuf> {
+        self.definition
+            .resources
+            .values()
+            .map(|definition| target::resolve(&definition.target))
+            .collect()
+    }
+
+    /// Check whether a resource is installed: symlinked, or decrypted by the module if it is
+    /// encrypted
+    fn is_installed(&self, resource: &str, definition: &ResourceDef) -> bool {
+        let system_file = target::resolve(&definition.target);
+        if definition.encrypted {
+            if !self.copies.borrow().contains(&system_file) {
+                return false;
+            }
+            return fs::symlink_metadata(system_file)
+                .map(|metadata| metadata.is_file())
+                .unwrap_or(false);
+        }
+        link_destination(&system_file)
+            .map(|actual_path| actual_path == self.path.join(resource))
+            .unwrap_or(false)
+    }
+
+    /// Where the files the module's scripts generate are
+    pub fn artifacts(&self) -> Vec<PathBuf> {
+        self.definition
+            .artifacts
+            .iter()
+            .map(|artifact| target::resolve(artifact))
+            .collect()
+    }
+
+    /// Delete the module's generated files, returning those that existed
+    pub fn remove_artifacts(&self) -> Result<Vec<PathBuf>, ModuleError> {
+        let mut removed = Vec::new();
+        for artifact in self.artifacts() {
+            let metadata = match fs::symlink_metadata(&artifact) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            info!("Module {}: Remove {}", self.name(), artifact.display());
+            if metadata.is_dir() {
+                fs::remove_dir_all(&artifact)
+            } else {
+                fs::remove_file(&artifact)
+            }
+            .context(self.name())?;
+            removed.push(artifact);
+        }
+        Ok(removed)
+    }
+
+    /// Existing files at the module's targets that aren't its own links or decrypted copies,
+    /// which a forced uninstall deletes
+    pub fn foreign_targets(&self) -> Vec<PathBuf> {
+        self.foreign_files()
+            .into_iter()
+            .map(|(_, system_file)| system_file)
+            .collect()
+    }
+
+    /// Resources whose target is taken by a file the module didn't link, with that target, sorted
+    /// by resource
+    pub fn foreign_files(&self) -> Vec<(&str, PathBuf)> {
+        let mut foreign: Vec<_> = self
+            .definition
+            .resources
+            .iter()
+            .filter(|&(resource, definition)| {
+                target::resolve(&definition.target).exists()
+                    && !self.is_installed(resource, definition)
+            })
+            .map(|(resource, definition)| (resource.as_str(), target::resolve(&definition.target)))
+            .collect();
+        foreign.sort();
+        foreign
+    }
+
+    /// Deal with a file in the way of a resource's link, so installing can go ahead (or, for
+    /// `Keep`, has to leave the resource out with `exclude_targets`)
+    pub fn resolve_foreign(
+        &self,
+        resource: &str,
+        resolution: Resolution,
+    ) -> Result<(), ModuleError> {
+        let definition = match self.definition.resources.get(resource) {
+            Some(definition) => definition,
+            None => return Ok(()),
+        };
+        let system_file = target::resolve(&definition.target);
+        let remove = |path: &Path| {
+            if fs::symlink_metadata(path)?.is_dir() {
+                fs::remove_dir_all(path)
+            } else {
+                fs::remove_file(path)
+            }
+        };
+        match resolution {
+            Resolution::Keep => Ok(()),
+            Resolution::Overwrite => remove(&system_file),
+            Resolution::Backup => {
+                let name = file_name_to_string(&system_file);
+                let backup = (1..)
+                    .map(|i| match i {
+                        1 => system_file.with_file_name(format!("{}.{}", name, BACKUP_SUFFIX)),
+                        _ => {
+                            system_file.with_file_name(format!("{}.{}.{}", name, BACKUP_SUFFIX, i))
+                        }
+                    })
+                    .find(|backup| fs::symlink_metadata(backup).is_err())
+                    .unwrap();
+                info!(
+                    "Module {}: Moved {} to {}",
+                    self.name(),
+                    system_file.display(),
+                    backup.display()
+                );
+                move_tree(&system_file, &backup)
+            }
+            Resolution::Adopt => {
+                let resource = self.path.join(resource);
+                remove(&resource).and_then(|()| move_tree(&system_file, &resource))
+            }
+        }
+        .context(self.name())
+    }
+
+    /// Resources linked outside the target root, to places the user can't write (e.g. `/etc`),
+    /// with their targets, sorted by resource. Encrypted resources aren't, as they can't be
+    /// decrypted there.
+    pub fn privileged_resources(&self) -> Vec<(&str, PathBuf)> {
+        let root = target::root();
+        self.resources()
+            .into_iter()
+            .filter(|&(resource, ref system_file)| {
+                !self.definition.resources[resource].encrypted
+                    && !target::normalize(system_file).starts_with(&root)
+                    && needs_privilege(system_file)
+            })
+            .collect()
+    }
+
+    /// Link and remove the privileged resources with sudo
+    pub fn allow_escalation(&mut self) {
+        self.escalate = true;
+    }
+
+    /// Stop scripts that don't set their own timeout after `timeout`, if there is one
+    pub fn set_script_timeout(&mut self, timeout: Option<Duration>) {
+        self.script_timeout = timeout;
+    }
+
+    /// Treat the files at `copies`, recorded when they were decrypted, as the module's own
+    pub fn set_copies<I: IntoIterator<Item = PathBuf>>(&mut self, copies: I) {
+        self.copies = RefCell::new(copies.into_iter().collect());
+    }
+
+    /// Remove the `links` recorded for the module on uninstall too, even if its definition no
+    /// longer makes them
+    pub fn set_links(&mut self, links: &[PathBuf]) {
+        self.links = links.to_vec();
+    }
+
+    /// Create relative links for resources that don't set `relative_link` themselves
+    pub fn prefer_relative_links(&mut self, relative: bool) {
+        self.relative_links = relative;
+    }
+
+    // What the link to a resource holds: its path, or the way there from the link's directory
+    fn link_contents(
+        &self,
+        definition: &ResourceDef,
+        resource: &Path,
+        system_file: &Path,
+    ) -> PathBuf {
+        if definition.relative_link.unwrap_or(self.relative_links) {
+            relative_link(system_file, resource)
+        } else {
+            resource.to_owned()
+        }
+    }
+
+    // Whether the target has to be changed with sudo
+    fn escalates(&self, definition: &ResourceDef, system_file: &Path) -> bool {
+        if !self.escalate || definition.encrypted {
+            return false;
+        }
+        let escalates = needs_privilege(system_file);
+        trace_module!(
+            self.name(),
+            "{} {} sudo, as the closest existing parent is {}writable",
+            system_file.display(),
+            if escalates { "needs" } else { "doesn't need" },
+            if escalates { "not " } else { "" }
+        );
+        escalates
+    }
+
+    // Run a command with sudo, for a target the user can't write
+    fn sudo(&self, program: &str, args: &[&Path]) -> Result<(), ModuleError> {
+        let status = process::Command::new("sudo")
+            .arg(program)
+            .args(args)
+            .status()
+            .context(self.name())?;
+        if !status.success() {
+            return Err(ModuleError::Exec(
+                self.name().to_owned(),
+                format!("sudo {}", program),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Leave out the resources installed to any of `targets`, e.g. because the files there are
+    /// to be kept
+    pub fn exclude_targets(&mut self, targets: &[PathBuf]) {
+        self.definition
+            .resources
+            .retain(|_, definition| !targets.contains(&target::resolve(&definition.target)));
+    }
+
+    /// Files in the way of the module's links, which a forced install deletes: foreign files at
+    /// its targets, and files where a target's parent directories should be
+    pub fn obstructions(&self) -> Vec<PathBuf> {
+        let mut obstructions = self.foreign_targets();
+        for definition in self.definition.resources.values() {
+            let system_file = target::resolve(&definition.target);
+            if dangling_link(&system_file).is_some() {
+                obstructions.push(system_file);
+            } else if !system_file.exists() {
+                if let Err(path) = Module::verify_module_creation(&system_file) {
+                    obstructions.push(path);
+                }
+            }
+        }
+        obstructions.sort();
+        obstructions.dedup();
+        obstructions
+    }
+
+    /// Check which resources are installed to their system location
+    pub fn status(&self) -> Status {
+        let linked = self
+            .definition
+            .resources
+            .iter()
+            .filter(|&(resource, definition)| self.is_installed(resource, definition))
+            .count();
+
+        if linked == self.definition.resources.len() {
+            Status::Installed
+        } else if linked == 0 {
+            Status::NotInstalled
+        } else {
+            Status::Partial
+        }
+    }
+
+    /// Check that no two modules install to the same system location
+    pub fn check_conflicts(modules: &[Module]) -> Result<(), ModuleError> {
+        let mut owners: HashMap<PathBuf, &str> = HashMap::new();
+        for module in modules {
+            for target in module.targets() {
+                if let Some(owner) = owners.get(&target) {
+                    return Err(ModuleError::Conflict(
+                        owner.to_string(),
+                        module.name().to_owned(),
+                        target.display().to_string(),
+                    ));
+                }
+                owners.insert(target, module.name());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn list<P: AsRef<Path>>(
+        module_dir: P,
+    ) -> Result<Vec<Result<Module, ModuleError>>, ModuleError> {
+        let iter = Module::read_dir(module_dir)?;
+        let mut modules: Vec<Result<Module, ModuleError>> = match iter.size_hint() {
+            (_, Some(n)) => Vec::with_capacity(n),
+            _ => Vec::new(),
+        };
+
+        for entry in iter {
+            let path = entry.unwrap().path();
+            let name = file_name_to_string(&path);
+            // Other hidden directories without a config hold configs shared through `include`
+            let hidden = VCS_DIRS.contains(&name.as_str())
+                || (name.starts_with('.') && !path.join(CONFIG_FILE).exists());
+            let reserved = path.ends_with(ARCHIVE_DIR) || path.ends_with(HOOKS_DIR);
+            if reserved && path.join(CONFIG_FILE).exists() {
+                warn!(
+                    "{} looks like a module, but {} is reserved for modman; Rename it to use it",
+                    path.display(),
+                    name
+                );
+            }
+            if path.is_dir() && !hidden && !reserved {
+                modules.push(Module::new(path))
+            }
+        }
+
+        Ok(modules)
+    }
+
+    /// Create a module in `module_dir` by moving existing system files into it, or creating
+    /// empty ones for targets that don't exist yet. Install it afterwards to link them back.
+    pub fn create<P: AsRef<Path>>(
+        module_dir: P,
+        name: &str,
+        app: Option<&str>,
+        targets: &[&str],
+    ) -> Result<Module, ModuleError> {
+        let path = module_dir.as_ref().join(name);
+        if path.exists() {
+            return Err(ModuleError::Exists(
+                name.to_owned(),
+                path.display().to_string(),
+            ));
+        }
+        fs::create_dir_all(&path).context(name)?;
+
+        let mut config = format!("version = {}\n", definition::SCHEMA_VERSION);
+        if let Some(app) = app {
+            config.push_str(&format!("app = {}\n", toml::Value::String(app.to_owned())));
+        }
+        config.push('\n');
+        config.push_str("[resources]\n");
+        let mut resources = HashSet::new();
+        for target in targets {
+            let resource = resource_name(target, &mut resources);
+            let system_file = target::resolve(target);
+            if fs::symlink_metadata(&system_file).is_ok() {
+                move_tree(&system_file, &path.join(&resource)).context(name)?;
+            } else if target::is_xdg(target) && !resource.contains('.') {
+                // Programs with an XDG directory usually keep several files in it
+                fs::create_dir(path.join(&resource)).context(name)?;
+            } else {
+                fs::write(path.join(&resource), "").context(name)?;
+            }
+            config.push_str(&format!(
+                "{} = {}\n",
+                toml::Value::String(resource),
+                toml::Value::String(target.to_string())
+            ));
+        }
+        fs::write(path.join(CONFIG_FILE), config).context(name)?;
+        Module::new(path)
+    }
+
+    /// Create a module in `module_dir` from a GNU Stow package, a directory mirroring the home
+    /// directory, by copying its files in. Directories shared by many programs, such as `.config`,
+    /// are descended into rather than linked whole. With `dotfiles`, a `dot-` prefix stands for a
+    /// leading dot, as with `stow --dotfiles`.
+    pub fn import_stow<P: AsRef<Path>, Q: AsRef<Path>>(
+        module_dir: P,
+        package: Q,
+        name: &str,
+        dotfiles: bool,
+    ) -> Result<Module, ModuleError> {
+        let path = module_dir.as_ref().join(name);
+        if path.exists() {
+            return Err(ModuleError::Exists(
+                name.to_owned(),
+                path.display().to_string(),
+            ));
+        }
+        let mut entries = Vec::new();
+        stow_entries(package.as_ref(), "", dotfiles, &mut entries).context(name)?;
+        entries.sort();
+        fs::create_dir_all(&path).context(name)?;
+
+        let mut config = format!("version = {}\n\n[resources]\n", definition::SCHEMA_VERSION);
+        let mut resources = HashSet::new();
+        for (relative, source) in entries {
+            let target = target::from_home(&relative);
+            let resource = resource_name(&target, &mut resources);
+            copy_tree(&source, &path.join(&resource)).context(name)?;
+            config.push_str(&format!(
+                "{} = {}\n",
+                toml::Value::String(resource),
+                toml::Value::String(target)
+            ));
+        }
+        fs::write(path.join(CONFIG_FILE), config).context(name)?;
+        Module::new(path)
+    }
+
+    /// Command to run after the module's links change, falling back to the catalog's reload
+    /// command for the module's app
+    pub fn reload_command<'a>(&'a self, catalog: &'a Catalog) -> Option<&'a str> {
+        self.definition.reload.as_deref().or_else(|| {
+            self.definition
+                .app
+                .as_ref()
+                .and_then(|app| catalog.get(app))
+                .and_then(|app| app.reload.as_deref())
+        })
+    }
+
+    /// Run the reload command, if there is one
+    pub fn reload(&self, catalog: &Catalog) -> Result<(), ModuleError> {
+        let command = match self.reload_command(catalog) {
+            Some(command) => command,
+            None => return Ok(()),
+        };
+        info!("Module {}: Reload with {}", self.name(), command);
+        let mut reload = process::Command::new("sh");
+        self.pin_env(&mut reload);
+        let status = reload
+            .arg("-c")
+            .arg(command)
+            .status()
+            .context(self.name())?;
+        if !status.success() {
+            return Err(ModuleError::Exec(
+                self.name().to_owned(),
+                command.to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Run the module's probe, if it has one, to check its program still starts with the
+    /// installed config. Its output is shown if it fails or doesn't finish in time.
+    pub fn probe(&self) -> Result<(), ModuleError> {
+        let command = match self.definition.probe {
+            Some(ref command) => command,
+            None => return Ok(()),
+        };
+        info!("Module {}: Probe with {}", self.name(), command);
+        let mut probe = process::Command::new("sh");
+        self.pin_env(&mut probe);
+        let mut child = probe
+            .arg("-c")
+            .arg(command)
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .context(self.name())?;
+        let receiver = output_lines(&mut child);
+
+        let start = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait().context(self.name())? {
+                break Some(status);
+            }
+            if start.elapsed() > PROBE_TIMEOUT {
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+            thread::sleep(PROBE_INTERVAL);
+        };
+        // Programs the probe started in the background may keep its output open, so stop once
+        // it goes quiet
+        let show_output = || {
+            while let Ok(line) = receiver.recv_timeout(PROBE_INTERVAL) {
+                warn!("{} | {}", self.name(), line);
+            }
+        };
+        match status {
+            Some(status) if status.success() => Ok(()),
+            Some(_) => {
+                show_output();
+                Err(ModuleError::Exec(self.name().to_owned(), command.clone()))
+            }
+            None => {
+                show_output();
+                Err(ModuleError::Timeout(
+                    self.name().to_owned(),
+                    format!("probe {}", command),
+                ))
+            }
+        }
+    }
+
+    /// Archived modules in `module_dir` and why they were archived
+    pub fn list_archived<P: AsRef<Path>>(module_dir: P) -> Vec<(String, String)> {
+        let archive = module_dir.as_ref().join(ARCHIVE_DIR);
+        let mut archived: Vec<(String, String)> = match fs::read_dir(archive) {
+            Ok(iter) => iter
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .map(|entry| {
+                    let reason = fs::read_to_string(entry.path().join(ARCHIVE_REASON_FILE))
+                        .unwrap_or_default();
+                    (file_name_to_string(entry.path()), reason.trim().to_owned())
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        archived.sort();
+        archived
+    }
+
+    /// Move the module into the archive, out of the way of discovery. It should be uninstalled
+    /// first.
+    pub fn archive(&self, reason: &str) -> Result<(), ModuleError> {
+        let archive = self.path.parent().unwrap().join(ARCHIVE_DIR);
+        let archived = archive.join(self.name());
+        if archived.exists() {
+            return Err(ModuleError::Exists(
+                self.name().to_owned(),
+                archived.display().to_string(),
+            ));
+        }
+        fs::create_dir_all(&archive).context(self.name())?;
+        fs::rename(&self.path, &archived).context(self.name())?;
+        fs::write(archived.join(ARCHIVE_REASON_FILE), reason).context(self.name())
+    }
+
+    /// Move an archived module back into `module_dir`
+    pub fn unarchive<P: AsRef<Path>>(module_dir: P, name: &str) -> Result<Module, ModuleError> {
+        let archived = module_dir.as_ref().join(ARCHIVE_DIR).join(name);
+        let path = module_dir.as_ref().join(name);
+        if path.exists() {
+            return Err(ModuleError::Exists(
+                name.to_owned(),
+                path.display().to_string(),
+            ));
+        }
+        fs::rename(&archived, &path).context(name)?;
+        let reason = path.join(ARCHIVE_REASON_FILE);
+        if reason.exists() {
+            fs::remove_file(reason).context(name)?;
+        }
+        Module::new(path)
+    }
+
+    /// Rename the module, pointing its links, and those of `previous_links` into it, at the new
+    /// directory, so it stays installed. Saved script logs are renamed with it.
+    pub fn rename(
+        &self,
+        new_name: &str,
+        previous_links: &[PathBuf],
+    ) -> Result<Module, ModuleError> {
+        let path = self.path.parent().unwrap().join(new_name);
+        if path.exists() {
+            return Err(ModuleError::Exists(
+                new_name.to_owned(),
+                path.display().to_string(),
+            ));
+        }
+        let mut links: Vec<(PathBuf, PathBuf)> = self
+            .targets()
+            .into_iter()
+            .chain(previous_links.iter().cloned())
+            .filter_map(|link| {
+                let destination = link_destination(&link).ok()?;
+                let resource = destination.strip_prefix(&self.path).ok()?.to_owned();
+                Some((link, resource))
+            })
+            .collect();
+        links.sort();
+        links.dedup();
+
+        fs::rename(&self.path, &path).context(self.name())?;
+        for (link, resource) in links {
+            let relative = fs::read_link(&link).is_ok_and(|contents| contents.is_relative());
+            let resource = path.join(resource);
+            let contents = if relative {
+                relative_link(&link, &resource)
+            } else {
+                resource
+            };
+            fs::remove_file(&link)
+                .and_then(|()| platform::symlink(&contents, &link))
+                .context(self.name())?;
+        }
+
+        let logs = script_log_dir().and_then(|dir| fs::read_dir(dir).ok());
+        for entry in logs.into_iter().flatten().filter_map(Result::ok) {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let kind = file_name
+                .strip_prefix(self.name())
+                .and_then(|rest| rest.strip_prefix('-'))
+                .filter(|kind| !kind.contains('-'));
+            if let Some(kind) = kind {
+                let renamed = entry
+                    .path()
+                    .with_file_name(format!("{}-{}", new_name, kind));
+                fs::rename(entry.path(), renamed).context(self.name())?;
+            }
+        }
+        Module::new(path)
+    }
+
+    /// Rewrite a module's config to the newest schema. Returns whether it needed rewriting.
+    pub fn migrate<P: AsRef<Path>>(module_path: P) -> Result<bool, ModuleError> {
+        let name = file_name_to_string(module_path.as_ref());
+        let path = module_path.as_ref().join(CONFIG_FILE);
+        let source = fs::read_to_string(&path).context(&name)?;
+        match definition::migrate(&source).map_err(|err| ModuleError::Parse(name.clone(), err))? {
+            Some(migrated) => {
+                fs::write(&path, migrated).context(name)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn missing_packages(&self) -> Result<Vec<&str>, ModuleError> {
+        let mut missing = Vec::new();
+        for package in &self.definition.packages {
+            let status = process::Command::new("pacman")
+                .arg("-Q")
+                .arg(package)
+                .stdout(process::Stdio::null())
+                .stderr(process::Stdio::null())
+                .status()
+                .context(self.name())?;
+            if !status.success() {
+                missing.push(package.as_str());
+            }
+        }
+        Ok(missing)
+    }
+
+    fn check_packages(&self, install_missing: bool) -> Result<(), ModuleError> {
+        let missing = self.missing_packages()?;
+        if !self.definition.packages.is_empty() {
+            trace_module!(
+                self.name(),
+                "Packages {} required, {} missing",
+                self.definition.packages.join(", "),
+                missing.len()
+            );
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        if !install_missing {
+            return Err(ModuleError::MissingPackage(
+                self.name().to_owned(),
+                missing.join(", "),
+            ));
+        }
+
+        info!(
+            "Module {}: Install packages {}",
+            self.name(),
+            missing.join(", ")
+        );
+
+        let status = process::Command::new("sudo")
+            .arg("pacman")
+            .arg("-S")
+            .arg("--needed")
+            .args(&missing)
+            .status()
+            .context(self.name())?;
+
+        if !status.success() {
+            return Err(ModuleError::MissingPackage(
+                self.name().to_owned(),
+                missing.join(", "),
+            ));
+        }
+        Ok(())
+    }
+
+    fn systemctl(&self, action: &str) -> Result<(), ModuleError> {
+        let units = &self.definition.systemd.units;
+        if units.is_empty() {
+            return Ok(());
+        }
+
+        info!(
+            "Module {}: systemctl --user {} --now {}",
+            self.name(),
+            action,
+            units.join(" ")
+        );
+
+        let status = process::Command::new("systemctl")
+            .arg("--user")
+            .arg(action)
+            .arg("--now")
+            .args(units)
+            .status()
+            .context(self.name())?;
+
+        if !status.success() {
+            return Err(ModuleError::Exec(
+                self.name().to_owned(),
+                format!("systemctl --user {} --now {}", action, units.join(" ")),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Block until the module's `wait_for` unit is active and its address accepts connections
+    fn wait_for(&self, cancel: &Cancel) -> Result<(), ModuleError> {
+        let wait = match self.definition.wait_for {
+            Some(ref wait) => wait,
+            None => return Ok(()),
+        };
+        // Checked when the config was parsed
+        let timeout = parse_duration(&wait.timeout).unwrap();
+        let what = wait
+            .unit
+            .iter()
+            .chain(wait.tcp.iter())
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" and ");
+        info!("Module {}: Wait for {}", self.name(), what);
+
+        let start = Instant::now();
+        loop {
+            let unit_ready = wait.unit.as_ref().is_none_or(|unit| {
+                process::Command::new("systemctl")
+                    .arg("is-active")
+                    .arg("--quiet")
+                    .arg(unit)
+                    .status()
+                    .is_ok_and(|status| status.success())
+            });
+            let tcp_ready = wait.tcp.as_ref().is_none_or(|address| {
+                address.to_socket_addrs().is_ok_and(|mut addrs| {
+                    addrs.any(|addr| TcpStream::connect_timeout(&addr, WAIT_INTERVAL).is_ok())
+                })
+            });
+            if unit_ready && tcp_ready {
+                return Ok(());
+            }
+            self.check_cancelled(cancel)?;
+            if start.elapsed() >= timeout {
+                return Err(ModuleError::Timeout(self.name().to_owned(), what));
+            }
+            thread::sleep(WAIT_INTERVAL);
+        }
+    }
+
+    fn check_cancelled(&self, cancel: &Cancel) -> Result<(), ModuleError> {
+        if cancel.is_cancelled() {
+            return Err(ModuleError::Cancelled(self.name().to_owned()));
+        }
+        Ok(())
+    }
+
+    /// Run a script, directly or through its interpreter
+    fn run_script(&self, kind: &str, script: &ScriptDef, default: &str) -> Result<(), ModuleError> {
+        // Absolute, as the script doesn't run in modman's working directory
+        let module_dir = env::current_dir()
+            .map(|cwd| cwd.join(&self.path))
+            .unwrap_or_else(|_| self.path.clone());
+        let path = module_dir.join(script.path(default));
+
+        info!("Module {}: Execute {} script", self.name(), kind);
+
+        let mut command = match script.interpreter {
+            Some(ref interpreter) => {
+                let mut command = process::Command::new(interpreter);
+                command.arg(&path);
+                command
             }
+            None => process::Command::new(&path),
+        };
+        self.pin_env(&mut command);
+        command.current_dir(script.workdir(&module_dir));
+        let timeout = script
+            .timeout
+            .as_ref()
+            .and_then(|timeout| parse_duration(timeout))
+            .or(self.script_timeout);
+        // Whatever the script starts is killed with it
+        if timeout.is_some() {
+            platform::own_process_group(&mut command);
         }
+        let mut child = command
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .context(self.name())?;
 
-        if self.cleanup {
-            let cleanup_script_path = module_path.as_ref().join(CLEANUP_SCRIPT);
-            if !cleanup_script_path.exists() || !check_permissions(
-                cleanup_script_path.metadata().unwrap().permissions().mode(),
-                PERMISSIONS_RX,
-            ) {
-                return Err(ModuleError::Script(
-                    file_name_to_string(module_path.as_ref()),
-                    "cleanup".to_owned(),
-                ));
+        let receiver = output_lines(&mut child);
+
+        // Output is only shown as it happens when verbose, otherwise only if the script fails
+        let verbose = log_enabled!(Level::Info);
+        let mut output = Vec::new();
+        let mut record = |line: String| {
+            info!("{} | {}", self.name(), line);
+            output.push(line);
+        };
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let status = loop {
+            match receiver.recv_timeout(SCRIPT_INTERVAL) {
+                Ok(line) => record(line),
+                Err(mpsc::RecvTimeoutError::Disconnected) => thread::sleep(SCRIPT_INTERVAL),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
             }
-        }
+            // Done when the script exits, not when its output closes
+            if let Some(status) = child.try_wait().context(self.name())? {
+                let drained = Instant::now() + SCRIPT_DRAIN;
+                while Instant::now() < drained {
+                    match receiver.recv_timeout(SCRIPT_INTERVAL) {
+                        Ok(line) => record(line),
+                        Err(_) => break,
+                    }
+                }
+                break Some(status);
+            }
+            if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+                platform::kill_group(&mut child);
+                let _ = child.wait();
+                break None;
+            }
+        };
 
-        for resource in self.resources.keys() {
-            let resource_path = module_path.as_ref().join(resource);
-            if !resource_path.exists() || !check_permissions(
-                resource_path.metadata().unwrap().permissions().mode(),
-                PERMISSIONS_R,
-            ) {
-                return Err(ModuleError::Resource(
-                    file_name_to_string(module_path.as_ref()),
-                    resource.to_owned(),
+        let status = match status {
+            Some(status) => status,
+            None => {
+                if !verbose {
+                    for line in &output {
+                        warn!("{} | {}", self.name(), line);
+                    }
+                }
+                self.save_script_log(kind, &output);
+                return Err(ModuleError::Timeout(
+                    self.name().to_owned(),
+                    format!("{} script {}", kind, script.path(default)),
                 ));
             }
+        };
+        if !status.success() {
+            if !verbose {
+                for line in &output {
+                    warn!("{} | {}", self.name(), line);
+                }
+            }
+            self.save_script_log(kind, &output);
+            return Err(ModuleError::Exec(
+                self.name().to_owned(),
+                script.path(default).to_owned(),
+            ));
         }
         Ok(())
     }
-}
-
-#[derive(Debug, Fail)]
-pub enum ModuleError {
-    #[fail(
-        display = "Module {}: Resource {} not found or incorrect permissions",
-        _0,
-        _1
-    )]
-    Resource(String, String),
-    #[fail(
-        display = "Module {}: {} script not found or has incorrect permissions",
-        _0,
-        _1
-    )]
-    Script(String, String),
-    #[fail(
-        display = "Module {}: {} script returned non-zero code",
-        _0,
-        _1
-    )]
-    Exec(String, String),
-    #[fail(
-        display = "Module {}: Existing file {} found; Use -f to force overwrite",
-        _0,
-        _1
-    )]
-    Install(String, String),
-    #[fail(
-        display = "Module {}: {} is not a directory; Use -f to force overwrite",
-        _0,
-        _1
-    )]
-    InstallPath(String, String),
-    #[fail(
-        display = "Module {}: {} is not a symlink or does not point to correct resource; Use -f to force deletion",
-        _0,
-        _1
-    )]
-    Uninstall(String, String),
-    #[fail(display = "Module {}: {}", _0, _1)]
-    Parse(String, toml::de::Error),
-    #[fail(display = "Module {}: {}", _0, _1)]
-    IO(String, io::Error),
-    #[fail(display = "Module directory not found or has invalid permissions")]
-    Directory,
-}
-
-#[derive(Debug)]
-pub struct Module {
-    path: PathBuf,
-    definition: ModuleDef,
-}
 
-impl fmt::Display for Module {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &self.definition.description {
-            None => write!(f, "{}", self.name()),
-            Some(desc) => write!(f, "{} - {}", self.name(), desc),
+    // Give a command the environment the module pins, so it behaves the same on every machine
+    fn pin_env(&self, command: &mut process::Command) {
+        let script_env = &self.definition.script_env;
+        if let Some(ref locale) = script_env.locale {
+            command.env("LANG", locale).env("LC_ALL", locale);
+        }
+        if !script_env.path.is_empty() {
+            let dirs = script_env.path.iter().map(|dir| self.path.join(dir));
+            let inherited = env::var_os("PATH").unwrap_or_default();
+            match env::join_paths(dirs.chain(env::split_paths(&inherited))) {
+                Ok(path) => {
+                    command.env("PATH", path);
+                }
+                Err(err) => warn!("Module {}: script_env path ignored; {}", self.name(), err),
+            }
+        }
+        if let Some(umask) = script_env.umask() {
+            platform::set_umask(command, umask);
         }
     }
-}
 
-impl Module {
-    pub fn new<P: AsRef<Path>>(module_path: P) -> Result<Module, ModuleError> {
-        let module_def = ModuleDef::new(module_path.as_ref())?;
-        Ok(Module {
-            path: module_path.as_ref().to_path_buf(),
-            definition: module_def,
-        })
+    /// Keep the output of a failed script for later, replacing that of the previous failure
+    fn save_script_log(&self, kind: &str, output: &[String]) {
+        let dir = match script_log_dir() {
+            Some(dir) => dir,
+            None => return,
+        };
+        let path = dir.join(format!("{}-{}.log", self.name(), kind));
+        let mut contents = output.join("\n");
+        contents.push('\n');
+        match fs::create_dir_all(&dir).and_then(|()| fs::write(&path, contents)) {
+            Ok(()) => warn!(
+                "Module {}: {} script output saved to {}",
+                self.name(),
+                kind,
+                path.display()
+            ),
+            Err(err) => warn!(
+                "Module {}: Can't save {} script output: {}",
+                self.name(),
+                kind,
+                err
+            ),
+        }
     }
 
-    pub fn name(&self) -> &str {
-        self.path.file_name().unwrap().to_str().unwrap()
-    }
+    /// Decrypt an encrypted resource into a new file only readable by the user
+    fn decrypt(&self, resource: &Path, system_file: &Path) -> Result<(), ModuleError> {
+        let mut command = if resource.extension().is_some_and(|ext| ext == AGE_EXTENSION) {
+            let mut command = process::Command::new("age");
+            command.arg("--decrypt");
+            if let Some(identity) = env::var_os(AGE_IDENTITY_ENV) {
+                command.arg("--identity").arg(identity);
+            }
+            command
+        } else {
+            let mut command = process::Command::new("gpg");
+            command.arg("--quiet").arg("--decrypt");
+            command
+        };
+        let program = command.get_program().to_string_lossy().into_owned();
 
-    fn read_dir<P: AsRef<Path>>(module_dir: P) -> Result<fs::ReadDir, ModuleError> {
-        fs::read_dir(module_dir).map_err(|_| ModuleError::Directory)
-    }
+        let file = platform::create_private(system_file).context(self.name())?;
+        let status = command
+            .arg(resource)
+            .stdout(file)
+            .status()
+            .context(self.name());
 
-    fn wrap_io_error(&self, err: Error) -> ModuleError {
-        ModuleError::IO(self.name().to_owned(), err)
+        match status {
+            Ok(ref status) if status.success() => {
+                self.copies.borrow_mut().insert(system_file.to_path_buf());
+                Ok(())
+            }
+            result => {
+                fs::remove_file(system_file).context(self.name())?;
+                result?;
+                Err(ModuleError::Exec(self.name().to_owned(), program))
+            }
+        }
     }
 
-    fn verify_module_creation<P: AsRef<Path>>(path: P) -> Result<(), PathBuf> {
-        for p in path.as_ref().ancestors() {
-            if p.exists() && !p.is_dir() {
-                return Err(p.to_path_buf());
+    /// Encrypt `system_file` into `output` the way `resource` is encrypted
+    fn encrypt(
+        &self,
+        system_file: &Path,
+        output: &Path,
+        resource: &Path,
+    ) -> Result<(), ModuleError> {
+        let mut command = if resource.extension().is_some_and(|ext| ext == AGE_EXTENSION) {
+            let mut command = process::Command::new("age");
+            command.arg("--encrypt");
+            if let Some(identity) = env::var_os(AGE_IDENTITY_ENV) {
+                command.arg("--identity").arg(identity);
             }
+            command
+        } else {
+            let listing = process::Command::new("gpg")
+                .args(["--batch", "--list-only", "--list-packets"])
+                .arg(resource)
+                .output()
+                .context(self.name())?;
+            let listing = String::from_utf8_lossy(&listing.stdout);
+            let recipients = gpg_recipients(&listing);
+            let mut command = process::Command::new("gpg");
+            command.arg("--quiet").arg("--yes").arg("--encrypt");
+            if recipients.is_empty() {
+                command.arg("--default-recipient-self");
+            }
+            for recipient in recipients {
+                command.arg("--recipient").arg(recipient);
+            }
+            if resource
+                .extension()
+                .is_some_and(|ext| ext == ARMOR_EXTENSION)
+            {
+                command.arg("--armor");
+            }
+            command
+        };
+        let program = command.get_program().to_string_lossy().into_owned();
+        let status = command
+            .arg("--output")
+            .arg(output)
+            .arg(system_file)
+            .status()
+            .context(self.name())?;
+        if !status.success() {
+            let _ = fs::remove_file(output);
+            return Err(ModuleError::Exec(self.name().to_owned(), program));
         }
         Ok(())
     }
 
-    pub fn list<P: AsRef<Path>>(
-        module_dir: P,
-    ) -> Result<Vec<Result<Module, ModuleError>>, ModuleError> {
-        let iter = Module::read_dir(module_dir)?;
-        let mut modules: Vec<Result<Module, ModuleError>> = match iter.size_hint() {
-            (_, Some(n)) => Vec::with_capacity(n),
-            _ => Vec::new(),
-        };
+    /// Overwrite a decrypted secret before removing it
+    fn shred(&self, system_file: &Path) -> Result<(), ModuleError> {
+        let len = fs::metadata(system_file).context(self.name())?.len();
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(system_file)
+            .context(self.name())?;
+        file.write_all(&vec![0; len as usize])
+            .and_then(|_| file.sync_all())
+            .context(self.name())?;
+        fs::remove_file(system_file).context(self.name())?;
+        self.copies.borrow_mut().remove(system_file);
+        Ok(())
+    }
 
-        for entry in iter {
-            let path = entry.unwrap().path();
-            if path.is_dir() {
-                modules.push(Module::new(path))
+    /// Symlink a resource to its system location, or decrypt it there if it is encrypted
+    fn install_resource(
+        &self,
+        resource: &str,
+        definition: &ResourceDef,
+    ) -> Result<(), ModuleError> {
+        let system_file = target::resolve(&definition.target);
+        let resource = self.path.join(resource);
+        if self.escalates(definition, &system_file) {
+            info!(
+                "Module {}: Symlink {} -> {} with sudo",
+                self.name(),
+                resource.display(),
+                system_file.display()
+            );
+            self.sudo("mkdir", &[Path::new("-p"), system_file.parent().unwrap()])?;
+            let contents = self.link_contents(definition, &resource, &system_file);
+            self.sudo("ln", &[Path::new("-s"), &contents, &system_file])?;
+            return self.verify_link(&resource, &system_file);
+        }
+        fs::create_dir_all(system_file.parent().unwrap()).context(self.name())?; // Safe as targets are never /
+        if definition.encrypted {
+            info!(
+                "Module {}: Decrypt {} -> {}",
+                self.name(),
+                resource.display(),
+                system_file.display()
+            );
+            return self.decrypt(&resource, &system_file);
+        }
+        info!(
+            "Module {}: Symlink {} -> {}",
+            self.name(),
+            resource.display(),
+            system_file.display()
+        );
+        let contents = self.link_contents(definition, &resource, &system_file);
+        trace_module!(
+            self.name(),
+            "Link {} holds {}, as links are {}",
+            system_file.display(),
+            contents.display(),
+            if contents.is_relative() {
+                "relative"
+            } else {
+                "absolute"
             }
+        );
+        platform::symlink(&contents, &system_file).context(self.name())?;
+        self.verify_link(&resource, &system_file)
+    }
+
+    fn dangling_error(&self, system_file: &Path, destination: &Path) -> ModuleError {
+        ModuleError::Dangling(
+            self.name().to_owned(),
+            system_file.display().to_string(),
+            destination.display().to_string(),
+        )
+    }
+
+    /// Check that a link just created points at the resource and that it can be read through it,
+    /// so e.g. a resource deleted meanwhile doesn't go unnoticed
+    fn verify_link(&self, resource: &Path, system_file: &Path) -> Result<(), ModuleError> {
+        let failed = |reason: String| {
+            ModuleError::Verification(
+                self.name().to_owned(),
+                system_file.display().to_string(),
+                reason,
+            )
+        };
+        let actual = link_destination(system_file).map_err(|err| failed(err.to_string()))?;
+        if actual != resource {
+            return Err(failed(format!("It points to {}", actual.display())));
         }
+        let readable = if system_file.is_dir() {
+            fs::read_dir(system_file).map(|_| ())
+        } else {
+            fs::File::open(system_file).map(|_| ())
+        };
+        readable.map_err(|err| failed(format!("The resource can't be read: {}", err)))
+    }
 
-        Ok(modules)
+    pub fn install(
+        &self,
+        remove_existing: bool,
+        install_packages: bool,
+        run_init: bool,
+        cancel: &Cancel,
+    ) -> Result<(), ModuleError> {
+        self.install_with_progress(
+            remove_existing,
+            install_packages,
+            run_init,
+            cancel,
+            |_, _| {},
+        )
     }
 
-    pub fn install(&self, remove_existing: bool) -> Result<(), ModuleError> {
-        // Check for existing system files and cleanup if required
-        for system_file in self.definition.resources.values() {
-            let system_file = dirs::home_dir().unwrap().join(system_file);
-            if system_file.exists() && remove_existing {
-                if system_file.is_file() {
-                    fs::remove_file(system_file).map_err(|err| self.wrap_io_error(err))?;
-                } else {
-                    fs::remove_dir(system_file).map_err(|err| self.wrap_io_error(err))?;
+    /// Install the module like `install`, calling `progress` with the number of resources done
+    /// and the total number of resources as they are linked
+    pub fn install_with_progress<F: FnMut(usize, usize)>(
+        &self,
+        remove_existing: bool,
+        install_packages: bool,
+        run_init: bool,
+        cancel: &Cancel,
+        progress: F,
+    ) -> Result<(), ModuleError> {
+        let actions = self.plan_install(remove_existing, install_packages, run_init)?;
+        let mut progress = ResourceProgress {
+            done: 0,
+            total: self.definition.resources.len(),
+            progress,
+        };
+        (progress.progress)(0, progress.total);
+        self.execute(&actions, cancel, &mut progress)
+    }
+
+    /// The steps installing the module takes, without taking any of them. Fails like `install`
+    /// if something is in the way of a resource, unless `remove_existing` is set.
+    pub fn plan_install(
+        &self,
+        remove_existing: bool,
+        install_packages: bool,
+        run_init: bool,
+    ) -> Result<Vec<Action>, ModuleError> {
+        // Check that required packages are present, installing them if asked
+        let mut actions = vec![Action::CheckPackages {
+            install: install_packages,
+        }];
+
+        // Check for existing system files and clean up if required. Links that already point
+        // at the right resource and the module's decrypted copies are left alone, so installing
+        // again is harmless.
+        let mut resources = Vec::new();
+        for (resource, definition) in &self.definition.resources {
+            let system_file = target::resolve(&definition.target);
+            trace_module!(
+                self.name(),
+                "Resource {} goes to {}, which is {}",
+                resource,
+                system_file.display(),
+                describe(&system_file)
+            );
+            if self.is_installed(resource, definition) {
+                resources.push(Action::Keep {
+                    resource: resource.clone(),
+                    target: system_file,
+                });
+                continue;
+            } else if let Some(destination) = dangling_link(&system_file) {
+                // `exists` follows links, so misses broken ones, which still take the target
+                if !remove_existing {
+                    return Err(self.dangling_error(&system_file, &destination));
                 }
+                actions.push(Action::Remove(system_file.clone()));
+            } else if system_file.exists() && remove_existing {
+                actions.push(Action::Remove(system_file.clone()));
             } else if system_file.exists() {
+                trace_module!(
+                    self.name(),
+                    "Refusing to replace {} without --force",
+                    system_file.display()
+                );
                 return Err(ModuleError::Install(
                     self.name().to_owned(),
                     system_file.display().to_string(),
                 ));
-            } else {
-                match Module::verify_module_creation(&system_file) {
-                    Err(path) => {
-                        if remove_existing {
-                            fs::remove_file(path).map_err(|err| self.wrap_io_error(err))?;
-                        } else {
-                            return Err(ModuleError::InstallPath(
-                                self.name().to_string(),
-                                path.display().to_string(),
-                            ));
-                        }
-                    }
-                    _ => continue,
+            } else if let Err(path) = Module::verify_module_creation(&system_file) {
+                trace_module!(
+                    self.name(),
+                    "{} can't be created, as its parent {} is {}",
+                    system_file.display(),
+                    path.display(),
+                    describe(&path)
+                );
+                if !remove_existing {
+                    return Err(ModuleError::InstallPath(
+                        self.name().to_string(),
+                        path.display().to_string(),
+                    ));
                 }
+                actions.push(Action::Remove(path));
             }
+            resources.push(Action::Install {
+                resource: resource.clone(),
+                target: system_file,
+            });
         }
+        actions.extend(resources);
 
-        // Iterate over resources and symlink them
-        for (resource, system_file) in &self.definition.resources {
-            let system_file = dirs::home_dir().unwrap().join(system_file);
-            let resource = self.path.join(resource);
-            fs::create_dir_all(system_file.parent().unwrap())
-                .map_err(|err| self.wrap_io_error(err))?; // Safe as home_dir is not /
-            info!(
-                "Module {}: Symlink {} -> {}",
-                self.name(),
-                resource.display().to_string(),
-                system_file.display().to_string()
-            );
-            symlink(&resource, &system_file).map_err(|err| self.wrap_io_error(err))?;
+        match self.definition.init {
+            Some(_) if run_init => actions.push(Action::RunInit),
+            Some(_) => info!("Module {}: Init script already ran, skipping", self.name()),
+            None => {}
         }
+        if !self.definition.systemd.units.is_empty() {
+            actions.push(Action::EnableUnits);
+        }
+        Ok(actions)
+    }
 
-        // Init Script
-        if self.definition.init {
-            let s = &self.path.clone().join(INIT_SCRIPT).display().to_string();
-
-            info!("Module {}: Execute init script", self.name());
-
-            let status = &process::Command::new(&s)
-                .spawn()
-                .map_err(|err| self.wrap_io_error(err))?
-                .wait()
-                .map_err(|err| self.wrap_io_error(err))?;
-
-            if !status.success() {
-                return Err(ModuleError::Exec(
+    /// The steps uninstalling the module takes, without taking any of them. Fails like
+    /// `uninstall` if a target isn't the module's, unless `force` is set.
+    pub fn plan_uninstall(&self, force: bool) -> Result<Vec<Action>, ModuleError> {
+        // Rather than leave links behind, nothing is removed if some need sudo and it's not allowed
+        if !self.escalate {
+            let privileged = self
+                .privileged_resources()
+                .into_iter()
+                .find(|(_, system_file)| fs::symlink_metadata(system_file).is_ok());
+            if let Some((_, system_file)) = privileged {
+                return Err(ModuleError::Privileged(
                     self.name().to_owned(),
-                    INIT_SCRIPT.to_owned(),
+                    system_file.display().to_string(),
                 ));
             }
         }
-
-        Ok(())
-    }
-
-    pub fn uninstall(&self, force: bool) -> Result<(), ModuleError> {
         // Test files to verify installation
         if !force {
-            for (resource, system_file) in &self.definition.resources {
-                let system_file = dirs::home_dir().unwrap().join(system_file);
-                let resource = self.path.join(resource);
+            for (resource, definition) in &self.definition.resources {
+                let system_file = target::resolve(&definition.target);
                 if !system_file.exists() {
                     continue;
                 }
-                match fs::read_link(system_file) {
+                if definition.encrypted {
+                    if !self.is_installed(resource, definition) {
+                        return Err(ModuleError::Uninstall(
+                            self.name().to_owned(),
+                            system_file.display().to_string(),
+                        ));
+                    }
+                    continue;
+                }
+                let resource = self.path.join(resource);
+                match link_destination(&system_file) {
                     Ok(actual_path) => if actual_path != resource {
                         return Err(ModuleError::Uninstall(
                             self.name().to_owned(),
@@ -285,47 +2367,486 @@ impl Module {
             }
         }
 
-        for system_file in self.definition.resources.values() {
-            let system_file = dirs::home_dir().unwrap().join(system_file);
+        let mut actions = Vec::new();
+        if !self.definition.systemd.units.is_empty() {
+            actions.push(Action::DisableUnits);
+        }
+        for (resource, definition) in &self.definition.resources {
+            let system_file = target::resolve(&definition.target);
+            if system_file.exists() {
+                actions.push(Action::Uninstall {
+                    resource: resource.clone(),
+                    target: system_file,
+                });
+            }
+        }
+        // What an install from before a condition or the definition changed left behind
+        for (resource, definition) in &self.left_out_resources {
+            if self.is_installed(resource, definition) {
+                actions.push(Action::Uninstall {
+                    resource: resource.clone(),
+                    target: target::resolve(&definition.target),
+                });
+            }
+        }
+        for link in self.stale_links(&self.links) {
+            let planned = actions.iter().any(|action| match *action {
+                Action::Uninstall { ref target, .. } => *target == link,
+                _ => false,
+            });
+            if !planned {
+                actions.push(Action::Remove(link));
+            }
+        }
+        if self.definition.cleanup.is_some() {
+            actions.push(Action::RunCleanup);
+        }
+        Ok(actions)
+    }
+
+    /// Take the steps planned for this module in order, telling `observer` as each starts and
+    /// finishes. Stops at the first that fails, or before the next once cancelled.
+    pub fn execute<O: Observer + ?Sized>(
+        &self,
+        actions: &[Action],
+        cancel: &Cancel,
+        observer: &mut O,
+    ) -> Result<(), ModuleError> {
+        for action in actions {
+            self.check_cancelled(cancel)?;
+            observer.started(self, action);
+            let result = self.take(action, cancel);
+            observer.finished(self, action, &result);
+            result?;
+        }
+        Ok(())
+    }
+
+    // Take a single planned step
+    fn take(&self, action: &Action, cancel: &Cancel) -> Result<(), ModuleError> {
+        match *action {
+            Action::CheckPackages { install } => self.check_packages(install),
+            Action::Remove(ref path) => {
+                info!("Module {}: Remove {}", self.name(), path.display());
+                let is_dir = fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir());
+                if is_dir {
+                    fs::remove_dir(path).context(self.name())
+                } else {
+                    fs::remove_file(path).context(self.name())
+                }
+            }
+            Action::Keep { ref target, .. } => {
+                info!(
+                    "Module {}: {} already installed",
+                    self.name(),
+                    target.display()
+                );
+                Ok(())
+            }
+            Action::Install { ref resource, .. } => {
+                self.install_resource(resource, &self.definition.resources[resource])
+            }
+            Action::RunInit => match self.definition.init {
+                Some(ref script) => {
+                    self.wait_for(cancel)?;
+                    self.run_script("init", script, INIT_SCRIPT)
+                }
+                None => Ok(()),
+            },
+            Action::EnableUnits => self.systemctl("enable"),
+            Action::DisableUnits => self.systemctl("disable"),
+            Action::Uninstall {
+                ref resource,
+                ref target,
+            } => {
+                let definition = self
+                    .definition
+                    .resources
+                    .get(resource)
+                    .unwrap_or_else(|| &self.left_out_resources[resource]);
+                if !target.exists() {
+                    return Ok(());
+                }
+                info!("Module {}: Remove {}", self.name(), target.display());
+                if definition.encrypted && target.is_file() {
+                    self.shred(target)
+                } else if self.escalates(definition, target) {
+                    self.sudo("rm", &[Path::new("-d"), target])
+                } else if target.is_file() {
+                    fs::remove_file(target).context(self.name())
+                } else {
+                    fs::remove_dir(target).context(self.name())
+                }
+            }
+            Action::RunCleanup => match self.definition.cleanup {
+                Some(ref script) => {
+                    self.wait_for(cancel)?;
+                    self.run_script("cleanup", script, CLEANUP_SCRIPT)
+                }
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// Bring the links of a module in line with its definition after it changed from `previous`.
+    ///
+    /// Links for resources that were removed are deleted and missing links are created, but
+    /// scripts, packages and units are left alone.
+    pub fn update_links(&self, previous: &Module) -> Result<(), ModuleError> {
+        self.copies
+            .borrow_mut()
+            .extend(previous.copies.borrow().iter().cloned());
+        let targets = self.targets();
+        for (resource, definition) in &previous.definition.resources {
+            let system_file = target::resolve(&definition.target);
+            if previous.is_installed(resource, definition) && !targets.contains(&system_file) {
+                info!("Module {}: Remove {}", self.name(), system_file.display());
+                if definition.encrypted {
+                    self.shred(&system_file)?;
+                } else {
+                    fs::remove_file(&system_file).context(self.name())?;
+                }
+            }
+        }
 
-            if !system_file.exists() {
+        for (resource, definition) in &self.definition.resources {
+            let system_file = target::resolve(&definition.target);
+            if self.is_installed(resource, definition) {
                 continue;
+            } else if let Some(destination) = dangling_link(&system_file) {
+                return Err(self.dangling_error(&system_file, &destination));
+            } else if system_file.exists() {
+                return Err(ModuleError::Install(
+                    self.name().to_owned(),
+                    system_file.display().to_string(),
+                ));
             }
+            self.install_resource(resource, definition)?;
+        }
+        Ok(())
+    }
 
-            info!(
-                "Module {}: Remove {}",
-                self.name(),
-                system_file.display().to_string()
-            );
+    /// Links of `previous_links` that still point into the module but are no longer one of its
+    /// targets
+    pub fn stale_links(&self, previous_links: &[PathBuf]) -> Vec<PathBuf> {
+        let targets = self.targets();
+        previous_links
+            .iter()
+            .filter(|system_file| {
+                link_destination(system_file).is_ok_and(|actual| actual.starts_with(&self.path))
+                    && !targets.contains(system_file)
+            })
+            .cloned()
+            .collect()
+    }
 
-            if system_file.is_file() {
-                fs::remove_file(system_file).map_err(|err| self.wrap_io_error(err))?;
-            } else {
-                fs::remove_dir(system_file).map_err(|err| self.wrap_io_error(err))?;
+    /// Whether the module is fully installed and has no stale links left from `previous_links`,
+    /// so syncing it would change nothing
+    pub fn is_clean(&self, previous_links: &[PathBuf]) -> bool {
+        self.status() == Status::Installed && self.stale_links(previous_links).is_empty()
+    }
+
+    /// Bring the links of a module in line with its definition: create missing links, replace
+    /// broken or wrong ones, and delete those of `previous_links` that point into the module but
+    /// are no longer one of its targets.
+    ///
+    /// Only symlinks are touched; a regular file in the way is an error. Scripts, packages and
+    /// units are left alone.
+    pub fn sync(&self, previous_links: &[PathBuf]) -> Result<Vec<SyncChange>, ModuleError> {
+        let mut changes = Vec::new();
+        for system_file in self.stale_links(previous_links) {
+            info!("Module {}: Remove {}", self.name(), system_file.display());
+            fs::remove_file(&system_file).context(self.name())?;
+            changes.push(SyncChange::Removed(system_file));
+        }
+
+        for (resource, definition) in &self.definition.resources {
+            let system_file = target::resolve(&definition.target);
+            if self.is_installed(resource, definition) {
+                continue;
+            }
+            let is_symlink = fs::symlink_metadata(&system_file)
+                .map(|metadata| metadata.file_type().is_symlink());
+            match is_symlink {
+                Ok(true) => {
+                    info!("Module {}: Remove {}", self.name(), system_file.display());
+                    fs::remove_file(&system_file).context(self.name())?;
+                    self.install_resource(resource, definition)?;
+                    changes.push(SyncChange::Repaired(system_file));
+                }
+                Ok(false) => {
+                    return Err(ModuleError::Install(
+                        self.name().to_owned(),
+                        system_file.display().to_string(),
+                    ))
+                }
+                Err(_) => {
+                    self.install_resource(resource, definition)?;
+                    changes.push(SyncChange::Linked(system_file));
+                }
             }
         }
+        Ok(changes)
+    }
+
+    pub fn uninstall(&self, force: bool, cancel: &Cancel) -> Result<(), ModuleError> {
+        let actions = self.plan_uninstall(force)?;
+        self.execute(&actions, cancel, &mut ())
+    }
+}
+
+/// Apply `op` to each module in turn, stopping before the next module once cancelled.
+///
+/// Returns the results of the modules that were processed, so an interrupted batch still
+/// reports what was done.
+pub fn for_each<'a, F>(
+    modules: &'a [Module],
+    cancel: &Cancel,
+    mut op: F,
+) -> Vec<(&'a Module, Result<(), ModuleError>)>
+where
+    F: FnMut(&Module) -> Result<(), ModuleError>,
+{
+    let mut results = Vec::with_capacity(modules.len());
+    for module in modules {
+        if cancel.is_cancelled() {
+            break;
+        }
+        results.push((module, op(module)));
+    }
+    results
+}
+
+/// Log target of the decisions made about a module, which `--trace` shows
+pub fn trace_target(module: &str) -> String {
+    format!("{}{}", TRACE_TARGET_PREFIX, module)
+}
 
-        // Cleanup Script
-        if self.definition.cleanup {
-            let s = &self.path.clone().join(CLEANUP_SCRIPT).display().to_string();
+// What is at a path, for traces
+fn describe(path: &Path) -> String {
+    match fs::symlink_metadata(path) {
+        Err(_) => "missing".to_owned(),
+        Ok(ref metadata) if metadata.file_type().is_symlink() => match link_destination(path) {
+            Ok(destination) => format!("a link to {}", destination.display()),
+            Err(_) => "an unreadable link".to_owned(),
+        },
+        Ok(ref metadata) if metadata.is_dir() => "a directory".to_owned(),
+        Ok(_) => "a file".to_owned(),
+    }
+}
 
-            info!("Module {}: Execute cleanup script", self.name());
+// Where a symlink points, if it is one and what it points at doesn't exist
+fn dangling_link(path: &Path) -> Option<PathBuf> {
+    let is_symlink =
+        fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink());
+    if !is_symlink || path.exists() {
+        return None;
+    }
+    link_destination(path).ok()
+}
 
-            let status = &process::Command::new(&s)
-                .spawn()
-                .map_err(|err| self.wrap_io_error(err))?
-                .wait()
-                .map_err(|err| self.wrap_io_error(err))?;
+// The key IDs a message is encrypted to, from `gpg --list-packets`. Hidden recipients are listed
+// as zeros, so they are left out.
+fn gpg_recipients(listing: &str) -> Vec<&str> {
+    listing
+        .lines()
+        .filter_map(|line| line.strip_prefix(":pubkey enc packet:"))
+        .filter_map(|packet| packet.split("keyid ").nth(1))
+        .map(str::trim)
+        .filter(|keyid| keyid.chars().any(|c| c != '0'))
+        .collect()
+}
 
-            if !status.success() {
-                return Err(ModuleError::Exec(
-                    self.name().to_owned(),
-                    CLEANUP_SCRIPT.to_owned(),
-                ));
+/// The 64-bit FNV-1a hash of a file's contents, in hex. It tells edits apart, not tampering.
+pub fn checksum(path: &Path) -> io::Result<String> {
+    let hash = fs::read(path)?
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    Ok(format!("{:016x}", hash))
+}
+
+/// Where the output of failed scripts is kept, as `<module>-<init|cleanup>.log`
+pub fn script_log_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join(SCRIPT_LOG_DIR))
+}
+
+// Lines of a child's stdout and stderr, in the order they arrive, until both are closed
+fn output_lines(child: &mut process::Child) -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    let streams: [Box<dyn Read + Send>; 2] = [
+        Box::new(child.stdout.take().unwrap()),
+        Box::new(child.stderr.take().unwrap()),
+    ];
+    for stream in streams {
+        let sender = sender.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                let _ = sender.send(line);
+            }
+        });
+    }
+    receiver
+}
+
+// Whether creating or removing the file needs more rights than the user has: its directory, or
+// the closest one that exists, isn't writable
+fn needs_privilege(system_file: &Path) -> bool {
+    system_file
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.exists())
+        .is_some_and(|dir| !platform::is_writable_dir(dir))
+}
+
+/// Where a symlink points, with a relative link resolved against its directory
+pub fn link_destination(link: &Path) -> io::Result<PathBuf> {
+    let contents = fs::read_link(link)?;
+    if contents.is_absolute() {
+        return Ok(contents);
+    }
+    let dir = link.parent().unwrap_or(link);
+    let mut destination = dir.canonicalize().unwrap_or_else(|_| dir.to_owned());
+    for component in contents.components() {
+        match component {
+            Component::ParentDir => {
+                destination.pop();
             }
+            Component::CurDir => {}
+            component => destination.push(component),
+        }
+    }
+    Ok(destination)
+}
+
+/// The way from the directory of a link to the file it should point at, for a relative link.
+/// It starts from the real directory, as the system resolves `..` there.
+pub fn relative_link(link: &Path, file: &Path) -> PathBuf {
+    let dir = link.parent().unwrap_or(link);
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_owned());
+    let common = dir
+        .components()
+        .zip(file.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in common..dir.components().count() {
+        relative.push("..");
+    }
+    relative.extend(file.components().skip(common));
+    relative
+}
+
+// Name a new resource after the file its target is, without hiding it, numbered if `taken` has it
+fn resource_name(target: &str, taken: &mut HashSet<String>) -> String {
+    let file_name = target
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap()
+        .trim_start_matches('.');
+    let mut resource = file_name.to_owned();
+    for i in 2.. {
+        if taken.insert(resource.clone()) {
+            break;
+        }
+        resource = format!("{}{}", file_name, i);
+    }
+    resource
+}
+
+// Whether stow leaves a file of a package alone by default: version control files, editor
+// backups, and the package's own documentation
+fn stow_ignored(name: &str, top_level: bool) -> bool {
+    STOW_IGNORE.contains(&name)
+        || name.ends_with('~')
+        || (name.starts_with('#') && name.ends_with('#'))
+        || (top_level
+            && STOW_IGNORE_TOP_LEVEL
+                .iter()
+                .any(|doc| name.starts_with(doc)))
+}
+
+// Files of a stow package below `dir` and where stow would link them, relative to the home
+// directory (`relative` being where `dir` is)
+fn stow_entries(
+    dir: &Path,
+    relative: &str,
+    dotfiles: bool,
+    entries: &mut Vec<(String, PathBuf)>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if stow_ignored(&name, relative.is_empty()) {
+            continue;
+        }
+        let name = match name.strip_prefix("dot-") {
+            Some(rest) if dotfiles => format!(".{}", rest),
+            _ => name,
+        };
+        let target = if relative.is_empty() {
+            name
+        } else {
+            format!("{}/{}", relative, name)
+        };
+        if entry.file_type()?.is_dir() && target::is_base_dir(&target) {
+            stow_entries(&entry.path(), &target, dotfiles, entries)?;
+        } else {
+            entries.push((target, entry.path()));
+        }
+    }
+    Ok(())
+}
+
+// Move a file or directory, copying it if it is on another filesystem
+fn move_tree(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    copy_tree(from, to)?;
+    if fs::symlink_metadata(from)?.is_dir() {
+        fs::remove_dir_all(from)
+    } else {
+        fs::remove_file(from)
+    }
+}
+
+// Copy a file or directory, keeping symlinks as symlinks
+pub(crate) fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.file_type().is_symlink() {
+        platform::symlink(&fs::read_link(from)?, to)
+    } else if metadata.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &to.join(entry.file_name()))?;
         }
         Ok(())
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+// Files below a directory (symlinks included, but not followed), as `/`-separated relative paths
+fn list_files(dir: &Path) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() {
+            let children = list_files(&entry.path())?;
+            files.extend(
+                children
+                    .into_iter()
+                    .map(|child| format!("{}/{}", name, child)),
+            );
+        } else {
+            files.push(name);
+        }
     }
+    Ok(files)
 }
 
 fn file_name_to_string<P: AsRef<Path>>(path: P) -> String {
@@ -341,6 +2862,13 @@ fn check_permissions(mode: u32, desired: u32) -> bool {
     ((mode >> 6) & desired) == desired
 }
 
+// Mode bits are only checked on platforms that have them
+fn check_path_permissions(path: &Path, desired: u32) -> bool {
+    path.metadata().is_ok_and(|metadata| {
+        platform::mode(&metadata).is_none_or(|mode| check_permissions(mode, desired))
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -359,6 +2887,31 @@ mod tests {
         assert!(!check_permissions(rw_mode, rwx)); // Test strictly greater permissions
     }
 
+    #[test]
+    fn test_checksum() {
+        let path = env::temp_dir().join(format!("modman-checksum-{}", process::id()));
+        fs::write(&path, "a").unwrap();
+        assert_eq!(checksum(&path).unwrap(), "af63dc4c8601ec8c");
+        fs::write(&path, "").unwrap();
+        assert_eq!(checksum(&path).unwrap(), "cbf29ce484222325");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_gpg_recipients() {
+        let listing = "# off=0 ctb=84 tag=1 hlen=2 plen=94\n\
+                       :pubkey enc packet: version 3, algo 18, keyid 89E6226EDBB9B91E\n\
+                       \tdata: [263 bits]\n\
+                       :pubkey enc packet: version 3, algo 1, keyid 0000000000000000\n\
+                       :pubkey enc packet: version 3, algo 1, keyid 5220CCE004D35726\n\
+                       :encrypted data packet:\n";
+        assert_eq!(
+            gpg_recipients(listing),
+            vec!["89E6226EDBB9B91E", "5220CCE004D35726"]
+        );
+        assert!(gpg_recipients(":encrypted data packet:\n").is_empty());
+    }
+
     mod module_def {
         use super::super::*;
 
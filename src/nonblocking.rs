@@ -0,0 +1,40 @@
+//! Tokio based variants of the module operations, enabled with the `async` feature.
+//!
+//! Module operations are blocking filesystem and process work, so each call is moved onto
+//! tokio's blocking thread pool and returns a handle that can be awaited alongside other IO.
+//! The handle only resolves to a `JoinError` if the operation panicked.
+extern crate tokio;
+
+use cancel::Cancel;
+use module::{Module, ModuleError};
+use std::path::PathBuf;
+
+pub fn list<P: Into<PathBuf>>(
+    module_dir: P,
+) -> tokio::task::JoinHandle<Result<Vec<Result<Module, ModuleError>>, ModuleError>> {
+    let module_dir = module_dir.into();
+    tokio::task::spawn_blocking(move || Module::list(module_dir))
+}
+
+pub fn install(
+    module: &Module,
+    remove_existing: bool,
+    install_packages: bool,
+    cancel: &Cancel,
+) -> tokio::task::JoinHandle<Result<(), ModuleError>> {
+    let module = module.clone();
+    let cancel = cancel.clone();
+    tokio::task::spawn_blocking(move || {
+        module.install(remove_existing, install_packages, true, &cancel)
+    })
+}
+
+pub fn uninstall(
+    module: &Module,
+    force: bool,
+    cancel: &Cancel,
+) -> tokio::task::JoinHandle<Result<(), ModuleError>> {
+    let module = module.clone();
+    let cancel = cancel.clone();
+    tokio::task::spawn_blocking(move || module.uninstall(force, &cancel))
+}
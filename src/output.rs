@@ -0,0 +1,374 @@
+//! Terminal output layer: logging, colours, aligned columns and progress for batches of modules.
+extern crate atty;
+
+use log::{self, Level, Metadata, Record};
+use modman::module;
+use runlog;
+use std::env;
+use std::fmt::Display;
+use std::io::{self, BufRead, Write};
+use std::iter;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+// Whether to draw a progress bar for batches, and whether one is on screen
+static BAR: AtomicBool = AtomicBool::new(false);
+static BAR_SHOWN: AtomicBool = AtomicBool::new(false);
+static BAR_WIDTH: usize = 30;
+static BAR_INTERVAL: Duration = Duration::from_millis(50);
+static LOGGER: Logger = Logger;
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+// Log targets of the modules whose decisions are traced
+static TRACED: OnceLock<Vec<String>> = OnceLock::new();
+
+/// When to colour output, as chosen with `--color`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(choice: &str) -> Option<ColorChoice> {
+        match choice {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    // Decide for `auto`, following the NO_COLOR (https://no-color.org) and CLICOLOR
+    // (https://bixense.com/clicolors) conventions. NO_COLOR wins over CLICOLOR_FORCE.
+    fn enabled(self, is_tty: bool) -> bool {
+        let set = |var| env::var_os(var).filter(|val| !val.is_empty());
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto if set("NO_COLOR").is_some() => false,
+            ColorChoice::Auto if set("CLICOLOR_FORCE").is_some_and(|val| val != "0") => true,
+            ColorChoice::Auto if set("CLICOLOR").is_some_and(|val| val == "0") => false,
+            ColorChoice::Auto => is_tty,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Style {
+    Ok,
+    Fail,
+    Warn,
+    Dim,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Ok => "32",
+            Style::Fail => "31",
+            Style::Warn => "33",
+            Style::Dim => "2",
+        }
+    }
+}
+
+struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let limit = if VERBOSE.load(Ordering::Relaxed) {
+            Level::Info
+        } else {
+            Level::Warn
+        };
+        metadata.level() <= limit
+            || TRACED
+                .get()
+                .is_some_and(|traced| traced.iter().any(|target| target == metadata.target()))
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= Level::Info {
+            runlog::message(record.level(), record.args());
+        }
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        clear_bar();
+        match record.level() {
+            Level::Error => eprintln!("{} {}", paint(Style::Fail, "error:"), record.args()),
+            Level::Warn => eprintln!("{} {}", paint(Style::Warn, "warning:"), record.args()),
+            Level::Trace => println!("{} {}", paint(Style::Dim, "trace:"), record.args()),
+            _ => println!("{}", record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Set up logging and decide whether to colour output and draw progress bars.
+///
+/// Progress bars are drawn only when stdout is a terminal and `--verbose` was not given.
+/// Unless `--color` says otherwise, colour is used when stdout is a terminal and `NO_COLOR` is
+/// unset or empty, or `CLICOLOR_FORCE` is set and not 0. `CLICOLOR=0` disables it on terminals.
+pub fn init(verbose: bool, color: ColorChoice) {
+    COLOR.store(
+        color.enabled(atty::is(atty::Stream::Stdout)),
+        Ordering::Relaxed,
+    );
+
+    BAR.store(
+        !verbose && atty::is(atty::Stream::Stdout),
+        Ordering::Relaxed,
+    );
+
+    VERBOSE.store(verbose, Ordering::Relaxed);
+    log::set_logger(&LOGGER).unwrap();
+    if verbose {
+        log::set_max_level(log::LevelFilter::Info);
+    } else {
+        log::set_max_level(log::LevelFilter::Warn);
+    }
+}
+
+/// Also show the decisions made about these modules, as `--trace` asks
+pub fn trace<'a, I: IntoIterator<Item = &'a str>>(modules: I) {
+    let targets = modules.into_iter().map(module::trace_target).collect();
+    if TRACED.set(targets).is_ok() {
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+}
+
+// Erase the progress bar, so other output starts on a clean line
+fn clear_bar() {
+    if BAR_SHOWN.swap(false, Ordering::Relaxed) {
+        print!("\r\x1b[K");
+        let _ = io::stdout().flush();
+    }
+}
+
+pub fn paint<D: Display>(style: Style, text: D) -> String {
+    if COLOR.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m", style.code(), text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Ask a question on the terminal, returning the answer or `default` if it was left empty
+pub fn ask(question: &str, default: &str) -> io::Result<String> {
+    if default.is_empty() {
+        print!("{}: ", question);
+    } else {
+        print!("{} [{}]: ", question, default);
+    }
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_owned())
+}
+
+pub fn confirm(question: &str, default: bool) -> io::Result<bool> {
+    let answer = ask(question, if default { "Y/n" } else { "y/N" })?;
+    Ok(match answer.to_lowercase().as_str() {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => default,
+    })
+}
+
+/// Whether there is a terminal to ask questions on
+pub fn can_ask() -> bool {
+    atty::is(atty::Stream::Stdin)
+}
+
+/// Ask before deleting files, listing every one of them. Nothing is asked if there is nothing
+/// to delete or `yes` is set, and without a terminal to ask on the answer is no.
+pub fn confirm_deletion(paths: &[PathBuf], yes: bool) -> io::Result<bool> {
+    if paths.is_empty() || yes {
+        return Ok(true);
+    }
+    println!("This will delete:");
+    for path in paths {
+        println!("  {}", paint(Style::Warn, path.display()));
+    }
+    if !can_ask() {
+        println!("Not asking without a terminal; Pass --yes to delete them");
+        return Ok(false);
+    }
+    confirm("Delete these files?", false)
+}
+
+/// Print an item ended by a NUL character rather than a newline, so names with spaces or
+/// newlines survive `xargs -0`
+pub fn print0<D: Display>(item: D) {
+    print!("{}\0", item);
+}
+
+/// Print rows with the first column padded to the widest entry
+pub fn columns(rows: &[(String, String)]) {
+    let width = rows
+        .iter()
+        .map(|row| visible_len(&row.0))
+        .max()
+        .unwrap_or(0);
+    for (first, rest) in rows {
+        let padding = width - visible_len(first);
+        println!("{}{}  {}", first, " ".repeat(padding), rest);
+    }
+}
+
+// Characters a string takes up on the terminal, leaving out the escape codes of `paint`
+fn visible_len(text: &str) -> usize {
+    let mut len = 0;
+    let mut escape = false;
+    for c in text.chars() {
+        match c {
+            '\x1b' => escape = true,
+            'm' if escape => escape = false,
+            _ if !escape => len += 1,
+            _ => {}
+        }
+    }
+    len
+}
+
+/// Print each node followed by its children, connected by branches like `tree` draws
+pub fn tree(nodes: &[(String, Vec<String>)]) {
+    for (node, children) in nodes {
+        println!("{}", node);
+        for (i, child) in children.iter().enumerate() {
+            let branch = if i + 1 == children.len() {
+                "└──"
+            } else {
+                "├──"
+            };
+            println!("{} {}", branch, child);
+        }
+    }
+}
+
+/// Print a table under a header, with every column but the last padded to its widest entry
+pub fn table(header: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = header.iter().map(|title| title.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(visible_len(cell));
+        }
+    }
+    let header: Vec<String> = header.iter().map(|title| title.to_string()).collect();
+    for row in iter::once(&header).chain(rows) {
+        let last = row.len() - 1;
+        let line: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                if i == last {
+                    cell.clone()
+                } else {
+                    let padding = widths[i] - visible_len(cell);
+                    format!("{}{}", cell, " ".repeat(padding))
+                }
+            })
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+/// Progress through a batch of modules.
+///
+/// On a terminal (without `--verbose`) this is a bar showing how many modules are done, and how
+/// far along the current module is, redrawn below the results as they are printed. Otherwise
+/// each result is prefixed with a counter.
+pub struct Progress {
+    current: usize,
+    total: usize,
+    module: String,
+    resources: Option<(usize, usize)>,
+    drawn: Option<Instant>,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Progress {
+        Progress {
+            current: 0,
+            total,
+            module: String::new(),
+            resources: None,
+            drawn: None,
+        }
+    }
+
+    /// Start working on the next module
+    pub fn start(&mut self, module: &str) {
+        self.module = module.to_owned();
+        self.resources = None;
+        self.draw(true);
+    }
+
+    /// Note how many of the current module's resources are done
+    pub fn resources(&mut self, done: usize, total: usize) {
+        self.resources = Some((done, total));
+        self.draw(done == total);
+    }
+
+    /// Print the result of the current module
+    pub fn println<D: Display>(&mut self, result: D) {
+        self.current += 1;
+        self.module.clear();
+        self.resources = None;
+        if BAR.load(Ordering::Relaxed) {
+            clear_bar();
+            println!("{}", result);
+            self.draw(true);
+        } else if self.total <= 1 {
+            println!("{}", result);
+        } else {
+            let width = self.total.to_string().len();
+            let counter = format!("[{:>width$}/{}] ", self.current, self.total, width = width);
+            println!("{}{}", paint(Style::Dim, counter), result);
+        }
+    }
+
+    // Redraw the bar, at most every BAR_INTERVAL unless forced
+    fn draw(&mut self, force: bool) {
+        if !BAR.load(Ordering::Relaxed) {
+            return;
+        }
+        let recent = self
+            .drawn
+            .is_some_and(|drawn| drawn.elapsed() < BAR_INTERVAL);
+        if recent && !force {
+            return;
+        }
+        let filled = BAR_WIDTH * self.current / self.total.max(1);
+        let mut line = format!(
+            "\r\x1b[K[{}{}] {}/{}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            self.current,
+            self.total
+        );
+        if !self.module.is_empty() {
+            line.push_str(&format!(" {}", self.module));
+            if let Some((done, total)) = self.resources {
+                line.push_str(&format!(" ({}/{} resources)", done, total));
+            }
+        }
+        print!("{}", line);
+        let _ = io::stdout().flush();
+        BAR_SHOWN.store(true, Ordering::Relaxed);
+        self.drawn = Some(Instant::now());
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        clear_bar();
+    }
+}
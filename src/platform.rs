@@ -0,0 +1,284 @@
+//! Filesystem operations that differ between Unix and Windows.
+#[cfg(target_os = "linux")]
+extern crate libc;
+
+use dirs;
+use std::fs::{File, Metadata, OpenOptions};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process;
+
+#[cfg(unix)]
+static DEFAULT_MODULES_DIR: &str = ".dotfiles";
+#[cfg(windows)]
+static DEFAULT_MODULES_DIR: &'static str = "dotfiles";
+
+// Filesystem types that are mounted over the network
+#[cfg(target_os = "linux")]
+static NETWORK_FILESYSTEMS: &[&str] = &[
+    "9p",
+    "afs",
+    "ceph",
+    "cifs",
+    "davfs",
+    "fuse.rclone",
+    "fuse.s3fs",
+    "fuse.sshfs",
+    "glusterfs",
+    "nfs",
+    "nfs4",
+    "smb3",
+    "smbfs",
+];
+// Where desktops mount removable media
+#[cfg(target_os = "linux")]
+static REMOVABLE_MOUNT_DIRS: &[&str] = &["/media", "/run/media"];
+
+/// Separates directories in a list, as in `$PATH`
+#[cfg(unix)]
+pub static PATH_LIST_SEPARATOR: &str = ":";
+#[cfg(windows)]
+pub static PATH_LIST_SEPARATOR: &'static str = ";";
+
+/// Where modules are looked for unless told otherwise
+pub fn default_modules_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(DEFAULT_MODULES_DIR))
+}
+
+/// Create a symlink at `link` pointing to `original`
+#[cfg(unix)]
+pub fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    ::std::os::unix::fs::symlink(original, link)
+}
+
+/// Create a symlink at `link` pointing to `original`.
+///
+/// Symlinks need developer mode or admin rights on Windows, so directories fall back to a
+/// junction, which doesn't.
+#[cfg(windows)]
+pub fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    use std::os::windows::fs::{symlink_dir, symlink_file};
+
+    if !original.is_dir() {
+        return symlink_file(original, link);
+    }
+    match symlink_dir(original, link) {
+        Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            let status = process::Command::new("cmd")
+                .arg("/C")
+                .arg("mklink")
+                .arg("/J")
+                .arg(link)
+                .arg(original)
+                .stdout(process::Stdio::null())
+                .status()?;
+            if status.success() {
+                Ok(())
+            } else {
+                Err(io::Error::other("mklink /J failed"))
+            }
+        }
+        result => result,
+    }
+}
+
+/// Describe the filesystem `path` is on if it is a network filesystem or on a removable device,
+/// as links into it silently break whenever it is unmounted
+#[cfg(target_os = "linux")]
+pub fn unreliable_mount(path: &Path) -> Option<String> {
+    use std::fs;
+
+    let path = path.canonicalize().ok()?;
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+    // Later mounts hide earlier ones on the same mount point, so the last longest match wins
+    let (mount_point, fstype, source) = mountinfo
+        .lines()
+        .filter_map(|line| {
+            let (mount, filesystem) = line.split_once(" - ")?;
+            let mount_point = PathBuf::from(unescape_mount(mount.split(' ').nth(4)?));
+            let mut filesystem = filesystem.split(' ');
+            Some((mount_point, filesystem.next()?, filesystem.next()?))
+        })
+        .filter(|(mount_point, _, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _, _)| mount_point.as_os_str().len())?;
+
+    if NETWORK_FILESYSTEMS.contains(&fstype) {
+        return Some(format!("a network filesystem ({} {})", fstype, source));
+    }
+    let device = Path::new(source).strip_prefix("/dev").ok();
+    let removable = device.is_some_and(|device| {
+        // Partitions have no removable flag of their own, their disk does
+        let block = Path::new("/sys/class/block").join(device);
+        let flag = |dir: &Path| fs::read_to_string(dir.join("removable")).ok();
+        let flag = flag(&block).or_else(|| {
+            let disk = block.canonicalize().ok()?;
+            flag(disk.parent()?)
+        });
+        flag.is_some_and(|flag| flag.trim() == "1")
+    });
+    if removable
+        || REMOVABLE_MOUNT_DIRS
+            .iter()
+            .any(|dir| mount_point.starts_with(dir))
+    {
+        return Some(format!("a removable device ({})", source));
+    }
+    None
+}
+
+/// Network and removable filesystems are only detected on Linux
+#[cfg(not(target_os = "linux"))]
+pub fn unreliable_mount(_path: &Path) -> Option<String> {
+    None
+}
+
+// Undo the octal escapes of whitespace and backslashes in /proc/self/mountinfo
+#[cfg(target_os = "linux")]
+fn unescape_mount(field: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = field;
+    while let Some(start) = rest.find('\\') {
+        unescaped.push_str(&rest[..start]);
+        let code = rest.get(start + 1..start + 4);
+        match code.and_then(|code| u8::from_str_radix(code, 8).ok()) {
+            Some(byte) => {
+                unescaped.push(byte as char);
+                rest = &rest[start + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Name of this machine, as printed by `hostname`
+pub fn hostname() -> Option<String> {
+    let output = process::Command::new("hostname").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout)
+        .ok()
+        .map(|hostname| hostname.trim().to_owned())
+}
+
+/// Whether `path` is a file the current user could run
+#[cfg(unix)]
+pub fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Whether `path` is a file the current user could run, trying the usual extensions
+#[cfg(windows)]
+pub fn is_executable(path: &Path) -> bool {
+    ["", "exe", "cmd", "bat"].iter().any(|ext| {
+        let path = if ext.is_empty() {
+            path.to_path_buf()
+        } else {
+            path.with_extension(ext)
+        };
+        path.is_file()
+    })
+}
+
+/// Whether the current user can create and remove files in the directory
+#[cfg(target_os = "linux")]
+pub fn is_writable_dir(dir: &Path) -> bool {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    match CString::new(dir.as_os_str().as_bytes()) {
+        Ok(dir) => unsafe { libc::access(dir.as_ptr(), libc::W_OK | libc::X_OK) == 0 },
+        Err(_) => false,
+    }
+}
+
+/// Whether the current user can create and remove files in the directory, as far as its
+/// read-only flag tells
+#[cfg(not(target_os = "linux"))]
+pub fn is_writable_dir(dir: &Path) -> bool {
+    dir.metadata()
+        .is_ok_and(|metadata| !metadata.permissions().readonly())
+}
+
+/// Start the command in a process group of its own, so `kill_group` also kills what it starts
+#[cfg(unix)]
+pub fn own_process_group(command: &mut process::Command) {
+    use std::os::unix::process::CommandExt;
+
+    command.process_group(0);
+}
+
+/// Start the command in a process group of its own, which Windows doesn't need for
+/// `kill_group`
+#[cfg(windows)]
+pub fn own_process_group(_command: &mut process::Command) {}
+
+/// Kill a child started with `own_process_group`, along with everything it started
+#[cfg(target_os = "linux")]
+pub fn kill_group(child: &mut process::Child) {
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+/// Kill a child started with `own_process_group`; only Linux also kills what it started
+#[cfg(not(target_os = "linux"))]
+pub fn kill_group(child: &mut process::Child) {
+    let _ = child.kill();
+}
+
+/// Run the command with a file mode creation mask
+#[cfg(target_os = "linux")]
+pub fn set_umask(command: &mut process::Command, umask: u32) {
+    use std::os::unix::process::CommandExt;
+
+    // umask is async-signal-safe, so it can run between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            libc::umask(umask as libc::mode_t);
+            Ok(())
+        });
+    }
+}
+
+/// Run the command with a file mode creation mask, which only Linux supports; elsewhere the
+/// command inherits modman's
+#[cfg(not(target_os = "linux"))]
+pub fn set_umask(_command: &mut process::Command, _umask: u32) {}
+
+/// Unix mode bits, if the platform has them
+#[cfg(unix)]
+pub fn mode(metadata: &Metadata) -> Option<u32> {
+    Some(metadata.permissions().mode())
+}
+
+/// Unix mode bits, if the platform has them
+#[cfg(windows)]
+pub fn mode(_metadata: &Metadata) -> Option<u32> {
+    None
+}
+
+/// Create a new file that only the current user can read
+#[cfg(unix)]
+pub fn create_private(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+/// Create a new file that only the current user can read. Files in the user profile inherit
+/// its ACL, which already restricts them to the user.
+#[cfg(windows)]
+pub fn create_private(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
@@ -0,0 +1,87 @@
+//! Admin provided restrictions on what modules may do, read from `/etc/modman/policy.toml`.
+extern crate toml;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use target;
+
+static POLICY_FILE: &str = "/etc/modman/policy.toml";
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Policy {
+    /// Targets must be inside one of these directories; `~` is the home directory
+    pub allowed_roots: Vec<String>,
+    /// Whether init and cleanup scripts may run
+    pub allow_scripts: bool,
+    /// Whether targets may be outside the home directory
+    pub allow_system: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            allowed_roots: Vec::new(),
+            allow_scripts: true,
+            allow_system: true,
+        }
+    }
+}
+
+impl Policy {
+    pub fn path() -> &'static Path {
+        Path::new(POLICY_FILE)
+    }
+
+    /// The system policy, loaded once. An unreadable or invalid policy file is an error
+    /// rather than being ignored, so a broken policy never silently allows everything.
+    pub fn get() -> Result<&'static Policy, &'static str> {
+        static POLICY: OnceLock<Result<Policy, String>> = OnceLock::new();
+        POLICY
+            .get_or_init(|| Policy::load(Policy::path()))
+            .as_ref()
+            .map_err(|err| err.as_str())
+    }
+
+    fn load(path: &Path) -> Result<Policy, String> {
+        if !path.exists() {
+            return Ok(Policy::default());
+        }
+        let buf = fs::read(path).map_err(|err| format!("policy {}: {}", path.display(), err))?;
+        toml::from_slice(&buf).map_err(|err| format!("policy {}: {}", path.display(), err))
+    }
+
+    /// Reason the target is not allowed, if it is not
+    pub fn check_target(&self, target: &Path) -> Option<String> {
+        let target = target::normalize(target);
+        let home = target::root();
+        if !self.allow_system && !target.starts_with(&home) {
+            return Some(format!(
+                "target {} outside the home directory",
+                target.display()
+            ));
+        }
+
+        if !self.allowed_roots.is_empty()
+            && !self
+                .allowed_roots
+                .iter()
+                .any(|root| target.starts_with(expand_root(root, &home)))
+        {
+            return Some(format!(
+                "target {} outside the allowed roots",
+                target.display()
+            ));
+        }
+        None
+    }
+}
+
+fn expand_root(root: &str, home: &Path) -> PathBuf {
+    let root = match root {
+        "~" => "",
+        _ => root.strip_prefix("~/").unwrap_or(root),
+    };
+    target::normalize(&home.join(root))
+}
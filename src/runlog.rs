@@ -0,0 +1,143 @@
+//! The run log: every operation on a module and every message of the commands that change
+//! anything, with timestamps, so a failed run can be reconstructed once the scrollback is gone.
+//!
+//! Each line is tab-separated: a UTC timestamp, the action (or message level), the module (or
+//! `-`) and the result (or message). Once the log reaches its size limit it is rotated to
+//! `modman.log.1`, and so on, dropping the oldest.
+use dirs;
+use log::{self, Level, LevelFilter};
+use modman::module::ModuleError;
+use std::env;
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static LOG_DIR: &str = "modman";
+static LOG_FILE: &str = "modman.log";
+// Rotated logs kept besides the current one
+static ROTATIONS: usize = 3;
+static FILE: Mutex<Option<File>> = Mutex::new(None);
+
+pub fn path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join(LOG_DIR).join(LOG_FILE))
+}
+
+/// Start logging to the run log, rotating it first if it is over `max_size` bytes, and record
+/// the command line of the run
+pub fn open(max_size: u64) -> io::Result<()> {
+    let path = match path() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    fs::create_dir_all(path.parent().unwrap())?;
+    if fs::metadata(&path).is_ok_and(|metadata| metadata.len() >= max_size) {
+        let rotated = |n: usize| path.with_extension(format!("log.{}", n));
+        for n in (1..ROTATIONS).rev() {
+            if rotated(n).exists() {
+                fs::rename(rotated(n), rotated(n + 1))?;
+            }
+        }
+        fs::rename(&path, rotated(1))?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    *FILE.lock().unwrap() = Some(file);
+    // Progress messages are logged even when they aren't shown
+    if log::max_level() < LevelFilter::Info {
+        log::set_max_level(LevelFilter::Info);
+    }
+    let args: Vec<String> = env::args().collect();
+    write("run", "-", args.join(" "));
+    Ok(())
+}
+
+/// Record the result of an action on a module
+pub fn record(action: &str, module: &str, result: &Result<(), ModuleError>) {
+    match *result {
+        Ok(()) => write(action, module, "ok"),
+        Err(ref err) => write(action, module, format!("failed: {}", err)),
+    }
+}
+
+/// Record how the run ended
+pub fn exit(code: i32, error: Option<&anyhow::Error>) {
+    match error {
+        Some(err) => write("exit", "-", format!("{}: {}", code, err)),
+        None => write("exit", "-", code),
+    }
+}
+
+/// Record a log message, under the module it starts with, if any
+pub fn message<D: Display>(level: Level, message: D) {
+    let message = message.to_string();
+    let module = message
+        .strip_prefix("Module ")
+        .and_then(|rest| rest.split_once(": "))
+        .map_or("-", |(module, _)| module);
+    write(&level.as_str().to_lowercase(), module, &message);
+}
+
+fn write<D: Display>(kind: &str, module: &str, text: D) {
+    let mut file = FILE.lock().unwrap();
+    if let Some(ref mut log) = *file {
+        // Messages spanning lines, e.g. script output, stay on one
+        let text = text.to_string().replace('\n', "\\n");
+        // The run goes on without the log if it can't be written
+        if writeln!(log, "{}\t{}\t{}\t{}", timestamp(), kind, module, text).is_err() {
+            *file = None;
+        }
+    }
+}
+
+// The current time in UTC as `YYYY-MM-DDTHH:MM:SSZ`
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_date(secs / 86400);
+    let time = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+// The proleptic Gregorian date of a day since the Unix epoch
+// (http://howardhinnant.github.io/date_algorithms.html#civil_from_days)
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+        assert_eq!(civil_date(20_742), (2026, 10, 16));
+    }
+}
@@ -0,0 +1,404 @@
+extern crate serde_json;
+
+use dirs;
+use module::{self, ModuleDirs, Resolution};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+static STATE_DIR: &str = "modman";
+static STATE_FILE: &str = "state.json";
+// New state is written here first, then renamed over the state file
+static JOURNAL_EXTENSION: &str = "journal";
+// The last consistent state, kept in case the state file is torn anyway
+static BACKUP_EXTENSION: &str = "bak";
+// Locked by the run using the state file
+static LOCK_EXTENSION: &str = "lock";
+static LOCK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ModuleState {
+    /// Consecutive failures of verification or scripts
+    #[serde(default)]
+    pub failures: u32,
+    /// Skipped by bulk operations until `modman unquarantine`
+    #[serde(default)]
+    pub quarantined: bool,
+    /// Skipped by bulk operations until `modman enable`
+    #[serde(default)]
+    pub disabled: bool,
+    /// The module's `init_once` script already ran successfully
+    #[serde(default)]
+    pub init_done: bool,
+    /// When the module was last installed successfully, in seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installed_at: Option<u64>,
+    /// System locations the module was last installed to, so `modman sync` can remove links
+    /// to resources that have since been dropped from the module
+    #[serde(default)]
+    pub links: Vec<PathBuf>,
+    /// Checksums of the decrypted copies the module installed, by location, so `modman drift`
+    /// can tell which were changed in place since
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<PathBuf, String>,
+    /// The commit of the module's repository it was last synced at, so `modman sync` can show
+    /// the changelog entries added since
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synced_revision: Option<String>,
+}
+
+/// What modman remembers about modules between runs.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    #[serde(default)]
+    pub modules: BTreeMap<String, ModuleState>,
+    /// Modules the last time-limited run stopped before reaching, which the next run starts with
+    #[serde(default)]
+    pub pending: Vec<String>,
+    /// What to do about files found in the way of links, by location, as chosen to remember
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub decisions: BTreeMap<PathBuf, Resolution>,
+    /// The state file was unreadable and this was recovered from the last consistent snapshot,
+    /// so it should be reconciled with the modules on disk
+    #[serde(skip)]
+    pub recovered: bool,
+}
+
+impl ModuleState {
+    /// Whether what the module was installed to is still as it was left: its links point into
+    /// `module_path`, and its decrypted copies match their checksums
+    pub fn intact(&self, module_path: &Path) -> bool {
+        self.links
+            .iter()
+            .all(|link| match self.checksums.get(link) {
+                Some(recorded) => module::checksum(link).is_ok_and(|current| current == *recorded),
+                None => module::link_destination(link)
+                    .is_ok_and(|actual| actual.starts_with(module_path)),
+            })
+    }
+}
+
+/// Exclusive use of the state file, until it is dropped or modman exits
+#[derive(Debug)]
+pub struct StateLock {
+    _file: fs::File,
+}
+
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error(
+        "State {0} is still in use by another modman run after {1}s; Try again once it finishes"
+    )]
+    Locked(String, u64),
+    #[error("State {0}: {1}")]
+    Parse(String, #[source] serde_json::Error),
+    #[error("State {0}: {1}")]
+    IO(String, #[source] io::Error),
+}
+
+impl State {
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join(STATE_DIR).join(STATE_FILE))
+    }
+
+    fn read(path: &Path) -> Result<State, StateError> {
+        let buf = fs::read(path).map_err(|err| StateError::IO(path.display().to_string(), err))?;
+        serde_json::from_slice(&buf)
+            .map_err(|err| StateError::Parse(path.display().to_string(), err))
+    }
+
+    /// Load the state, finishing or discarding a write that was interrupted, and falling back
+    /// to the last consistent snapshot if the state file is torn.
+    pub fn load() -> Result<State, StateError> {
+        let path = match State::path() {
+            Some(path) => path,
+            None => return Ok(State::default()),
+        };
+        let wrap = |err| StateError::IO(path.display().to_string(), err);
+
+        // A complete journal was written but not yet renamed, otherwise it is torn
+        let journal = path.with_extension(JOURNAL_EXTENSION);
+        if journal.exists() {
+            if State::read(&journal).is_ok() {
+                fs::rename(&journal, &path).map_err(&wrap)?;
+            } else {
+                warn!("Discarding interrupted write to {}", path.display());
+                fs::remove_file(&journal).map_err(&wrap)?;
+            }
+        }
+        if !path.exists() {
+            return Ok(State::default());
+        }
+
+        match State::read(&path) {
+            Ok(state) => Ok(state),
+            Err(err) => {
+                let backup = path.with_extension(BACKUP_EXTENSION);
+                warn!("{}; Recovering the last consistent state", err);
+                let mut state = State::read(&backup).unwrap_or_default();
+                state.recovered = true;
+                Ok(state)
+            }
+        }
+    }
+
+    /// Lock the state file against other modman runs, waiting up to `timeout` (or forever) for
+    /// the one holding it to finish. Nothing is locked while there is no state file yet.
+    pub fn lock(timeout: Option<Duration>) -> Result<Option<StateLock>, StateError> {
+        let path = match State::path() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+        let lock_path = path.with_extension(LOCK_EXTENSION);
+        let wrap = |err| StateError::IO(lock_path.display().to_string(), err);
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .map_err(&wrap)?;
+        let start = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(Some(StateLock { _file: file })),
+                Err(fs::TryLockError::WouldBlock) => {}
+                Err(fs::TryLockError::Error(err)) => return Err(wrap(err)),
+            }
+            if let Some(timeout) = timeout.filter(|&timeout| start.elapsed() >= timeout) {
+                let path = path.display().to_string();
+                return Err(StateError::Locked(path, timeout.as_secs()));
+            }
+            if start.elapsed() < LOCK_INTERVAL {
+                warn!(
+                    "State {} is in use by another modman run; Waiting for it to finish",
+                    path.display()
+                );
+            }
+            thread::sleep(LOCK_INTERVAL);
+        }
+    }
+
+    /// Save the state without ever leaving a partially written state file behind
+    pub fn save(&self) -> Result<(), StateError> {
+        let path = match State::path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let wrap = |err| StateError::IO(path.display().to_string(), err);
+        fs::create_dir_all(path.parent().unwrap()).map_err(&wrap)?;
+        let buf = serde_json::to_vec_pretty(self)
+            .map_err(|err| StateError::Parse(path.display().to_string(), err))?;
+
+        let journal = path.with_extension(JOURNAL_EXTENSION);
+        let mut file = fs::File::create(&journal).map_err(&wrap)?;
+        file.write_all(&buf)
+            .and_then(|()| file.sync_all())
+            .map_err(&wrap)?;
+
+        if State::read(&path).is_ok() {
+            let backup = path.with_extension(BACKUP_EXTENSION);
+            fs::copy(&path, &backup).map_err(&wrap)?;
+        }
+        fs::rename(&journal, &path).map_err(&wrap)
+    }
+
+    /// Drop entries for modules that no longer exist in any of `module_dirs`
+    pub fn reconcile(&mut self, module_dirs: &ModuleDirs) {
+        let exists = |name: &String| module_dirs.contains(name);
+        self.modules.retain(|name, _| exists(name));
+        self.pending.retain(exists);
+        self.recovered = false;
+    }
+
+    pub fn is_quarantined(&self, module: &str) -> bool {
+        self.modules
+            .get(module)
+            .is_some_and(|state| state.quarantined)
+    }
+
+    pub fn is_disabled(&self, module: &str) -> bool {
+        self.modules.get(module).is_some_and(|state| state.disabled)
+    }
+
+    /// Disable or enable a module. Returns whether that changed anything.
+    pub fn set_disabled(&mut self, module: &str, disabled: bool) -> bool {
+        let state = self.modules.entry(module.to_owned()).or_default();
+        mem::replace(&mut state.disabled, disabled) != disabled
+    }
+
+    pub fn init_done(&self, module: &str) -> bool {
+        self.modules
+            .get(module)
+            .is_some_and(|state| state.init_done)
+    }
+
+    /// Record that the module was just installed successfully
+    pub fn record_install(&mut self, module: &str) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        self.modules
+            .entry(module.to_owned())
+            .or_default()
+            .installed_at = Some(now);
+    }
+
+    /// When the module was last installed successfully
+    pub fn installed_at(&self, module: &str) -> Option<SystemTime> {
+        self.modules
+            .get(module)
+            .and_then(|state| state.installed_at)
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    pub fn record_init(&mut self, module: &str) {
+        self.modules.entry(module.to_owned()).or_default().init_done = true;
+    }
+
+    /// Record a success, which resets the failure count of a module.
+    pub fn record_success(&mut self, module: &str) {
+        if let Some(state) = self.modules.get_mut(module) {
+            state.failures = 0;
+        }
+    }
+
+    /// Record a failure, quarantining the module once it has failed `limit` times in a row
+    /// (never if `limit` is 0). Returns whether the module was newly quarantined.
+    pub fn record_failure(&mut self, module: &str, limit: u32) -> bool {
+        let state = self.modules.entry(module.to_owned()).or_default();
+        state.failures += 1;
+        if limit == 0 || state.quarantined || state.failures < limit {
+            return false;
+        }
+        state.quarantined = true;
+        true
+    }
+
+    /// Lift the quarantine of a module. Returns whether it was quarantined.
+    pub fn unquarantine(&mut self, module: &str) -> bool {
+        match self.modules.get_mut(module) {
+            Some(state) => {
+                state.failures = 0;
+                mem::replace(&mut state.quarantined, false)
+            }
+            None => false,
+        }
+    }
+
+    /// The remembered decision about a file in the way of a link
+    pub fn decision(&self, system_file: &Path) -> Option<Resolution> {
+        self.decisions.get(system_file).cloned()
+    }
+
+    pub fn record_decision(&mut self, system_file: &Path, resolution: Resolution) {
+        self.decisions.insert(system_file.to_owned(), resolution);
+    }
+
+    /// Forget the decision about a file, so it is asked about again. Returns whether there was one.
+    pub fn forget_decision(&mut self, system_file: &Path) -> bool {
+        self.decisions.remove(system_file).is_some()
+    }
+
+    /// Move everything remembered about a module to its new name
+    pub fn rename_module(&mut self, old: &str, new: &str) {
+        if let Some(state) = self.modules.remove(old) {
+            self.modules.insert(new.to_owned(), state);
+        }
+        for name in self.pending.iter_mut().filter(|name| *name == old) {
+            *name = new.to_owned();
+        }
+    }
+
+    /// System locations the module was last installed to
+    pub fn links(&self, module: &str) -> &[PathBuf] {
+        self.modules
+            .get(module)
+            .map_or(&[], |state| state.links.as_slice())
+    }
+
+    pub fn synced_revision(&self, module: &str) -> Option<&str> {
+        self.modules
+            .get(module)
+            .and_then(|state| state.synced_revision.as_deref())
+    }
+
+    pub fn record_synced_revision(&mut self, module: &str, revision: String) {
+        self.modules
+            .entry(module.to_owned())
+            .or_default()
+            .synced_revision = Some(revision);
+    }
+
+    pub fn record_links(&mut self, module: &str, links: Vec<PathBuf>) {
+        self.modules.entry(module.to_owned()).or_default().links = links;
+    }
+
+    /// Checksums of the copies the module installed, by location
+    pub fn checksums(&self, module: &str) -> Option<&BTreeMap<PathBuf, String>> {
+        self.modules.get(module).map(|state| &state.checksums)
+    }
+
+    pub fn record_checksums(&mut self, module: &str, checksums: BTreeMap<PathBuf, String>) {
+        self.modules.entry(module.to_owned()).or_default().checksums = checksums;
+    }
+
+    /// Record checksums of copies that have none yet, keeping those of copies that may have
+    /// drifted since they were installed, and forgetting those of copies that are gone
+    pub fn add_checksums(&mut self, module: &str, mut checksums: BTreeMap<PathBuf, String>) {
+        let state = self.modules.entry(module.to_owned()).or_default();
+        for (target, checksum) in &mut checksums {
+            if let Some(recorded) = state.checksums.get(target) {
+                checksum.clone_from(recorded);
+            }
+        }
+        state.checksums = checksums;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_quarantine() {
+        let mut state = State::default();
+        assert!(!state.record_failure("zsh", 2));
+        state.record_success("zsh");
+        assert!(!state.record_failure("zsh", 2), "success resets failures");
+        assert!(state.record_failure("zsh", 2));
+        assert!(state.is_quarantined("zsh"));
+        assert!(!state.record_failure("zsh", 2), "already quarantined");
+        assert!(state.unquarantine("zsh"));
+        assert!(!state.is_quarantined("zsh"));
+        assert!(!state.record_failure("vim", 0), "0 never quarantines");
+    }
+
+    #[test]
+    fn test_intact() {
+        let dir = env::temp_dir().join(format!("modman-intact-{}", process::id()));
+        let module_path = dir.join("secrets");
+        fs::create_dir_all(&module_path).unwrap();
+        let copy = dir.join("token");
+        fs::write(&copy, "secret").unwrap();
+        let mut module = ModuleState {
+            links: vec![copy.clone()],
+            ..ModuleState::default()
+        };
+        let checksum = module::checksum(&copy).unwrap();
+        module.checksums.insert(copy.clone(), checksum);
+        assert!(module.intact(&module_path), "a copy isn't a link");
+        fs::write(&copy, "edited").unwrap();
+        assert!(!module.intact(&module_path));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+extern crate serde_json;
+
+use dirs;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+static STATS_DIR: &str = "modman";
+static STATS_FILE: &str = "stats.json";
+
+#[derive(Debug, Clone, Copy)]
+pub enum Operation {
+    Install,
+    Uninstall,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Counters {
+    pub runs: u64,
+    pub failures: u64,
+    pub duration_ms: u64,
+}
+
+impl Counters {
+    fn record(&mut self, success: bool, duration: Duration) {
+        self.runs += 1;
+        if !success {
+            self.failures += 1;
+        }
+        self.duration_ms += duration.as_millis() as u64;
+    }
+}
+
+impl fmt::Display for Counters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} runs, {} failed, {}ms total",
+            self.runs, self.failures, self.duration_ms
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ModuleStats {
+    #[serde(default)]
+    pub install: Counters,
+    #[serde(default)]
+    pub uninstall: Counters,
+}
+
+impl ModuleStats {
+    fn counters(&mut self, op: Operation) -> &mut Counters {
+        match op {
+            Operation::Install => &mut self.install,
+            Operation::Uninstall => &mut self.uninstall,
+        }
+    }
+}
+
+/// Purely local usage statistics, only recorded when enabled in the global config.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Stats {
+    #[serde(flatten)]
+    pub total: ModuleStats,
+    #[serde(default)]
+    pub modules: BTreeMap<String, ModuleStats>,
+}
+
+#[derive(Debug, Error)]
+pub enum StatsError {
+    #[error("Stats {0}: {1}")]
+    Parse(String, #[source] serde_json::Error),
+    #[error("Stats {0}: {1}")]
+    IO(String, #[source] io::Error),
+}
+
+impl Stats {
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join(STATS_DIR).join(STATS_FILE))
+    }
+
+    pub fn load() -> Result<Stats, StatsError> {
+        let path = match Stats::path() {
+            Some(path) => path,
+            None => return Ok(Stats::default()),
+        };
+        if !path.exists() {
+            return Ok(Stats::default());
+        }
+
+        let buf = fs::read(&path).map_err(|err| StatsError::IO(path.display().to_string(), err))?;
+        serde_json::from_slice(&buf)
+            .map_err(|err| StatsError::Parse(path.display().to_string(), err))
+    }
+
+    pub fn save(&self) -> Result<(), StatsError> {
+        let path = match Stats::path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let wrap = |err| StatsError::IO(path.display().to_string(), err);
+        fs::create_dir_all(path.parent().unwrap()).map_err(&wrap)?;
+        let buf = serde_json::to_vec_pretty(self)
+            .map_err(|err| StatsError::Parse(path.display().to_string(), err))?;
+        fs::write(&path, buf).map_err(&wrap)
+    }
+
+    pub fn record(&mut self, op: Operation, module: &str, success: bool, duration: Duration) {
+        self.total.counters(op).record(success, duration);
+        self.modules
+            .entry(module.to_owned())
+            .or_default()
+            .counters(op)
+            .record(success, duration);
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "install: {}", self.total.install)?;
+        write!(f, "uninstall: {}", self.total.uninstall)?;
+        for (name, module) in &self.modules {
+            write!(
+                f,
+                "\n\n{}\n  install: {}\n  uninstall: {}",
+                name, module.install, module.uninstall
+            )?;
+        }
+        Ok(())
+    }
+}
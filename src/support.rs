@@ -0,0 +1,152 @@
+//! Support bundles: what a maintainer needs to reproduce an issue, with personal details removed.
+use dirs;
+use modman::config::Config;
+use modman::module::ModuleDirs;
+use modman::policy::Policy;
+use modman::state::State;
+use modman::stats::Stats;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Config keys whose values are replaced, matched case-insensitively as substrings
+static SECRET_KEYS: &[&str] = &[
+    "secret",
+    "token",
+    "password",
+    "passphrase",
+    "key",
+    "identity",
+];
+static REDACTED: &str = "\"<redacted>\"";
+
+/// Write a support bundle tarball to `out_dir`, returning its path
+pub fn create(module_dirs: &ModuleDirs, out_dir: &Path) -> io::Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let name = format!("modman-support-{}", timestamp);
+    let staging = env::temp_dir().join(&name);
+    fs::create_dir_all(&staging)?;
+
+    let result = collect(module_dirs, &staging).and_then(|()| {
+        let bundle = out_dir.join(format!("{}.tar.gz", name));
+        let status = process::Command::new("tar")
+            .arg("-czf")
+            .arg(&bundle)
+            .arg("-C")
+            .arg(env::temp_dir())
+            .arg(&name)
+            .status()?;
+        if status.success() {
+            Ok(bundle)
+        } else {
+            Err(io::Error::other("tar returned non-zero code"))
+        }
+    });
+    fs::remove_dir_all(&staging)?;
+    result
+}
+
+fn collect(module_dirs: &ModuleDirs, staging: &Path) -> io::Result<()> {
+    let mut environment = format!(
+        "modman {}\nos: {} {}\n",
+        env!("CARGO_PKG_VERSION"),
+        env::consts::OS,
+        env::consts::ARCH
+    );
+    for dir in module_dirs.iter() {
+        environment.push_str(&format!("modules dir: {}\n", dir.display()));
+    }
+    for (var, val) in env::vars() {
+        if var.starts_with("MODMAN_") || var.starts_with("XDG_") {
+            environment.push_str(&format!("{}={}\n", var, val));
+        }
+    }
+    write(staging, "environment.txt", &environment)?;
+
+    let mut modules = String::new();
+    match module_dirs.list() {
+        Ok(list) => {
+            for module in list {
+                match module {
+                    Ok(module) => {
+                        modules.push_str(&format!("{}: {:?}\n", module.name(), module.status()))
+                    }
+                    Err(err) => modules.push_str(&format!("{}\n", err)),
+                }
+            }
+        }
+        Err(err) => modules.push_str(&format!("{}\n", err)),
+    }
+    write(staging, "modules.txt", &modules)?;
+
+    // Only the TOML configs can hold secrets
+    let files = [
+        ("config.toml", Config::path(), true),
+        ("policy.toml", Some(Policy::path().to_path_buf()), true),
+        ("state.json", State::path(), false),
+        ("stats.json", Stats::path(), false),
+    ];
+    for &(name, ref path, secrets) in &files {
+        if let Some(contents) = path.as_ref().and_then(|path| fs::read_to_string(path).ok()) {
+            write(
+                staging,
+                name,
+                &if secrets { redact(&contents) } else { contents },
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn write(staging: &Path, name: &str, contents: &str) -> io::Result<()> {
+    fs::write(staging.join(name), anonymise(contents))
+}
+
+// Hide the user name, which is part of most paths
+fn anonymise(contents: &str) -> String {
+    match dirs::home_dir() {
+        Some(ref home) if home.parent().is_some() => {
+            contents.replace(home.to_str().unwrap_or("~"), "~")
+        }
+        _ => contents.to_owned(),
+    }
+}
+
+// Replace the values of secret looking `key = value` lines
+fn redact(contents: &str) -> String {
+    contents
+        .lines()
+        .map(|line| match line.find('=') {
+            Some(i)
+                if SECRET_KEYS
+                    .iter()
+                    .any(|secret| line[..i].to_lowercase().contains(secret)) =>
+            {
+                format!("{}= {}", &line[..i], REDACTED)
+            }
+            _ => line.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(redact("stats = true"), "stats = true");
+        assert_eq!(redact("api_token = \"abc\""), "api_token = \"<redacted>\"");
+        assert_eq!(
+            redact("stats = true\nPassword=\"abc\""),
+            "stats = true\nPassword= \"<redacted>\""
+        );
+    }
+}
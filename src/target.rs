@@ -0,0 +1,214 @@
+use dirs;
+use std::borrow::Cow;
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+// Prefix, environment variable and default relative to $HOME, per the XDG base directory spec
+static XDG_PREFIXES: &[(&str, &str, &str)] = &[
+    ("xdg-config:", "XDG_CONFIG_HOME", ".config"),
+    ("xdg-data:", "XDG_DATA_HOME", ".local/share"),
+    ("xdg-cache:", "XDG_CACHE_HOME", ".cache"),
+    ("xdg-state:", "XDG_STATE_HOME", ".local/state"),
+];
+
+/// Replaces the home directory as the root of targets, e.g. to install into a scratch directory
+pub static TARGET_ENV: &str = "MODMAN_TARGET";
+
+fn root_override() -> Option<PathBuf> {
+    match env::var_os(TARGET_ENV) {
+        Some(ref dir) if Path::new(dir).is_absolute() => Some(PathBuf::from(dir)),
+        _ => None,
+    }
+}
+
+/// The directory targets are relative to: `$MODMAN_TARGET` if it is an absolute path, otherwise
+/// the home directory
+pub fn root() -> PathBuf {
+    root_override().unwrap_or_else(|| dirs::home_dir().unwrap())
+}
+
+/// Resolve a resource target from a module config to a system location.
+///
+/// Environment variables in the target are expanded first (see `expand`), and one that isn't set
+/// is left as it is; modules are checked for those when they are loaded. Targets are then
+/// relative to the home directory, unless absolute or prefixed with one of `xdg-config:`,
+/// `xdg-data:`, `xdg-cache:` or `xdg-state:`, in which case they are relative to the
+/// corresponding XDG base directory. With `$MODMAN_TARGET` set, everything is relative to it
+/// instead and the XDG variables, which describe the real home directory, are ignored.
+pub fn resolve(system_file: &str) -> PathBuf {
+    let system_file = expand(system_file).unwrap_or(Cow::Borrowed(system_file));
+    let system_file = system_file.as_ref();
+    let root_override = root_override();
+    let home = root();
+    for &(prefix, var, default) in XDG_PREFIXES {
+        if let Some(relative) = system_file.strip_prefix(prefix) {
+            // The spec says relative paths in these variables are invalid and should be ignored
+            let base = match env::var_os(var) {
+                Some(ref dir) if root_override.is_none() && Path::new(dir).is_absolute() => {
+                    PathBuf::from(dir)
+                }
+                _ => home.join(default),
+            };
+            return base.join(relative);
+        }
+    }
+    home.join(system_file)
+}
+
+/// Expand the environment variables in a target: `$NAME` (the longest run of letters, digits and
+/// underscores) and `${NAME}` are replaced by the variable's value, and `$$` by a single `$`. A
+/// `$` followed by anything else is kept. Fails with why on the first variable that isn't set or
+/// is empty, as the target would silently end up somewhere else, or on a `${` without a `}`.
+pub fn expand(system_file: &str) -> Result<Cow<'_, str>, String> {
+    if !system_file.contains('$') {
+        return Ok(Cow::Borrowed(system_file));
+    }
+    let mut expanded = String::with_capacity(system_file.len());
+    let mut rest = system_file;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+        let (name, after) = match rest.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => return Err(format!("${{{} isn't closed", braced)),
+            },
+            None => {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            }
+        };
+        if name.is_empty() {
+            expanded.push('$');
+            continue;
+        }
+        match env::var(name) {
+            Ok(ref value) if !value.is_empty() => expanded.push_str(value),
+            _ => return Err(format!("${} isn't set", name)),
+        }
+        rest = after;
+    }
+    expanded.push_str(rest);
+    Ok(Cow::Owned(expanded))
+}
+
+/// Whether a target is relative to one of the XDG base directories
+pub fn is_xdg(system_file: &str) -> bool {
+    XDG_PREFIXES
+        .iter()
+        .any(|&(prefix, _, _)| system_file.starts_with(prefix))
+}
+
+/// Write a path relative to the home directory as a target, using the XDG prefix if it is below
+/// the default location of an XDG base directory, e.g. `.config/nvim` as `xdg-config:nvim`
+pub fn from_home(relative: &str) -> String {
+    for &(prefix, _, default) in XDG_PREFIXES {
+        let inside = relative
+            .strip_prefix(default)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .filter(|rest| !rest.is_empty());
+        if let Some(rest) = inside {
+            return format!("{}{}", prefix, rest);
+        }
+    }
+    relative.to_owned()
+}
+
+/// Whether a path relative to the home directory is the default location of an XDG base
+/// directory or contains one (`.local`). Many programs share these, so they are never linked whole.
+pub fn is_base_dir(relative: &str) -> bool {
+    XDG_PREFIXES
+        .iter()
+        .any(|&(_, _, default)| Path::new(default).starts_with(relative))
+}
+
+/// The program a target most likely configures: the first component below the home or XDG base
+/// directory (or below `.config` or a leading variable), without a leading dot or extension. For
+/// example `xdg-config:waybar/config`, `$XDG_CONFIG_HOME/waybar` and `.tmux.conf` give `waybar`,
+/// `waybar` and `tmux`.
+pub fn app_name(system_file: &str) -> Option<String> {
+    let relative = XDG_PREFIXES
+        .iter()
+        .find_map(|&(prefix, _, _)| system_file.strip_prefix(prefix))
+        .unwrap_or(system_file);
+    let mut components = Path::new(relative)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .skip_while(|name| name.starts_with('$'));
+    let mut name = components.next()?;
+    if name == ".config" {
+        name = components.next()?;
+    }
+    let name = name.trim_start_matches('.');
+    let name = name.split('.').next().unwrap_or(name);
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_owned())
+    }
+}
+
+/// Lexically resolve `.` and `..` components, without following symlinks
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_app_name() {
+        assert_eq!(app_name("xdg-config:waybar/config").unwrap(), "waybar");
+        assert_eq!(app_name(".config/waybar").unwrap(), "waybar");
+        assert_eq!(app_name(".tmux.conf").unwrap(), "tmux");
+        assert_eq!(app_name("xdg-config:starship.toml").unwrap(), "starship");
+        assert_eq!(app_name("$XDG_CONFIG_HOME/waybar").unwrap(), "waybar");
+        assert!(app_name(".config").is_none());
+    }
+
+    #[test]
+    fn test_from_home() {
+        assert_eq!(from_home(".config/nvim"), "xdg-config:nvim");
+        assert_eq!(from_home(".local/share/fonts"), "xdg-data:fonts");
+        assert_eq!(from_home(".configs"), ".configs");
+        assert_eq!(from_home(".vimrc"), ".vimrc");
+        assert!(is_base_dir(".local") && is_base_dir(".config"));
+        assert!(!is_base_dir(".vim") && !is_base_dir(".config/nvim"));
+    }
+
+    #[test]
+    fn test_expand() {
+        env::set_var("MODMAN_TEST_EXPAND", "work");
+        env::remove_var("MODMAN_TEST_UNSET");
+        assert_eq!(expand(".vimrc").unwrap(), ".vimrc");
+        assert_eq!(expand("$MODMAN_TEST_EXPAND/a").unwrap(), "work/a");
+        assert_eq!(expand("${MODMAN_TEST_EXPAND}s/a").unwrap(), "works/a");
+        assert_eq!(expand("a$$b/$/c").unwrap(), "a$b/$/c");
+        assert_eq!(
+            expand("a/$MODMAN_TEST_UNSET").unwrap_err(),
+            "$MODMAN_TEST_UNSET isn't set"
+        );
+        assert!(expand("${MODMAN_TEST_EXPAND").is_err());
+    }
+}
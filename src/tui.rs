@@ -0,0 +1,340 @@
+//! `modman tui`: an interactive list of the modules, to look through them and install or
+//! uninstall them without remembering their names.
+//!
+//! Operations run as child modman processes, so they go through the same locking, logging and
+//! state as on the command line, and their output is shown in the log pane as it arrives.
+extern crate ratatui;
+
+use self::ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use self::ratatui::layout::{Constraint, Direction, Layout};
+use self::ratatui::style::{Color, Modifier, Style};
+use self::ratatui::text::{Line, Span};
+use self::ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use self::ratatui::{DefaultTerminal, Frame};
+use log::{self, LevelFilter};
+use modman::module::{Module, ModuleDirs, ResourceStatus, Status};
+use modman::state::State;
+use std::collections::VecDeque;
+use std::env;
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+// Lines of operation output kept in the log pane
+static LOG_LINES: usize = 500;
+// How often the screen is redrawn while waiting for keys
+static TICK: Duration = Duration::from_millis(100);
+
+// What the threads following a child modman report
+enum Message {
+    Output(String),
+    Done(String, Option<i32>),
+}
+
+// A module in the list, or why it couldn't be loaded
+struct Entry {
+    name: String,
+    module: Result<Module, String>,
+}
+
+struct App<'a> {
+    module_dirs: ModuleDirs,
+    // Arguments every child modman is started with
+    args: Vec<OsString>,
+    configure: &'a dyn Fn(&mut Module),
+    entries: Vec<Entry>,
+    selected: ListState,
+    // Show what installing the selected module would change instead of its resources
+    diff: bool,
+    log: VecDeque<String>,
+    // The operation running, if any
+    running: Option<String>,
+    sender: Sender<Message>,
+    receiver: Receiver<Message>,
+}
+
+/// Show the modules of `module_dirs` until the user quits. Children get `args` before their
+/// subcommand, and modules are adjusted with `configure` before they are previewed.
+pub fn run(
+    module_dirs: ModuleDirs,
+    args: Vec<OsString>,
+    configure: &dyn Fn(&mut Module),
+) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut app = App {
+        module_dirs,
+        args,
+        configure,
+        entries: Vec::new(),
+        selected: ListState::default(),
+        diff: false,
+        log: VecDeque::new(),
+        running: None,
+        sender,
+        receiver,
+    };
+    app.refresh();
+    if !app.entries.is_empty() {
+        app.selected.select(Some(0));
+    }
+
+    // Log messages would be written over the screen
+    let level = log::max_level();
+    log::set_max_level(LevelFilter::Off);
+    let mut terminal = ratatui::try_init()?;
+    let result = app.event_loop(&mut terminal);
+    ratatui::restore();
+    log::set_max_level(level);
+    result
+}
+
+impl<'a> App<'a> {
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            while let Ok(message) = self.receiver.try_recv() {
+                self.receive(message);
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+            if !event::poll(TICK)? {
+                continue;
+            }
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Down | KeyCode::Char('j') => self.selected.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => self.selected.select_previous(),
+                KeyCode::Char('d') => self.diff = !self.diff,
+                KeyCode::Char('r') => self.refresh(),
+                KeyCode::Char('i') => self.start("install"),
+                KeyCode::Char('u') => self.start("uninstall"),
+                _ => {}
+            }
+        }
+    }
+
+    // Load the modules again, as an operation may have changed them
+    fn refresh(&mut self) {
+        self.entries = match self.module_dirs.list() {
+            Ok(modules) => modules
+                .into_iter()
+                .map(|module| match module {
+                    Ok(mut module) => {
+                        (self.configure)(&mut module);
+                        Entry {
+                            name: module.name().to_owned(),
+                            module: Ok(module),
+                        }
+                    }
+                    Err(err) => Entry {
+                        name: err.module().unwrap_or("?").to_owned(),
+                        module: Err(err.to_string()),
+                    },
+                })
+                .collect(),
+            Err(err) => {
+                self.push_log(err.to_string());
+                Vec::new()
+            }
+        };
+    }
+
+    fn current(&self) -> Option<&Entry> {
+        self.selected
+            .selected()
+            .and_then(|index| self.entries.get(index))
+    }
+
+    // Run `modman <action> <module>` on the selected module, unless something is running
+    fn start(&mut self, action: &str) {
+        let name = match self.current() {
+            Some(entry) if self.running.is_none() => entry.name.clone(),
+            _ => return,
+        };
+        let child = env::current_exe().and_then(|exe| {
+            Command::new(exe)
+                .args(&self.args)
+                .arg("--verbose")
+                .arg(action)
+                .arg(&name)
+                .env("NO_COLOR", "1")
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+        });
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => return self.push_log(format!("Can't start modman: {}", err)),
+        };
+        let operation = format!("{} {}", action, name);
+        self.push_log(format!("$ modman {}", operation));
+        self.running = Some(operation.clone());
+
+        let readers = vec![
+            follow(child.stdout.take().unwrap(), self.sender.clone()),
+            follow(child.stderr.take().unwrap(), self.sender.clone()),
+        ];
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            for reader in readers {
+                let _ = reader.join();
+            }
+            let code = child.wait().ok().and_then(|status| status.code());
+            let _ = sender.send(Message::Done(operation, code));
+        });
+    }
+
+    fn receive(&mut self, message: Message) {
+        match message {
+            Message::Output(line) => self.push_log(line),
+            Message::Done(operation, code) => {
+                match code {
+                    Some(0) => self.push_log(format!("{}: done", operation)),
+                    Some(code) => self.push_log(format!("{}: failed with {}", operation, code)),
+                    None => self.push_log(format!("{}: killed", operation)),
+                }
+                self.running = None;
+                self.refresh();
+            }
+        }
+    }
+
+    fn push_log(&mut self, line: String) {
+        if self.log.len() == LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(line);
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(5),
+                Constraint::Length(10),
+                Constraint::Length(1),
+            ])
+            .split(frame.area());
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(35), Constraint::Percentage(65)])
+            .split(rows[0]);
+
+        let items: Vec<ListItem> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let (label, color) = match entry.module {
+                    Ok(ref module) => match module.status() {
+                        Status::Installed => ("installed", Color::Green),
+                        Status::Partial => ("partial", Color::Yellow),
+                        Status::NotInstalled => ("not installed", Color::DarkGray),
+                    },
+                    Err(_) => ("invalid", Color::Red),
+                };
+                ListItem::new(Line::from(vec![
+                    Span::raw(format!("{:20} ", entry.name)),
+                    Span::styled(label, Style::default().fg(color)),
+                ]))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("Modules"))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, columns[0], &mut self.selected);
+
+        let title = if self.diff { "Install would" } else { "Module" };
+        let details = Paragraph::new(self.details())
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(details, columns[1]);
+
+        // Only the newest lines that fit are shown
+        let height = rows[1].height.saturating_sub(2) as usize;
+        let log: Vec<Line> = self
+            .log
+            .iter()
+            .skip(self.log.len().saturating_sub(height))
+            .map(|line| Line::raw(line.as_str()))
+            .collect();
+        let log = Paragraph::new(log).block(Block::default().borders(Borders::ALL).title("Log"));
+        frame.render_widget(log, rows[1]);
+
+        let help = match self.running {
+            Some(ref operation) => format!("Running modman {} ...", operation),
+            None => "j/k move  i install  u uninstall  d diff  r refresh  q quit".to_owned(),
+        };
+        frame.render_widget(
+            Paragraph::new(help).style(Style::default().add_modifier(Modifier::DIM)),
+            rows[2],
+        );
+    }
+
+    // The description of the selected module, then its resources or what installing it would do
+    fn details(&self) -> Vec<Line<'static>> {
+        let module = match self.current().map(|entry| &entry.module) {
+            Some(Ok(module)) => module,
+            Some(Err(err)) => return vec![Line::raw(err.clone())],
+            None => return Vec::new(),
+        };
+        let mut lines = vec![
+            Line::raw(module.description().unwrap_or("No description").to_owned()),
+            Line::raw(""),
+        ];
+        if self.diff {
+            let state = State::load().unwrap_or_default();
+            let run_init = !module.init_once() || !state.init_done(module.name());
+            match module.plan_install(false, false, run_init) {
+                Ok(actions) => {
+                    lines.extend(actions.iter().map(|action| Line::raw(action.to_string())))
+                }
+                Err(err) => lines.push(Line::styled(
+                    format!("Fails: {}", err),
+                    Style::default().fg(Color::Red),
+                )),
+            }
+            return lines;
+        }
+        for resource in module.resource_states() {
+            let color = match resource.status {
+                ResourceStatus::Installed => Color::Green,
+                ResourceStatus::Missing => Color::DarkGray,
+                _ => Color::Yellow,
+            };
+            lines.push(Line::from(vec![
+                Span::styled(
+                    format!("{:10} ", resource.status),
+                    Style::default().fg(color),
+                ),
+                Span::raw(format!(
+                    "{} -> {}",
+                    resource.resource,
+                    resource.target.display()
+                )),
+            ]));
+        }
+        lines
+    }
+}
+
+// Send each line `stream` produces until it is closed
+fn follow<R: Read + Send + 'static>(stream: R, sender: Sender<Message>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            match line {
+                Ok(line) => {
+                    if sender.send(Message::Output(line)).is_err() {
+                        return;
+                    }
+                }
+                Err(_) => return,
+            }
+        }
+    })
+}
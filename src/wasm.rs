@@ -0,0 +1,12 @@
+//! WebAssembly bindings for the module config validator, enabled with the `wasm` feature.
+//!
+//! Build with `cargo build --lib --release --target wasm32-unknown-unknown --features wasm`.
+
+use definition;
+use wasm_bindgen::prelude::*;
+
+/// Validate the contents of a module `config.toml`, returning the error message if it is invalid
+#[wasm_bindgen(js_name = validateConfig)]
+pub fn validate_config(source: &str) -> Option<String> {
+    definition::validate(source).err().map(|err| err.to_string())
+}
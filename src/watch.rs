@@ -0,0 +1,103 @@
+//! Minimal inotify wrapper for watching module directories.
+extern crate libc;
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Changes to directory entries that can alter a module (editors often save by renaming)
+const WATCH_MASK: u32 = libc::IN_CREATE
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_MODIFY
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_ATTRIB;
+
+pub struct Watcher {
+    fd: RawFd,
+    dirs: HashMap<i32, PathBuf>,
+}
+
+impl Watcher {
+    pub fn new() -> io::Result<Watcher> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Watcher {
+            fd,
+            dirs: HashMap::new(),
+        })
+    }
+
+    pub fn add<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<()> {
+        let path = CString::new(dir.as_ref().as_os_str().as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.dirs.insert(wd, dir.as_ref().to_path_buf());
+        Ok(())
+    }
+
+    /// Block until a watched directory changes, then keep collecting changes until none arrive
+    /// for `debounce`, so a burst of writes from an editor is handled once.
+    pub fn wait(&mut self, debounce: Duration) -> io::Result<HashSet<PathBuf>> {
+        let mut changed = HashSet::new();
+        self.read(&mut changed)?;
+        while self.poll(debounce)? {
+            self.read(&mut changed)?;
+        }
+        Ok(changed)
+    }
+
+    fn poll(&self, timeout: Duration) -> io::Result<bool> {
+        let mut fds = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int) };
+        if ready < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ready > 0)
+    }
+
+    fn read(&self, changed: &mut HashSet<PathBuf>) -> io::Result<()> {
+        let mut buf = [0u8; 4096];
+        let len = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut offset = 0;
+        while offset + mem::size_of::<libc::inotify_event>() <= len as usize {
+            let event: libc::inotify_event = unsafe {
+                (buf.as_ptr().add(offset) as *const libc::inotify_event).read_unaligned()
+            };
+            if let Some(dir) = self.dirs.get(&event.wd) {
+                if event.mask & libc::IN_IGNORED == 0 {
+                    changed.insert(dir.clone());
+                }
+            }
+            offset += mem::size_of::<libc::inotify_event>() + event.len as usize;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
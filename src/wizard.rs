@@ -0,0 +1,73 @@
+//! `modman init`: interactive first-run setup of the global config.
+use clap::{App, Shell};
+use dirs;
+use modman::bootstrap;
+use modman::config::Config;
+use output::{ask, confirm};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Where each shell picks up user completions from without further setup
+fn completion_path(shell: Shell) -> Option<PathBuf> {
+    match shell {
+        Shell::Bash => dirs::data_dir().map(|dir| dir.join("bash-completion/completions/modman")),
+        Shell::Fish => dirs::config_dir().map(|dir| dir.join("fish/completions/modman.fish")),
+        Shell::Zsh => dirs::home_dir().map(|dir| dir.join(".zfunc/_modman")),
+        _ => None,
+    }
+}
+
+fn current_shell() -> Option<Shell> {
+    let shell = env::var("SHELL").ok()?;
+    match Path::new(&shell).file_name()?.to_str()? {
+        "bash" => Some(Shell::Bash),
+        "fish" => Some(Shell::Fish),
+        "zsh" => Some(Shell::Zsh),
+        _ => None,
+    }
+}
+
+/// Walk through setting up the global config, cloning the modules and installing completions
+pub fn run(mut app: App, default_dir: &str) -> io::Result<()> {
+    let path = Config::path().ok_or_else(|| io::Error::other("no config directory"))?;
+    if path.exists() && !confirm(&format!("{} exists; Overwrite it?", path.display()), false)? {
+        return Ok(());
+    }
+    let mut config = Config::default();
+
+    let modules_dir = ask("Modules directory", default_dir)?;
+    if !Path::new(&modules_dir).exists() {
+        let remote = ask("Git remote to clone the modules from (empty to skip)", "")?;
+        if remote.is_empty() {
+            fs::create_dir_all(&modules_dir)?;
+        } else {
+            bootstrap::clone(&remote, Path::new(&modules_dir))
+                .map_err(|err| io::Error::other(err.to_string()))?;
+        }
+    }
+    if modules_dir != default_dir {
+        config.modules_dir = Some(modules_dir);
+    }
+
+    config.stats = confirm("Record local usage statistics?", false)?;
+
+    if let Some(shell) = current_shell() {
+        if confirm(&format!("Install {} completions?", shell), true)? {
+            let completions = completion_path(shell).unwrap();
+            fs::create_dir_all(completions.parent().unwrap())?;
+            app.gen_completions_to("modman", shell, &mut fs::File::create(&completions)?);
+            println!("Wrote {}", completions.display());
+            if let Shell::Zsh = shell {
+                println!("Add ~/.zfunc to fpath before compinit in ~/.zshrc to enable them");
+            }
+        }
+    }
+
+    config
+        .save()
+        .map_err(|err| io::Error::other(err.to_string()))?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
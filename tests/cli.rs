@@ -0,0 +1,1034 @@
+//! End-to-end tests of the binary against module trees in a scratch directory. `--target-dir`
+//! keeps the links inside it, and the XDG variables keep the state and config there too.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
+
+struct Sandbox {
+    root: PathBuf,
+}
+
+impl Sandbox {
+    fn new(name: &str) -> Sandbox {
+        let root = env::temp_dir().join(format!("modman-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        for dir in &["modules", "target", "home", "config", "data"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        Sandbox { root }
+    }
+
+    /// Create a module with the config and resource files, each containing its own name
+    fn module(&self, name: &str, config: &str, resources: &[&str]) {
+        let dir = self.root.join("modules").join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("config.toml"), config).unwrap();
+        for resource in resources {
+            fs::write(dir.join(resource), resource).unwrap();
+        }
+    }
+
+    fn resource(&self, module: &str, resource: &str) -> PathBuf {
+        self.root.join("modules").join(module).join(resource)
+    }
+
+    fn target(&self, path: &str) -> PathBuf {
+        self.root.join("target").join(path)
+    }
+
+    /// Run gpg with its home in the sandbox's home, where modman finds it too
+    fn gpg(&self, args: &[&str]) -> Output {
+        let gnupg = self.root.join("home/.gnupg");
+        fs::create_dir_all(&gnupg).unwrap();
+        Command::new("gpg")
+            .arg("--batch")
+            .arg("--homedir")
+            .arg(&gnupg)
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_modman"))
+            .arg("-m")
+            .arg(self.root.join("modules"))
+            .arg("--target-dir")
+            .arg(self.root.join("target"))
+            .args(args)
+            .env("HOME", self.root.join("home"))
+            .env("XDG_CONFIG_HOME", self.root.join("config"))
+            .env("XDG_DATA_HOME", self.root.join("data"))
+            .env("NO_COLOR", "1")
+            .env_remove("MODMAN_TARGET")
+            .env_remove("MODMAN_READONLY")
+            .env_remove("GNUPGHOME")
+            .output()
+            .unwrap()
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let gnupg = self.root.join("home/.gnupg");
+        if gnupg.exists() {
+            let _ = Command::new("gpgconf")
+                .arg("--homedir")
+                .arg(&gnupg)
+                .args(["--kill", "gpg-agent"])
+                .status();
+        }
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn links_to(link: &Path, resource: &Path) -> bool {
+    fs::read_link(link).is_ok_and(|actual| actual == resource)
+}
+
+#[test]
+fn test_install_and_uninstall() {
+    let sandbox = Sandbox::new("install");
+    sandbox.module(
+        "vim",
+        "[resources]\nvimrc = \".vimrc\"\nnvim = \"xdg-config:nvim/init.vim\"\n",
+        &["vimrc", "nvim"],
+    );
+
+    let output = sandbox.run(&["install", "vim"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(links_to(
+        &sandbox.target(".vimrc"),
+        &sandbox.resource("vim", "vimrc")
+    ));
+    assert!(links_to(
+        &sandbox.target(".config/nvim/init.vim"),
+        &sandbox.resource("vim", "nvim")
+    ));
+
+    let output = sandbox.run(&["uninstall", "vim"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(fs::symlink_metadata(sandbox.target(".vimrc")).is_err());
+    assert!(fs::symlink_metadata(sandbox.target(".config/nvim/init.vim")).is_err());
+}
+
+#[test]
+fn test_list() {
+    let sandbox = Sandbox::new("list");
+    sandbox.module("tmux", "[resources]\ntmux = \".tmux.conf\"\n", &["tmux"]);
+    sandbox.module("zsh", "[resources]\nzshrc = \".zshrc\"\n", &["zshrc"]);
+    sandbox.module("broken", "[resources]\nmissing = \".missing\"\n", &[]);
+
+    let mut modules: Vec<String> = stdout(&sandbox.run(&["list", "--print0"]))
+        .split_terminator('\0')
+        .map(str::to_owned)
+        .collect();
+    modules.sort();
+    assert_eq!(modules, ["tmux", "zsh"], "invalid modules aren't listed");
+
+    assert!(sandbox.run(&["install", "zsh"]).status.success());
+    assert_eq!(
+        stdout(&sandbox.run(&["list", "--installed"])),
+        "zsh  installed\n"
+    );
+    assert_eq!(
+        stdout(&sandbox.run(&["list", "--not-installed"])),
+        "tmux  not installed\n"
+    );
+
+    // A resource added since the install isn't linked yet
+    sandbox.module(
+        "zsh",
+        "[resources]\nzshrc = \".zshrc\"\nzshenv = \".zshenv\"\n",
+        &["zshenv"],
+    );
+    assert!(stdout(&sandbox.run(&["list"])).contains("zsh   partial\n"));
+    let tree = stdout(&sandbox.run(&["list", "--tree", "--installed"]));
+    let zshenv = sandbox.target(".zshenv");
+    let zshrc = sandbox.target(".zshrc");
+    assert_eq!(
+        tree,
+        format!(
+            "zsh  partial\n├── zshenv -> {} (missing)\n└── zshrc -> {}\n",
+            zshenv.display(),
+            zshrc.display()
+        )
+    );
+}
+
+#[test]
+fn test_hidden_directories() {
+    let sandbox = Sandbox::new("hidden-directories");
+    sandbox.module(
+        ".config-foo",
+        "include = [\"../.shared/base.toml\"]\n[resources]\nfoorc = \".foorc\"\n",
+        &["foorc"],
+    );
+    let shared = sandbox.root.join("modules/.shared");
+    fs::create_dir(&shared).unwrap();
+    fs::write(shared.join("base.toml"), "description = \"Shared\"\n").unwrap();
+    // Version control metadata isn't a module, even with a config
+    sandbox.module(".git", "[resources]\nx = \".x\"\n", &["x"]);
+
+    let output = sandbox.run(&["list", "--print0"]);
+    assert_eq!(stdout(&output), ".config-foo\0", "{:?}", output);
+    assert!(sandbox.run(&["install", ".config-foo"]).status.success());
+    assert!(links_to(
+        &sandbox.target(".foorc"),
+        &sandbox.resource(".config-foo", "foorc")
+    ));
+}
+
+#[test]
+fn test_install_keeps_existing_files() {
+    let sandbox = Sandbox::new("existing");
+    sandbox.module(
+        "git",
+        "[resources]\ngitconfig = \".gitconfig\"\n",
+        &["gitconfig"],
+    );
+    fs::write(sandbox.target(".gitconfig"), "mine").unwrap();
+
+    let output = sandbox.run(&["install", "git"]);
+    assert_eq!(output.status.code(), Some(12), "{:?}", output);
+    assert_eq!(
+        fs::read_to_string(sandbox.target(".gitconfig")).unwrap(),
+        "mine"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_broken_link_in_the_way() {
+    let sandbox = Sandbox::new("broken-link");
+    sandbox.module(
+        "git",
+        "[resources]\ngitconfig = \".gitconfig\"\n",
+        &["gitconfig"],
+    );
+    std::os::unix::fs::symlink("/modman-no-such-file", sandbox.target(".gitconfig")).unwrap();
+
+    let output = sandbox.run(&["install", "git"]);
+    assert_eq!(output.status.code(), Some(12), "{:?}", output);
+    assert!(stdout(&output).contains("Broken link"), "{:?}", output);
+
+    let output = sandbox.run(&["install", "--force", "--yes", "git"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(links_to(
+        &sandbox.target(".gitconfig"),
+        &sandbox.resource("git", "gitconfig")
+    ));
+}
+
+#[test]
+fn test_if_app_skips_missing_programs() {
+    let sandbox = Sandbox::new("if-app");
+    sandbox.module(
+        "desktop",
+        "[resources]\nbar = { target = \"xdg-config:modman-no-such-bar\", if_app = true }\nprofile = \".profile\"\n",
+        &["bar", "profile"],
+    );
+
+    let output = sandbox.run(&["install", "desktop"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).contains("Skipped 1 resources"));
+    assert!(fs::symlink_metadata(sandbox.target(".config/modman-no-such-bar")).is_err());
+    assert!(links_to(
+        &sandbox.target(".profile"),
+        &sandbox.resource("desktop", "profile")
+    ));
+}
+
+#[test]
+fn test_link_children() {
+    let sandbox = Sandbox::new("link-children");
+    sandbox.module(
+        "nvim",
+        "[resources]\nnvim = { target = \"xdg-config:nvim\", link_children = true }\n",
+        &[],
+    );
+    fs::create_dir_all(sandbox.resource("nvim", "nvim/lua")).unwrap();
+    fs::write(sandbox.resource("nvim", "nvim/init.lua"), "init").unwrap();
+    fs::write(sandbox.resource("nvim", "nvim/lua/plugins.lua"), "plugins").unwrap();
+    // Files programs create next to the links are left alone
+    fs::create_dir_all(sandbox.target(".config/nvim")).unwrap();
+    fs::write(sandbox.target(".config/nvim/generated.lua"), "generated").unwrap();
+
+    let output = sandbox.run(&["install", "nvim"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(links_to(
+        &sandbox.target(".config/nvim/init.lua"),
+        &sandbox.resource("nvim", "nvim/init.lua")
+    ));
+    assert!(links_to(
+        &sandbox.target(".config/nvim/lua/plugins.lua"),
+        &sandbox.resource("nvim", "nvim/lua/plugins.lua")
+    ));
+
+    let output = sandbox.run(&["uninstall", "nvim"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(fs::symlink_metadata(sandbox.target(".config/nvim/init.lua")).is_err());
+    assert!(sandbox.target(".config/nvim/generated.lua").exists());
+}
+
+#[test]
+fn test_target_variables() {
+    let sandbox = Sandbox::new("target-variables");
+    sandbox.module(
+        "work",
+        "[resources]\nsettings = \"${MODMAN_TARGET}/work/settings\"\n",
+        &["settings"],
+    );
+    sandbox.module(
+        "unset",
+        "[resources]\nrc = \"$MODMAN_TEST_UNSET/rc\"\n",
+        &["rc"],
+    );
+
+    let output = sandbox.run(&["install", "work"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(links_to(
+        &sandbox.target("work/settings"),
+        &sandbox.resource("work", "settings")
+    ));
+    let output = sandbox.run(&["install", "unset"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("$MODMAN_TEST_UNSET isn't set"));
+}
+
+#[test]
+fn test_remove_artifacts() {
+    let sandbox = Sandbox::new("remove-artifacts");
+    sandbox.module(
+        "zsh",
+        "artifacts = [\"xdg-cache:zsh\", \".zcompdump\"]\n[resources]\nzshrc = \".zshrc\"\n",
+        &["zshrc"],
+    );
+    sandbox.module(
+        "home",
+        "artifacts = [\"$HOME\"]\n[resources]\nrc = \".rc\"\n",
+        &["rc"],
+    );
+    assert!(sandbox.run(&["install", "zsh"]).status.success());
+    fs::create_dir_all(sandbox.target(".cache/zsh")).unwrap();
+    fs::write(sandbox.target(".cache/zsh/zcompcache"), "").unwrap();
+    fs::write(sandbox.target(".zcompdump"), "").unwrap();
+
+    // Nothing is deleted without confirmation
+    let output = sandbox.run(&["uninstall", "--remove-artifacts", "zsh"]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("Pass --yes to delete them"));
+    assert!(sandbox.target(".zcompdump").exists());
+    let output = sandbox.run(&["uninstall", "--remove-artifacts", "--yes", "zsh"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!sandbox.target(".cache/zsh").exists() && !sandbox.target(".zcompdump").exists());
+    assert!(sandbox.target(".cache").exists());
+
+    // An artifact that only turns absolute once expanded is rejected as well
+    let output = sandbox.run(&["uninstall", "--remove-artifacts", "--yes", "home"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("must be relative to the home directory"),
+        "{}",
+        stderr
+    );
+    assert!(sandbox.root.join("home").exists());
+}
+
+#[test]
+fn test_remembered_overwrite_is_confirmed() {
+    let sandbox = Sandbox::new("remembered-overwrite");
+    sandbox.module("tmux", "[resources]\ntmux = \".tmux.conf\"\n", &["tmux"]);
+    fs::write(sandbox.target(".tmux.conf"), "mine").unwrap();
+    let state = sandbox.root.join("data/modman/state.json");
+    fs::create_dir_all(state.parent().unwrap()).unwrap();
+    let decisions = format!(
+        "{{\"decisions\": {{{:?}: \"overwrite\"}}}}",
+        sandbox.target(".tmux.conf")
+    );
+    fs::write(&state, decisions).unwrap();
+
+    let output = sandbox.run(&["install", "tmux"]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("Pass --yes to delete them"));
+    assert_eq!(
+        fs::read_to_string(sandbox.target(".tmux.conf")).unwrap(),
+        "mine"
+    );
+    let output = sandbox.run(&["install", "--yes", "tmux"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(links_to(
+        &sandbox.target(".tmux.conf"),
+        &sandbox.resource("tmux", "tmux")
+    ));
+}
+
+#[test]
+fn test_archive_forgets_links() {
+    let sandbox = Sandbox::new("archive");
+    sandbox.module("tmux", "[resources]\ntmux = \".tmux.conf\"\n", &["tmux"]);
+    assert!(sandbox.run(&["install", "tmux"]).status.success());
+    let state = sandbox.root.join("data/modman/state.json");
+    let target = sandbox.target(".tmux.conf").display().to_string();
+    assert!(fs::read_to_string(&state).unwrap().contains(&target));
+
+    let output = sandbox.run(&["archive", "tmux"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(fs::symlink_metadata(sandbox.target(".tmux.conf")).is_err());
+    assert!(!fs::read_to_string(&state).unwrap().contains(&target));
+}
+
+#[test]
+fn test_condition_changed_since_install() {
+    let sandbox = Sandbox::new("condition-changed");
+    sandbox.module(
+        "work",
+        "[resources]\ngitconfig = \".gitconfig\"\n",
+        &["gitconfig"],
+    );
+    assert!(sandbox.run(&["install", "work"]).status.success());
+    let config = "[resources]\ngitconfig = { target = \".gitconfig\", if_os = \"plan9\" }\n";
+    fs::write(sandbox.resource("work", "config.toml"), config).unwrap();
+
+    // The link is still the module's, not an orphan
+    let output = sandbox.run(&["prune", "--dry-run"]);
+    assert!(
+        stdout(&output).contains("No orphaned links"),
+        "{:?}",
+        output
+    );
+    let output = sandbox.run(&["uninstall", "work"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(fs::symlink_metadata(sandbox.target(".gitconfig")).is_err());
+}
+
+#[test]
+fn test_install_encrypted_twice() {
+    let sandbox = Sandbox::new("encrypted-twice");
+    let key = [
+        "--passphrase",
+        "",
+        "--quick-gen-key",
+        "me@example.com",
+        "future-default",
+    ];
+    assert!(sandbox.gpg(&key).status.success());
+    sandbox.module(
+        "secrets",
+        "[resources]\n\"token.gpg\" = { target = \".token\", encrypted = true }\n",
+        &[],
+    );
+    let plain = sandbox.root.join("token");
+    fs::write(&plain, "secret").unwrap();
+    let resource = sandbox.resource("secrets", "token.gpg");
+    let encrypt = [
+        "--trust-model",
+        "always",
+        "-r",
+        "me@example.com",
+        "-o",
+        resource.to_str().unwrap(),
+        "-e",
+        plain.to_str().unwrap(),
+    ];
+    assert!(sandbox.gpg(&encrypt).status.success());
+
+    // The copy installed the first time is kept, with or without --force
+    for args in &[
+        &["install", "secrets"][..],
+        &["install", "secrets"],
+        &["install", "-f", "secrets"],
+    ] {
+        let output = sandbox.run(args);
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(
+            fs::read_to_string(sandbox.target(".token")).unwrap(),
+            "secret"
+        );
+    }
+}
+
+#[test]
+fn test_foreign_file_at_encrypted_target() {
+    let sandbox = Sandbox::new("foreign-copy");
+    sandbox.module(
+        "secrets",
+        "[resources]\n\"token.gpg\" = { target = \".token\", encrypted = true }\n",
+        &["token.gpg"],
+    );
+    fs::write(sandbox.target(".token"), "mine").unwrap();
+
+    let output = sandbox.run(&["resources", "secrets"]);
+    assert!(
+        !stdout(&output).contains("installed"),
+        "{}",
+        stdout(&output)
+    );
+    // Only copies modman decrypted itself are shredded
+    assert!(!sandbox.run(&["uninstall", "secrets"]).status.success());
+    assert_eq!(
+        fs::read_to_string(sandbox.target(".token")).unwrap(),
+        "mine"
+    );
+}
+
+#[test]
+fn test_pull_keeps_gpg_recipients() {
+    let sandbox = Sandbox::new("gpg-recipients");
+    let gpg = |args: &[&str]| sandbox.gpg(args);
+    for user in &["me@example.com", "friend@example.com"] {
+        let key = [
+            "--passphrase",
+            "",
+            "--quick-gen-key",
+            user,
+            "future-default",
+            "default",
+            "never",
+        ];
+        assert!(gpg(&key).status.success());
+    }
+    sandbox.module(
+        "secrets",
+        "[resources]\n\"token.gpg\" = { target = \".token\", encrypted = true }\n",
+        &[],
+    );
+    let plain = sandbox.root.join("token");
+    fs::write(&plain, "secret").unwrap();
+    let resource = sandbox.resource("secrets", "token.gpg");
+    let recipients = || {
+        let listing = gpg(&["--list-only", "--list-packets", resource.to_str().unwrap()]);
+        let listing = String::from_utf8_lossy(&listing.stdout).into_owned();
+        let mut keyids: Vec<String> = listing
+            .lines()
+            .filter(|line| line.starts_with(":pubkey enc packet:"))
+            .filter_map(|line| line.split("keyid ").nth(1).map(str::to_owned))
+            .collect();
+        keyids.sort();
+        keyids
+    };
+    let encrypt = [
+        "--trust-model",
+        "always",
+        "-r",
+        "me@example.com",
+        "-r",
+        "friend@example.com",
+        "-o",
+        resource.to_str().unwrap(),
+        "-e",
+        plain.to_str().unwrap(),
+    ];
+    assert!(gpg(&encrypt).status.success());
+    let before = recipients();
+    assert_eq!(before.len(), 2);
+
+    assert!(sandbox.run(&["install", "secrets"]).status.success());
+    fs::write(sandbox.target(".token"), "rotated").unwrap();
+    let output = sandbox.run(&["drift", "--pull"]);
+    assert!(stdout(&output).contains("Pulled"), "{:?}", output);
+    assert_eq!(recipients(), before);
+}
+
+#[test]
+fn test_probe_failures_are_summarised() {
+    let sandbox = Sandbox::new("probe");
+    sandbox.module(
+        "shell",
+        "probe = \"exit 3\"\n[resources]\nrc = \".shellrc\"\n",
+        &["rc"],
+    );
+
+    let output = sandbox.run(&["install", "shell"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).contains("1 modules failed their probe"));
+    assert!(stdout(&output).contains("exit 3 returned non-zero code"));
+}
+
+#[test]
+fn test_info() {
+    let sandbox = Sandbox::new("info");
+    sandbox.module(
+        "zsh",
+        "description = \"Shell\"\npackages = [\"zsh\"]\nprobe = \"zsh -ic exit\"\n[resources]\nzshrc = \".zshrc\"\n",
+        &["zshrc"],
+    );
+    fs::write(sandbox.resource("zsh", "README.md"), "Needs a Nerd Font.\n").unwrap();
+
+    let output = sandbox.run(&["info", "zsh"]);
+    assert!(output.status.success(), "{:?}", output);
+    let info = stdout(&output);
+    assert!(
+        info.starts_with("zsh - Shell\n\nNeeds a Nerd Font.\n"),
+        "{}",
+        info
+    );
+    assert!(info.contains("zshrc  "));
+    assert!(info.contains("Packages: zsh\n"));
+    assert!(info.contains("probe  zsh -ic exit\n"));
+
+    sandbox.module(
+        "zsh",
+        "[resources]\nzshrc = \".zshrc\"\nother = { target = \".other\", if_os = \"plan9\" }\n",
+        &["other"],
+    );
+    let output = sandbox.run(&["info", "--config", "zsh"]);
+    assert!(output.status.success(), "{:?}", output);
+    let config = stdout(&output);
+    assert!(config.contains("[resources.zshrc]\n"), "{}", config);
+    assert!(!config.contains("[resources.other]"));
+    assert!(config.ends_with("# other is left out, as its if_os doesn't hold\n"));
+}
+
+#[test]
+fn test_resources() {
+    let sandbox = Sandbox::new("resources");
+    sandbox.module(
+        "git",
+        "[resources]\ngitconfig = \".gitconfig\"\ngitignore = \".gitignore\"\n",
+        &["gitconfig", "gitignore"],
+    );
+    assert!(sandbox.run(&["install", "git"]).status.success());
+    fs::remove_file(sandbox.target(".gitignore")).unwrap();
+
+    let output = sandbox.run(&["resources", "git"]);
+    assert!(output.status.success(), "{:?}", output);
+    let lines: Vec<Vec<String>> = stdout(&output)
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().map(str::to_owned).collect())
+        .collect();
+    assert_eq!(lines[0][..3], ["git", "installed", "symlink"]);
+    assert_eq!(lines[1][..3], ["git", "missing", "symlink"]);
+}
+
+#[test]
+fn test_absorb() {
+    let sandbox = Sandbox::new("absorb");
+    sandbox.module(
+        "git",
+        "[resources]\ngitconfig = \".gitconfig\"\ngitignore = \".gitignore\"\n",
+        &["gitconfig", "gitignore"],
+    );
+    assert!(sandbox.run(&["install", "git"]).status.success());
+    fs::remove_file(sandbox.target(".gitignore")).unwrap();
+
+    let output = sandbox.run(&["absorb", "git"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).contains(".gitconfig links into the module; Nothing to absorb"));
+    assert!(stdout(&output).contains(".gitignore isn't installed; Nothing to absorb"));
+    assert!(!sandbox.run(&["absorb", "git", "vimrc"]).status.success());
+}
+
+#[test]
+fn test_sync_shows_new_changelog_entries() {
+    let sandbox = Sandbox::new("changelog");
+    sandbox.module("tmux", "[resources]\ntmux = \".tmux.conf\"\n", &["tmux"]);
+    let changelog = sandbox.resource("tmux", "CHANGELOG.md");
+    fs::write(&changelog, "# Changes\n- Use C-a as the prefix\n").unwrap();
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(["-c", "user.name=a", "-c", "user.email=a@b"])
+            .args(args)
+            .current_dir(sandbox.root.join("modules"))
+            .status()
+            .unwrap()
+            .success()
+    };
+    assert!(git(&["init", "-q"]) && git(&["add", "-A"]) && git(&["commit", "-qm", "init"]));
+    assert!(sandbox.run(&["install", "tmux"]).status.success());
+    let output = sandbox.run(&["sync"]);
+    assert!(!stdout(&output).contains("CHANGELOG"), "{:?}", output);
+
+    fs::write(
+        &changelog,
+        "# Changes\n- Start windows at 1\n- Use C-a as the prefix\n",
+    )
+    .unwrap();
+    assert!(git(&["commit", "-qam", "base-index"]));
+    let output = sandbox.run(&["sync"]);
+    assert!(
+        stdout(&output).contains("Module tmux: New in CHANGELOG.md:\n  - Start windows at 1\n"),
+        "{:?}",
+        output
+    );
+    assert!(!stdout(&output).contains("C-a"));
+    // Each entry is only shown once
+    assert!(!stdout(&sandbox.run(&["sync"])).contains("CHANGELOG"));
+}
+
+#[test]
+fn test_require_clean() {
+    let sandbox = Sandbox::new("require-clean");
+    sandbox.module("tmux", "[resources]\ntmux = \".tmux.conf\"\n", &["tmux"]);
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(["-c", "user.name=a", "-c", "user.email=a@b"])
+            .args(args)
+            .current_dir(sandbox.root.join("modules"))
+            .status()
+            .unwrap()
+            .success()
+    };
+    assert!(git(&["init", "-q"]) && git(&["add", "-A"]) && git(&["commit", "-qm", "init"]));
+    fs::write(sandbox.resource("tmux", "tmux"), "edited").unwrap();
+
+    let output = sandbox.run(&["install", "--require-clean", "tmux"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has uncommitted changes (tmux/tmux)"));
+    assert!(fs::symlink_metadata(sandbox.target(".tmux.conf")).is_err());
+    let output = sandbox.run(&["install", "tmux"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has uncommitted changes"));
+
+    assert!(git(&["commit", "-qam", "edit"]));
+    let output = sandbox.run(&["uninstall", "--require-clean", "tmux"]);
+    assert!(output.status.success(), "{:?}", output);
+}
+
+#[test]
+fn test_git_backups() {
+    let sandbox = Sandbox::new("git-backups");
+    sandbox.module("tmux", "[resources]\ntmux = \".tmux.conf\"\n", &["tmux"]);
+    let modules = sandbox.root.join("modules");
+    assert!(Command::new("git")
+        .args(["init", "-q"])
+        .current_dir(&modules)
+        .status()
+        .unwrap()
+        .success());
+    fs::create_dir_all(sandbox.root.join("config/modman")).unwrap();
+    fs::write(
+        sandbox.root.join("config/modman/config.toml"),
+        "git_backups = true\n",
+    )
+    .unwrap();
+    fs::write(sandbox.target(".tmux.conf"), "mine").unwrap();
+
+    let output = sandbox.run(&["install", "--force", "--yes", "tmux"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).contains("Backed up 1 files to modman-backups"));
+    let backup = format!(
+        "modman-backups:{}",
+        sandbox
+            .target(".tmux.conf")
+            .to_str()
+            .unwrap()
+            .trim_start_matches('/')
+    );
+    let shown = Command::new("git")
+        .args(["show", &backup])
+        .current_dir(&modules)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&shown.stdout), "mine");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_repository_hooks() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let sandbox = Sandbox::new("repository-hooks");
+    sandbox.module("tmux", "[resources]\ntmux = \".tmux.conf\"\n", &["tmux"]);
+    sandbox.module("vim", "[resources]\nvimrc = \".vimrc\"\n", &["vimrc"]);
+    let hooks = sandbox.root.join("modules/hooks");
+    fs::create_dir_all(&hooks).unwrap();
+    let log = sandbox.root.join("hooks.log");
+    for (hook, script) in &[
+        (
+            "pre_install_all.sh",
+            format!("echo pre $MODMAN_MODULES >> {}", log.display()),
+        ),
+        (
+            "post_install_all.sh",
+            format!("echo post $MODMAN_MODULES >> {}", log.display()),
+        ),
+        ("pre_uninstall_all.sh", "exit 1".to_owned()),
+    ] {
+        let path = hooks.join(hook);
+        fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let output = sandbox.run(&["install", "tmux", "vim"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        fs::read_to_string(&log).unwrap(),
+        "pre tmux vim\npost tmux vim\n"
+    );
+    assert!(
+        !stdout(&sandbox.run(&["list"])).contains("hooks"),
+        "hooks isn't a module"
+    );
+
+    let output = sandbox.run(&["uninstall", "tmux"]);
+    assert!(!output.status.success());
+    assert!(links_to(
+        &sandbox.target(".tmux.conf"),
+        &sandbox.resource("tmux", "tmux")
+    ));
+
+    // A module that happens to be called hooks isn't dropped silently
+    fs::write(sandbox.resource("hooks", "config.toml"), "[resources]\n").unwrap();
+    let output = sandbox.run(&["list"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("hooks is reserved for modman"));
+}
+
+#[test]
+fn test_bootstrap() {
+    let sandbox = Sandbox::new("bootstrap");
+    let repo = sandbox.root.join("repo");
+    fs::create_dir_all(repo.join("zsh")).unwrap();
+    fs::create_dir_all(repo.join("git")).unwrap();
+    fs::write(
+        repo.join("zsh/config.toml"),
+        "[resources]\nzshrc = \".zshrc\"\n",
+    )
+    .unwrap();
+    fs::write(repo.join("zsh/zshrc"), "zshrc").unwrap();
+    fs::write(
+        repo.join("git/config.toml"),
+        "[resources]\ngitconfig = \".gitconfig\"\n",
+    )
+    .unwrap();
+    fs::write(repo.join("git/gitconfig"), "gitconfig").unwrap();
+    fs::write(repo.join("bootstrap.toml"), "modules = [\"zsh\"]\n").unwrap();
+    for args in &[
+        &["init", "-q"][..],
+        &["add", "-A"],
+        &[
+            "-c",
+            "user.name=a",
+            "-c",
+            "user.email=a@b",
+            "commit",
+            "-qm",
+            "init",
+        ],
+    ] {
+        assert!(Command::new("git")
+            .args(*args)
+            .current_dir(&repo)
+            .status()
+            .unwrap()
+            .success());
+    }
+
+    let output = sandbox.run(&["bootstrap", repo.to_str().unwrap()]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(links_to(
+        &sandbox.target(".zshrc"),
+        &sandbox.resource("zsh", "zshrc")
+    ));
+    assert!(fs::symlink_metadata(sandbox.target(".gitconfig")).is_err());
+
+    let output = sandbox.run(&["bootstrap", repo.to_str().unwrap()]);
+    assert!(
+        !output.status.success(),
+        "the modules directory isn't empty"
+    );
+}
+
+#[test]
+fn test_run_log() {
+    let sandbox = Sandbox::new("run-log");
+    sandbox.module("tmux", "[resources]\ntmux = \".tmux.conf\"\n", &["tmux"]);
+    assert!(sandbox.run(&["install", "tmux"]).status.success());
+    assert!(sandbox.run(&["list"]).status.success());
+
+    let log = fs::read_to_string(sandbox.root.join("data/modman/modman.log")).unwrap();
+    let actions: Vec<&str> = log
+        .lines()
+        .map(|line| line.split('\t').nth(1).unwrap())
+        .collect();
+    assert_eq!(actions, ["run", "info", "install", "exit"], "{}", log);
+    assert!(log.contains("\tinstall\ttmux\tok\n"));
+}
+
+#[test]
+fn test_state_lock() {
+    let sandbox = Sandbox::new("state-lock");
+    sandbox.module("tmux", "[resources]\ntmux = \".tmux.conf\"\n", &["tmux"]);
+    assert!(sandbox.run(&["install", "tmux"]).status.success());
+    fs::create_dir_all(sandbox.root.join("config/modman")).unwrap();
+    fs::write(
+        sandbox.root.join("config/modman/config.toml"),
+        "state_lock_timeout = 1\n",
+    )
+    .unwrap();
+
+    let lock = fs::File::create(sandbox.root.join("data/modman/state.lock")).unwrap();
+    lock.lock().unwrap();
+    let output = sandbox.run(&["uninstall", "tmux"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("still in use by another modman run"));
+    assert!(
+        sandbox.run(&["list"]).status.success(),
+        "reading needs no lock"
+    );
+    drop(lock);
+    assert!(sandbox.run(&["uninstall", "tmux"]).status.success());
+}
+
+#[test]
+fn test_gc_keeps_modules_of_other_directories() {
+    let sandbox = Sandbox::new("gc-other-directories");
+    sandbox.module("tmux", "[resources]\ntmux = \".tmux.conf\"\n", &["tmux"]);
+    let other = sandbox.root.join("other");
+    fs::create_dir_all(other.join("git")).unwrap();
+    fs::write(
+        other.join("git/config.toml"),
+        "[resources]\ngitconfig = \".gitconfig\"\n",
+    )
+    .unwrap();
+    fs::write(other.join("git/gitconfig"), "gitconfig").unwrap();
+    let other = other.to_str().unwrap();
+    let state = || fs::read_to_string(sandbox.root.join("data/modman/state.json")).unwrap();
+
+    assert!(sandbox
+        .run(&["-m", other, "install", "git"])
+        .status
+        .success());
+    assert!(sandbox.run(&["install", "tmux"]).status.success());
+    assert!(state().contains("gitconfig"), "{}", state());
+    let output = sandbox.run(&["gc"]);
+    assert!(
+        stdout(&output).contains("Forgot deleted modules git"),
+        "{:?}",
+        output
+    );
+    assert!(!state().contains("gitconfig"));
+}
+
+#[test]
+fn test_relative_link() {
+    let sandbox = Sandbox::new("relative-link");
+    sandbox.module(
+        "bash",
+        "[resources]\nbashrc = { target = \".bashrc\", relative_link = true }\n",
+        &["bashrc"],
+    );
+
+    let output = sandbox.run(&["install", "bash"]);
+    assert!(output.status.success(), "{:?}", output);
+    let contents = fs::read_link(sandbox.target(".bashrc")).unwrap();
+    assert!(contents.is_relative(), "{}", contents.display());
+    assert_eq!(
+        fs::read_to_string(sandbox.target(".bashrc")).unwrap(),
+        "bashrc"
+    );
+    assert!(stdout(&sandbox.run(&["list"])).contains("bash  installed"));
+
+    let output = sandbox.run(&["uninstall", "bash"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(fs::symlink_metadata(sandbox.target(".bashrc")).is_err());
+}
+
+#[test]
+fn test_rename_keeps_links() {
+    let sandbox = Sandbox::new("rename");
+    sandbox.module("vim", "[resources]\nvimrc = \".vimrc\"\n", &["vimrc"]);
+    assert!(sandbox.run(&["install", "vim"]).status.success());
+
+    let output = sandbox.run(&["rename", "vim", "neovim"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!sandbox.resource("vim", "").exists());
+    assert!(links_to(
+        &sandbox.target(".vimrc"),
+        &sandbox.resource("neovim", "vimrc")
+    ));
+    assert_eq!(
+        stdout(&sandbox.run(&["list", "--installed"])),
+        "neovim  installed\n"
+    );
+}
+
+#[test]
+fn test_script_env() {
+    let sandbox = Sandbox::new("script-env");
+    sandbox.module(
+        "env",
+        "probe = \"helper\"\n[script_env]\nlocale = \"C\"\npath = [\"bin\"]\n[resources]\nrc = \".envrc\"\n",
+        &["rc"],
+    );
+    fs::create_dir(sandbox.resource("env", "bin")).unwrap();
+    let helper = sandbox.resource("env", "bin/helper");
+    fs::write(&helper, "#!/bin/sh\n[ \"$LC_ALL\" = C ] || exit 1\n").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&helper, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let output = sandbox.run(&["install", "env"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        !stdout(&output).contains("failed their probe"),
+        "{:?}",
+        output
+    );
+}
+
+#[test]
+fn test_script_workdir() {
+    let sandbox = Sandbox::new("script-workdir");
+    sandbox.module(
+        "gen",
+        "init = { script = \"init.sh\", interpreter = \"sh\" }\ncleanup = { script = \"init.sh\", interpreter = \"sh\", workdir = \"out\" }\n[resources]\n",
+        &[],
+    );
+    fs::write(sandbox.resource("gen", "init.sh"), "touch generated\n").unwrap();
+    fs::create_dir(sandbox.resource("gen", "out")).unwrap();
+
+    let output = sandbox.run(&["install", "gen"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(sandbox.resource("gen", "generated").exists());
+    let output = sandbox.run(&["uninstall", "gen"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(sandbox.resource("gen", "out/generated").exists());
+}
+
+#[test]
+fn test_script_timeout() {
+    let sandbox = Sandbox::new("script-timeout");
+    sandbox.module(
+        "slow",
+        "init = { script = \"init.sh\", interpreter = \"sh\", timeout = \"1\" }\n[resources]\n",
+        &[],
+    );
+    fs::write(sandbox.resource("slow", "init.sh"), "sleep 30\n").unwrap();
+
+    let start = std::time::Instant::now();
+    let output = sandbox.run(&["install", "slow"]);
+    assert_eq!(output.status.code(), Some(18), "{:?}", output);
+    assert!(start.elapsed().as_secs() < 10);
+}
+
+#[test]
+fn test_script_starting_a_daemon() {
+    let sandbox = Sandbox::new("script-daemon");
+    sandbox.module(
+        "daemon",
+        "init = { script = \"init.sh\", interpreter = \"sh\", timeout = \"5\" }\n[resources]\n",
+        &[],
+    );
+    // The daemon keeps the script's output open long after it exits
+    fs::write(
+        sandbox.resource("daemon", "init.sh"),
+        "echo started\nsleep 30 &\n",
+    )
+    .unwrap();
+
+    let start = std::time::Instant::now();
+    let output = sandbox.run(&["--verbose", "install", "daemon"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).contains("daemon | started"), "{:?}", output);
+    assert!(start.elapsed().as_secs() < 5);
+}